@@ -0,0 +1,263 @@
+use core::ptr;
+
+use spin::Once;
+
+use super::{
+    AddressSpace, GenericAddress, find_table, map_table, read_u8, read_u16, read_u32, read_u64,
+};
+use crate::arch::x86_64::cpu;
+use crate::arch::x86_64::port::{inw, outb, outw};
+use crate::memory::paging;
+use crate::time;
+
+// Field offsets from the start of the table, header included
+const OFFSET_DSDT: usize = 40;
+const OFFSET_SCI_INTERRUPT: usize = 46;
+const OFFSET_SMI_COMMAND: usize = 48;
+const OFFSET_ACPI_ENABLE: usize = 52;
+const OFFSET_PM1A_CONTROL_BLOCK: usize = 64;
+const OFFSET_PM1B_CONTROL_BLOCK: usize = 68;
+const OFFSET_CENTURY: usize = 108;
+const OFFSET_BOOT_ARCHITECTURE_FLAGS: usize = 109;
+const OFFSET_FLAGS: usize = 112;
+const OFFSET_RESET_REGISTER: usize = 116;
+const OFFSET_RESET_VALUE: usize = 128;
+const OFFSET_X_DSDT: usize = 140;
+const OFFSET_X_PM1A_CONTROL_BLOCK: usize = 172;
+const OFFSET_X_PM1B_CONTROL_BLOCK: usize = 184;
+
+// Fixed feature flags
+const FLAG_RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+
+// IA-PC boot architecture flags
+const BOOT_ARCHITECTURE_8042: u16 = 1 << 1;
+
+// PM1 control register bits
+const PM1_SCI_ENABLE: u16 = 1 << 0;
+const PM1_SLEEP_TYPE_SHIFT: u16 = 10;
+const PM1_SLEEP_ENABLE: u16 = 1 << 13;
+
+// AML opcodes needed to find the \_S5 package in the DSDT
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+
+// Keyboard controller command that pulses the CPU reset line
+const KEYBOARD_CONTROLLER_COMMAND: u16 = 0x64;
+const KEYBOARD_CONTROLLER_RESET: u8 = 0xFE;
+
+/// The fixed ACPI description table fields the kernel uses.
+#[derive(Debug)]
+pub struct Fadt {
+    pub dsdt: u64,
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: Option<GenericAddress>,
+    pub pm1b_control: Option<GenericAddress>,
+    /// CMOS register holding the RTC century, if any.
+    pub century_register: Option<u8>,
+    /// Whether the machine has a PS/2 (8042) controller. Absent on old
+    /// tables, in which case one is assumed to exist.
+    pub has_8042: bool,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    fn parse(bytes: &[u8]) -> Self {
+        // The 64-bit X_ fields supersede the legacy 32-bit ones when set
+        let dsdt = read_u64(bytes, OFFSET_X_DSDT)
+            .filter(|&address| address != 0)
+            .or_else(|| read_u32(bytes, OFFSET_DSDT).map(u64::from))
+            .unwrap_or(0);
+
+        let pm1_control = |extended: usize, legacy: usize| {
+            GenericAddress::parse(bytes, extended)
+                .filter(|register| register.address != 0)
+                .or_else(|| {
+                    let port = read_u32(bytes, legacy).filter(|&port| port != 0)?;
+
+                    Some(GenericAddress {
+                        address_space: AddressSpace::SystemIo,
+                        address: port as u64,
+                    })
+                })
+        };
+
+        let flags = read_u32(bytes, OFFSET_FLAGS).unwrap_or(0);
+
+        Self {
+            dsdt,
+            sci_interrupt: read_u16(bytes, OFFSET_SCI_INTERRUPT).unwrap_or(0),
+            smi_command: read_u32(bytes, OFFSET_SMI_COMMAND).unwrap_or(0),
+            acpi_enable: read_u8(bytes, OFFSET_ACPI_ENABLE).unwrap_or(0),
+            pm1a_control: pm1_control(OFFSET_X_PM1A_CONTROL_BLOCK, OFFSET_PM1A_CONTROL_BLOCK),
+            pm1b_control: pm1_control(OFFSET_X_PM1B_CONTROL_BLOCK, OFFSET_PM1B_CONTROL_BLOCK),
+            century_register: read_u8(bytes, OFFSET_CENTURY).filter(|&register| register != 0),
+            has_8042: read_u16(bytes, OFFSET_BOOT_ARCHITECTURE_FLAGS)
+                .is_none_or(|flags| flags & BOOT_ARCHITECTURE_8042 != 0),
+            reset_register: GenericAddress::parse(bytes, OFFSET_RESET_REGISTER)
+                .filter(|_| flags & FLAG_RESET_REGISTER_SUPPORTED != 0),
+            reset_value: read_u8(bytes, OFFSET_RESET_VALUE).unwrap_or(0),
+        }
+    }
+}
+
+static FADT: Once<Option<Fadt>> = Once::new();
+
+/// Returns the parsed FADT, if the firmware provides one.
+pub fn get() -> Option<&'static Fadt> {
+    FADT.call_once(|| find_table(b"FACP").map(|table| Fadt::parse(table.bytes())))
+        .as_ref()
+}
+
+fn read_pm1(register: &GenericAddress) -> u16 {
+    match register.address_space {
+        AddressSpace::SystemIo => unsafe { inw(register.address as u16) },
+        _ => unsafe { ptr::read_volatile(paging::map_mmio(register.address, 2) as *const u16) },
+    }
+}
+
+fn write_pm1(register: &GenericAddress, value: u16) {
+    match register.address_space {
+        AddressSpace::SystemIo => unsafe { outw(register.address as u16, value) },
+        _ => unsafe {
+            ptr::write_volatile(paging::map_mmio(register.address, 2) as *mut u16, value)
+        },
+    }
+}
+
+// Decodes an AML integer constant, as used for the sleep type values
+fn aml_integer(bytes: &[u8]) -> Option<(u8, usize)> {
+    match *bytes.first()? {
+        AML_ZERO_OP => Some((0, 1)),
+        AML_ONE_OP => Some((1, 1)),
+        AML_BYTE_PREFIX => Some((*bytes.get(1)?, 2)),
+        // Small values may be stored without a prefix
+        value => Some((value, 1)),
+    }
+}
+
+/// Finds the SLP_TYPa and SLP_TYPb values of the \_S5 sleep state.
+///
+/// A full AML interpreter is overkill for this, so the DSDT is scanned for
+/// the `Name (_S5, Package () { a, b, ... })` definition every firmware uses.
+fn s5_sleep_types(dsdt: &[u8]) -> Option<(u8, u8)> {
+    let position = dsdt.windows(4).position(|window| window == b"_S5_")?;
+
+    // The name must be defined with NameOp, optionally with a root prefix
+    let is_definition = position >= 1
+        && (dsdt[position - 1] == AML_NAME_OP
+            || (position >= 2 && dsdt[position - 1] == b'\\' && dsdt[position - 2] == AML_NAME_OP));
+    if !is_definition || *dsdt.get(position + 4)? != AML_PACKAGE_OP {
+        return None;
+    }
+
+    // Skip the package length, whose top two bits give its extra byte count,
+    // and the element count
+    let package_length_bytes = ((*dsdt.get(position + 5)? >> 6) & 0b11) as usize + 1;
+    let elements = dsdt.get(position + 5 + package_length_bytes + 1..)?;
+
+    let (sleep_type_a, used) = aml_integer(elements)?;
+    let (sleep_type_b, _) = aml_integer(&elements[used..])?;
+
+    Some((sleep_type_a, sleep_type_b))
+}
+
+/// Puts the machine into sleep state S5 (soft off).
+pub fn shutdown() -> ! {
+    if let Err(error) = try_shutdown() {
+        log::error!("ACPI shutdown failed: {error}");
+    }
+
+    halt()
+}
+
+fn try_shutdown() -> Result<(), &'static str> {
+    let fadt = get().ok_or("no FADT")?;
+    let pm1a_control = fadt.pm1a_control.as_ref().ok_or("no PM1a control block")?;
+
+    let dsdt = map_table(fadt.dsdt);
+    let (sleep_type_a, sleep_type_b) =
+        s5_sleep_types(dsdt.bytes()).ok_or("no \\_S5 object in the DSDT")?;
+
+    // Firmware may still own the power management registers until ACPI
+    // mode is entered through the SMI command port
+    if read_pm1(pm1a_control) & PM1_SCI_ENABLE == 0 && fadt.smi_command != 0 {
+        unsafe { outb(fadt.smi_command as u16, fadt.acpi_enable) };
+
+        let deadline = time::Instant::now() + core::time::Duration::from_secs(1);
+        while read_pm1(pm1a_control) & PM1_SCI_ENABLE == 0 {
+            if time::Instant::now() >= deadline {
+                return Err("firmware did not enter ACPI mode");
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    cpu::disable_interrupts();
+
+    let sleep = |register: &GenericAddress, sleep_type: u8| {
+        let value = read_pm1(register) & !(0b111 << PM1_SLEEP_TYPE_SHIFT);
+        write_pm1(
+            register,
+            value | (sleep_type as u16) << PM1_SLEEP_TYPE_SHIFT | PM1_SLEEP_ENABLE,
+        );
+    };
+
+    sleep(pm1a_control, sleep_type_a);
+    if let Some(pm1b_control) = &fadt.pm1b_control {
+        sleep(pm1b_control, sleep_type_b);
+    }
+
+    // Entering S5 takes a moment
+    time::delay(core::time::Duration::from_millis(100));
+
+    Err("the machine is still running after entering S5")
+}
+
+/// Resets the machine through the FADT reset register, falling back to the
+/// keyboard controller and finally a triple fault.
+pub fn reboot() -> ! {
+    cpu::disable_interrupts();
+
+    if let Some(fadt) = get()
+        && let Some(register) = &fadt.reset_register
+    {
+        match register.address_space {
+            AddressSpace::SystemIo => unsafe { outb(register.address as u16, fadt.reset_value) },
+            AddressSpace::SystemMemory => unsafe {
+                ptr::write_volatile(
+                    paging::map_mmio(register.address, 1) as *mut u8,
+                    fadt.reset_value,
+                )
+            },
+            // PCI configuration space resets are rare and need the PCI driver
+            _ => {}
+        }
+
+        time::delay(core::time::Duration::from_millis(100));
+    }
+
+    unsafe { outb(KEYBOARD_CONTROLLER_COMMAND, KEYBOARD_CONTROLLER_RESET) };
+    time::delay(core::time::Duration::from_millis(100));
+
+    // With an empty IDT any exception escalates to a triple fault
+    let empty = cpu::DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe {
+        cpu::lidt(&empty);
+        core::arch::asm!("int3");
+    }
+
+    halt()
+}
+
+fn halt() -> ! {
+    loop {
+        cpu::disable_interrupts();
+        cpu::hlt();
+    }
+}
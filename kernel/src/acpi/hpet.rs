@@ -0,0 +1,26 @@
+use spin::Once;
+
+use super::{GenericAddress, find_table, table_body};
+
+/// The HPET description table.
+#[derive(Debug)]
+pub struct Hpet {
+    pub base_address: GenericAddress,
+}
+
+impl Hpet {
+    fn parse(body: &[u8]) -> Option<Self> {
+        // The base address follows the 4 byte event timer block id
+        Some(Self {
+            base_address: GenericAddress::parse(body, 4)?,
+        })
+    }
+}
+
+static HPET: Once<Option<Hpet>> = Once::new();
+
+/// Returns the parsed HPET table, if the firmware provides one.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.call_once(|| find_table(b"HPET").and_then(|table| Hpet::parse(table_body(table))))
+        .as_ref()
+}
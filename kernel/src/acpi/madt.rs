@@ -0,0 +1,203 @@
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use super::{SdtHeader, find_table, read_u8, read_u16, read_u32, read_u64, table_body};
+
+// Entry types in the MADT's interrupt controller structure list
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+// Local APIC flags
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// A processor's local APIC.
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApic {
+    pub processor_id: u32,
+    pub apic_id: u32,
+}
+
+/// An I/O APIC and the first global system interrupt it handles.
+#[derive(Clone, Copy, Debug)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u64,
+    pub gsi_base: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// How a legacy ISA IRQ is wired to a global system interrupt.
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+/// A local APIC input wired to NMI.
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApicNmi {
+    /// ACPI processor id, or `None` for every processor.
+    pub processor_id: Option<u8>,
+    /// LINT0 or LINT1.
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+/// Interrupt controller topology described by the MADT.
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// Whether the system also has 8259 PICs that must be disabled.
+    pub has_legacy_pics: bool,
+    pub local_apics: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    fn parse(table: &'static SdtHeader) -> Self {
+        let body = table_body(table);
+
+        let mut madt = Self {
+            local_apic_address: read_u32(body, 0).unwrap_or(0) as u64,
+            has_legacy_pics: read_u32(body, 4).is_some_and(|flags| flags & 1 != 0),
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            local_apic_nmis: Vec::new(),
+        };
+
+        // Entries follow the 4 byte local APIC address and 4 byte flags
+        let mut offset = 8;
+
+        while let (Some(entry_type), Some(length)) =
+            (read_u8(body, offset), read_u8(body, offset + 1))
+        {
+            let length = length as usize;
+            let Some(entry) = body.get(offset..offset + length).filter(|_| length >= 2) else {
+                break;
+            };
+
+            // Entries too short for their type are skipped
+            madt.parse_entry(entry_type, entry);
+
+            offset += length;
+        }
+
+        madt
+    }
+
+    fn parse_entry(&mut self, entry_type: u8, entry: &[u8]) -> Option<()> {
+        match entry_type {
+            ENTRY_LOCAL_APIC => {
+                let flags = read_u32(entry, 4)?;
+
+                if flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0 {
+                    self.local_apics.push(LocalApic {
+                        processor_id: read_u8(entry, 2)? as u32,
+                        apic_id: read_u8(entry, 3)? as u32,
+                    });
+                }
+            }
+            ENTRY_LOCAL_X2APIC => {
+                let flags = read_u32(entry, 8)?;
+
+                if flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0 {
+                    self.local_apics.push(LocalApic {
+                        processor_id: read_u32(entry, 12)?,
+                        apic_id: read_u32(entry, 4)?,
+                    });
+                }
+            }
+            ENTRY_IO_APIC => self.io_apics.push(IoApic {
+                id: read_u8(entry, 2)?,
+                address: read_u32(entry, 4)? as u64,
+                gsi_base: read_u32(entry, 8)?,
+            }),
+            ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+                let (polarity, trigger_mode) = decode_flags(read_u16(entry, 8)?);
+
+                self.overrides.push(InterruptOverride {
+                    irq: read_u8(entry, 3)?,
+                    gsi: read_u32(entry, 4)?,
+                    polarity: polarity.unwrap_or(Polarity::ActiveHigh),
+                    trigger_mode: trigger_mode.unwrap_or(TriggerMode::Edge),
+                });
+            }
+            ENTRY_LOCAL_APIC_NMI => {
+                let (polarity, trigger_mode) = decode_flags(read_u16(entry, 3)?);
+
+                self.local_apic_nmis.push(LocalApicNmi {
+                    processor_id: Some(read_u8(entry, 2)?).filter(|&id| id != 0xFF),
+                    lint: read_u8(entry, 5)?,
+                    polarity: polarity.unwrap_or(Polarity::ActiveHigh),
+                    trigger_mode: trigger_mode.unwrap_or(TriggerMode::Edge),
+                });
+            }
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                self.local_apic_address = read_u64(entry, 4)?;
+            }
+            _ => {}
+        }
+
+        Some(())
+    }
+
+    /// Returns the global system interrupt, polarity and trigger mode a
+    /// legacy ISA IRQ is delivered as.
+    pub fn isa_irq(&self, irq: u8) -> (u32, Polarity, TriggerMode) {
+        self.overrides
+            .iter()
+            .find(|entry| entry.irq == irq)
+            .map(|entry| (entry.gsi, entry.polarity, entry.trigger_mode))
+            // Without an override ISA IRQs are identity mapped, edge triggered
+            // and active high
+            .unwrap_or((irq as u32, Polarity::ActiveHigh, TriggerMode::Edge))
+    }
+}
+
+// MPS INTI flags; `None` means "conforms to the bus", which is active high
+// and edge triggered for ISA
+fn decode_flags(flags: u16) -> (Option<Polarity>, Option<TriggerMode>) {
+    let polarity = match flags & 0b11 {
+        0b01 => Some(Polarity::ActiveHigh),
+        0b11 => Some(Polarity::ActiveLow),
+        _ => None,
+    };
+
+    let trigger_mode = match (flags >> 2) & 0b11 {
+        0b01 => Some(TriggerMode::Edge),
+        0b11 => Some(TriggerMode::Level),
+        _ => None,
+    };
+
+    (polarity, trigger_mode)
+}
+
+static MADT: Once<Madt> = Once::new();
+
+/// Returns the parsed MADT, parsing it on first use.
+pub fn get() -> &'static Madt {
+    MADT.call_once(|| Madt::parse(find_table(b"APIC").expect("No MADT found")))
+}
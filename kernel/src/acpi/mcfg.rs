@@ -0,0 +1,43 @@
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use super::{find_table, read_u8, read_u16, read_u64, table_body};
+
+// Size of an allocation entry, which follow 8 reserved bytes
+const ENTRY_SIZE: usize = 16;
+
+/// A PCI Express enhanced configuration space region.
+#[derive(Clone, Copy, Debug)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+static ENTRIES: Once<Vec<McfgEntry>> = Once::new();
+
+/// Returns the memory mapped configuration space regions, which is empty
+/// when the firmware provides no MCFG.
+pub fn entries() -> &'static [McfgEntry] {
+    ENTRIES.call_once(|| {
+        let Some(table) = find_table(b"MCFG") else {
+            return Vec::new();
+        };
+
+        table_body(table)
+            .get(8..)
+            .unwrap_or_default()
+            .chunks_exact(ENTRY_SIZE)
+            .filter_map(|entry| {
+                Some(McfgEntry {
+                    base_address: read_u64(entry, 0)?,
+                    segment: read_u16(entry, 8)?,
+                    start_bus: read_u8(entry, 10)?,
+                    end_bus: read_u8(entry, 11)?,
+                })
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,307 @@
+use core::{slice, str};
+
+use spin::Once;
+
+use crate::boot;
+use crate::memory::paging::{self, PageFlags};
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+/// Header shared by every system description table.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    pub fn signature(&self) -> &str {
+        str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    pub fn oem_id(&self) -> &str {
+        str::from_utf8(&self.oem_id).unwrap_or("??????").trim_end()
+    }
+
+    /// The whole table, header included.
+    pub fn bytes(&'static self) -> &'static [u8] {
+        unsafe {
+            slice::from_raw_parts(self as *const SdtHeader as *const u8, self.length as usize)
+        }
+    }
+
+    fn is_valid(&'static self) -> bool {
+        checksum(self.bytes())
+    }
+}
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // Only present from revision 2 on
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+// Size of the revision 0 RSDP, which its checksum covers
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Address space of a [`GenericAddress`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    Other(u8),
+}
+
+/// ACPI generic address structure, describing a register.
+#[derive(Clone, Copy, Debug)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// Parses the 12 byte structure at `offset` in `bytes`.
+    pub fn parse(bytes: &[u8], offset: usize) -> Option<Self> {
+        let address_space = match *bytes.get(offset)? {
+            0 => AddressSpace::SystemMemory,
+            1 => AddressSpace::SystemIo,
+            2 => AddressSpace::PciConfiguration,
+            other => AddressSpace::Other(other),
+        };
+
+        Some(Self {
+            address_space,
+            // Register width, offset and access size follow the address
+            // space; every register the kernel touches has a fixed width
+            address: read_u64(bytes, offset + 4)?,
+        })
+    }
+}
+
+// Little endian field accessors. Fields past the end of a table read as
+// `None`, since older table revisions are shorter.
+pub(crate) fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    bytes.get(offset).copied()
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// ACPI structures are valid when all of their bytes sum to zero
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// The root table: the XSDT, which holds 64-bit table pointers, or on
+/// revision 0 firmware the RSDT, which holds 32-bit ones.
+pub struct RootTable {
+    pub header: &'static SdtHeader,
+    pub is_xsdt: bool,
+}
+
+impl RootTable {
+    /// Physical addresses of every table the root table points to.
+    pub fn entries(&self) -> impl Iterator<Item = u64> + '_ {
+        let entry_size = if self.is_xsdt { 8 } else { 4 };
+        let bytes = &self.header.bytes()[size_of::<SdtHeader>()..];
+
+        bytes.chunks_exact(entry_size).map(move |entry| {
+            if self.is_xsdt {
+                read_u64(entry, 0).unwrap()
+            } else {
+                read_u32(entry, 0).unwrap() as u64
+            }
+        })
+    }
+}
+
+static ROOT_TABLE: Once<RootTable> = Once::new();
+
+// Tables are mapped read-only; the kernel never writes to them
+fn map_table(phys: u64) -> &'static SdtHeader {
+    let flags = PageFlags::NO_EXECUTE;
+
+    let header = paging::map_physical(phys, size_of::<SdtHeader>() as u64, flags);
+    let length = unsafe { (*(header as *const SdtHeader)).length };
+    paging::map_physical(phys, length as u64, flags);
+
+    unsafe { &*(header as *const SdtHeader) }
+}
+
+/// Locates and validates the root system description table through
+/// Limine's RSDP.
+pub fn init() {
+    let rsdp_response = boot::RSDP_REQUEST
+        .get_response()
+        .expect("Failed to get RSDP response");
+
+    // With base revision 3 Limine reports the physical address
+    let rsdp_phys = rsdp_response.address() as u64;
+    let rsdp = paging::map_physical(rsdp_phys, size_of::<Rsdp>() as u64, PageFlags::NO_EXECUTE);
+    let rsdp_bytes = unsafe { slice::from_raw_parts(rsdp as *const u8, size_of::<Rsdp>()) };
+    let rsdp = unsafe { &*(rsdp as *const Rsdp) };
+
+    assert!(&rsdp.signature == b"RSD PTR ", "Invalid RSDP signature");
+    assert!(
+        checksum(&rsdp_bytes[..RSDP_V1_LENGTH]),
+        "Invalid RSDP checksum"
+    );
+
+    let is_xsdt = rsdp.revision >= 2 && rsdp.xsdt_address != 0;
+
+    if is_xsdt {
+        assert!(checksum(rsdp_bytes), "Invalid extended RSDP checksum");
+    }
+
+    let root = RootTable {
+        header: map_table(if is_xsdt {
+            rsdp.xsdt_address
+        } else {
+            rsdp.rsdt_address as u64
+        }),
+        is_xsdt,
+    };
+
+    if !root.header.is_valid() {
+        log::warn!("ACPI: {} checksum mismatch", root.header.signature());
+    }
+
+    ROOT_TABLE.call_once(|| root);
+}
+
+/// Returns the root table.
+pub fn root() -> &'static RootTable {
+    ROOT_TABLE.get().expect("ACPI not initialized")
+}
+
+/// Returns every table the root table points to whose checksum is valid.
+pub fn tables() -> impl Iterator<Item = &'static SdtHeader> {
+    root().entries().map(map_table).filter(|table| {
+        let valid = table.is_valid();
+
+        if !valid {
+            log::warn!("ACPI: ignoring {} with a bad checksum", table.signature());
+        }
+
+        valid
+    })
+}
+
+/// Finds the first table with the given signature.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    tables().find(|table| &table.signature == signature)
+}
+
+/// Returns the bytes of `table` following its header.
+pub fn table_body(table: &'static SdtHeader) -> &'static [u8] {
+    &table.bytes()[size_of::<SdtHeader>()..]
+}
+
+/// Logs the ACPI tables, processors and I/O APICs that were found.
+pub fn dump() {
+    let root = root();
+
+    log::info!(
+        "ACPI: {} from {} with {} tables",
+        root.header.signature(),
+        root.header.oem_id(),
+        root.entries().count()
+    );
+
+    for table in tables() {
+        log::info!(
+            "ACPI:   {} rev {} {} ({} bytes)",
+            table.signature(),
+            table.revision,
+            table.oem_id(),
+            { table.length }
+        );
+    }
+
+    if let Some(fadt) = fadt::get() {
+        log::info!(
+            "ACPI: SCI on IRQ {}, 8042 {}, reset register {}",
+            fadt.sci_interrupt,
+            if fadt.has_8042 { "present" } else { "absent" },
+            if fadt.reset_register.is_some() {
+                "supported"
+            } else {
+                "unsupported"
+            }
+        );
+    }
+
+    let madt = madt::get();
+
+    for local_apic in &madt.local_apics {
+        log::info!(
+            "ACPI: CPU {} with local APIC id {}",
+            local_apic.processor_id,
+            local_apic.apic_id
+        );
+    }
+
+    for io_apic in &madt.io_apics {
+        log::info!(
+            "ACPI: I/O APIC {} at {:#x}, GSIs from {}",
+            io_apic.id,
+            io_apic.address,
+            io_apic.gsi_base
+        );
+    }
+
+    for entry in mcfg::entries() {
+        log::info!(
+            "ACPI: PCIe segment {} buses {}-{} at {:#x}",
+            entry.segment,
+            entry.start_bus,
+            entry.end_bus,
+            entry.base_address
+        );
+    }
+}
+
+/// Powers the machine off by entering sleep state S5.
+pub fn shutdown() -> ! {
+    fadt::shutdown()
+}
+
+/// Resets the machine.
+pub fn reboot() -> ! {
+    fadt::reboot()
+}
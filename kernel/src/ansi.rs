@@ -0,0 +1,533 @@
+//! VT100 and xterm escape sequences.
+//!
+//! [`Parser`] splits console output into printable characters, control
+//! characters and escape sequences, the way a terminal reads the bytes sent
+//! to it. [`Style`] holds the text attributes SGR sequences select, and
+//! [`StyledLine`] a line of text in runs of different styles.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use cosmic_text::{Attrs, Color, Weight};
+
+// Parameters beyond this many are dropped
+const MAX_PARAMS: usize = 16;
+
+const ESCAPE: char = '\x1B';
+const BELL: char = '\x07';
+// Cancel a sequence in progress
+const CANCEL: char = '\x18';
+const SUBSTITUTE: char = '\x1A';
+
+/// Something the console should do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Draw a character at the cursor.
+    Print(char),
+    /// A C0 control character, such as `\n` or `\r`.
+    Control(char),
+    /// A control sequence introduced by `ESC [`.
+    Csi(Csi),
+    /// A sequence of `ESC` and a single final character, such as `ESC 7`.
+    Escape(char),
+}
+
+/// A parsed control sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    count: usize,
+    /// Set for DEC private sequences, whose parameters start with `?`.
+    pub private: bool,
+    /// The final character, which selects the function.
+    pub function: char,
+}
+
+impl Csi {
+    /// The numeric parameters. Empty ones are 0.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.count]
+    }
+
+    /// Parameter `index`, or `default` if it is missing or 0.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Ground,
+    Escape,
+    // After ESC and an intermediate byte; the sequence is skipped
+    EscapeIntermediate,
+    Csi,
+    // A control sequence with bytes we don't understand, skipped up to its
+    // final character
+    CsiIgnore,
+    // An operating system command or other string, skipped up to its
+    // terminator
+    String,
+    StringEscape,
+}
+
+/// Turns characters written to a terminal into [`Action`]s.
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                count: 0,
+                private: false,
+                function: '\0',
+            },
+        }
+    }
+
+    fn start_csi(&mut self) {
+        self.csi.params = [0; MAX_PARAMS];
+        self.csi.count = 0;
+        self.csi.private = false;
+        self.state = State::Csi;
+    }
+
+    /// Feeds the next character, returning what to do once a character or
+    /// sequence is complete.
+    pub fn advance(&mut self, character: char) -> Option<Action> {
+        // These abort any sequence, wherever they appear
+        match character {
+            ESCAPE if !matches!(self.state, State::String) => {
+                self.state = State::Escape;
+                return None;
+            }
+            CANCEL | SUBSTITUTE => {
+                self.state = State::Ground;
+                return None;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => match character {
+                '\0'..='\x1F' => Some(Action::Control(character)),
+                // DEL and the C1 controls, which nothing sends us
+                '\x7F'..='\u{9F}' => None,
+                _ => Some(Action::Print(character)),
+            },
+            State::Escape => {
+                self.state = State::Ground;
+
+                match character {
+                    '[' => self.start_csi(),
+                    ']' | 'P' | 'X' | '^' | '_' => self.state = State::String,
+                    ' '..='/' => self.state = State::EscapeIntermediate,
+                    '0'..='~' => return Some(Action::Escape(character)),
+                    _ => {}
+                }
+                None
+            }
+            State::EscapeIntermediate => {
+                if !(' '..='/').contains(&character) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::Csi => self.advance_csi(character),
+            State::CsiIgnore => {
+                if ('@'..='~').contains(&character) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::String => {
+                match character {
+                    BELL => self.state = State::Ground,
+                    ESCAPE => self.state = State::StringEscape,
+                    _ => {}
+                }
+                None
+            }
+            State::StringEscape => {
+                // ESC \ ends the string; anything else ends it too, since
+                // the escape can't be part of it
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+
+    fn advance_csi(&mut self, character: char) -> Option<Action> {
+        let csi = &mut self.csi;
+
+        match character {
+            '0'..='9' => {
+                if csi.count == 0 {
+                    csi.count = 1;
+                }
+                if let Some(param) = csi.params.get_mut(csi.count - 1) {
+                    let digit = character as u16 - '0' as u16;
+                    *param = param.saturating_mul(10).saturating_add(digit);
+                }
+                None
+            }
+            // Colons separate sub-parameters, as in `38:2:r:g:b`; they are
+            // read like semicolons
+            ';' | ':' => {
+                if csi.count == 0 {
+                    csi.count = 1;
+                }
+                csi.count = (csi.count + 1).min(MAX_PARAMS);
+                None
+            }
+            '<'..='?' if csi.count == 0 && !csi.private => {
+                csi.private = character == '?';
+                if !csi.private {
+                    self.state = State::CsiIgnore;
+                }
+                None
+            }
+            '@'..='~' => {
+                csi.function = character;
+                self.state = State::Ground;
+                Some(Action::Csi(*csi))
+            }
+            // Controls inside a sequence take effect right away
+            '\0'..='\x1F' => Some(Action::Control(character)),
+            _ => {
+                self.state = State::CsiIgnore;
+                None
+            }
+        }
+    }
+}
+
+/// Text attributes selected with SGR sequences.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Style {
+    /// `None` for the console's default color.
+    pub foreground: Option<Color>,
+    /// `None` for the console's background.
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    /// Swaps the foreground and background colors.
+    pub inverse: bool,
+}
+
+// The xterm colors of SGR 30-37 and 90-97
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xCD, 0x00, 0x00),
+    (0x00, 0xCD, 0x00),
+    (0xCD, 0xCD, 0x00),
+    (0x00, 0x00, 0xEE),
+    (0xCD, 0x00, 0xCD),
+    (0x00, 0xCD, 0xCD),
+    (0xE5, 0xE5, 0xE5),
+    (0x7F, 0x7F, 0x7F),
+    (0xFF, 0x00, 0x00),
+    (0x00, 0xFF, 0x00),
+    (0xFF, 0xFF, 0x00),
+    (0x5C, 0x5C, 0xFF),
+    (0xFF, 0x00, 0xFF),
+    (0x00, 0xFF, 0xFF),
+    (0xFF, 0xFF, 0xFF),
+];
+
+/// Color `index` of the xterm 256-color palette: the 16 basic colors, a
+/// 6x6x6 cube and a ramp of 24 grays.
+pub fn indexed_color(index: u8) -> Color {
+    match index {
+        0..16 => {
+            let (r, g, b) = PALETTE[index as usize];
+            Color::rgb(r, g, b)
+        }
+        16..232 => {
+            let level = |value: u8| if value == 0 { 0 } else { 55 + 40 * value };
+            let cube = index - 16;
+            Color::rgb(level(cube / 36), level(cube / 6 % 6), level(cube % 6))
+        }
+        232.. => {
+            let gray = 8 + 10 * (index - 232);
+            Color::rgb(gray, gray, gray)
+        }
+    }
+}
+
+impl Style {
+    pub const PLAIN: Self = Self {
+        foreground: None,
+        background: None,
+        bold: false,
+        italic: false,
+        inverse: false,
+    };
+
+    /// Applies the parameters of an SGR (`ESC [ ... m`) sequence. Unknown
+    /// attributes are ignored.
+    pub fn apply_sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            *self = Self::PLAIN;
+            return;
+        }
+
+        let mut params = params.iter().copied();
+
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Self::PLAIN,
+                1 => self.bold = true,
+                3 => self.italic = true,
+                7 => self.inverse = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                27 => self.inverse = false,
+                30..=37 => self.foreground = Some(indexed_color(param as u8 - 30)),
+                38 => self.foreground = extended_color(&mut params),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(indexed_color(param as u8 - 40)),
+                48 => self.background = extended_color(&mut params),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(indexed_color(param as u8 - 90 + 8)),
+                100..=107 => self.background = Some(indexed_color(param as u8 - 100 + 8)),
+                _ => {}
+            }
+        }
+    }
+
+    /// The attributes to shape text in this style with, given the console's
+    /// default text color.
+    pub fn attrs(&self, default_color: Color) -> Attrs<'static> {
+        let color = if self.inverse {
+            self.background.unwrap_or(Color::rgb(0, 0, 0))
+        } else {
+            self.foreground.unwrap_or(default_color)
+        };
+
+        let mut attrs = Attrs::new().color(color);
+        if self.bold {
+            attrs = attrs.weight(Weight::BOLD);
+        }
+        if self.italic {
+            attrs = attrs.style(cosmic_text::Style::Italic);
+        }
+
+        attrs
+    }
+
+    /// The color to fill behind text in this style, if not the console's
+    /// background.
+    pub fn background_color(&self, default_color: Color) -> Option<Color> {
+        if self.inverse {
+            Some(self.foreground.unwrap_or(default_color))
+        } else {
+            self.background
+        }
+    }
+}
+
+// Reads the rest of a 256-color (`5;n`) or truecolor (`2;r;g;b`) selection
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let mut component = || params.next().map(|value| value.min(255) as u8);
+
+    match component()? {
+        5 => Some(indexed_color(component()?)),
+        2 => Some(Color::rgb(component()?, component()?, component()?)),
+        _ => None,
+    }
+}
+
+/// A line of text made of runs that each have a [`Style`].
+#[derive(Clone, Debug)]
+pub struct StyledLine {
+    text: String,
+    // Byte offset each run ends at; a run starts where the previous one
+    // ends, and the last one ends at the end of the text
+    runs: Vec<(usize, Style)>,
+}
+
+// Appends a run ending at `end`, merging it into the last run if they have
+// the same style and dropping it if it is empty
+fn push_run(runs: &mut Vec<(usize, Style)>, end: usize, style: Style) {
+    let start = runs.last().map_or(0, |&(end, _)| end);
+
+    if end <= start {
+        return;
+    }
+
+    match runs.last_mut() {
+        Some((last_end, last_style)) if *last_style == style => *last_end = end,
+        _ => runs.push((end, style)),
+    }
+}
+
+impl StyledLine {
+    pub const fn new() -> Self {
+        Self {
+            text: String::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// The runs of text in order, with their styles.
+    pub fn runs(&self) -> impl Iterator<Item = (&str, Style)> {
+        let mut start = 0;
+
+        self.runs.iter().map(move |&(end, style)| {
+            let text = &self.text[start..end];
+            start = end;
+            (text, style)
+        })
+    }
+
+    /// Appends `text` in `style`.
+    pub fn push_str(&mut self, text: &str, style: Style) {
+        self.text.push_str(text);
+        push_run(&mut self.runs, self.text.len(), style);
+    }
+
+    /// Replaces the bytes in `range` with `text` in `style`.
+    pub fn replace(&mut self, range: Range<usize>, text: &str, style: Style) {
+        if range.start == self.text.len() {
+            self.push_str(text, style);
+            return;
+        }
+
+        let mut runs = Vec::with_capacity(self.runs.len() + 2);
+
+        // The parts of runs before the range keep their offsets, and those
+        // after it move by the change in length
+        let mut start = 0;
+        for &(end, run_style) in &self.runs {
+            if start < range.start {
+                push_run(&mut runs, end.min(range.start), run_style);
+            }
+            start = end;
+        }
+
+        push_run(&mut runs, range.start + text.len(), style);
+
+        for &(end, run_style) in &self.runs {
+            if end > range.end {
+                push_run(
+                    &mut runs,
+                    end - range.end + range.start + text.len(),
+                    run_style,
+                );
+            }
+        }
+
+        self.text.replace_range(range, text);
+        self.runs = runs;
+    }
+
+    /// Drops the text from byte `length` on.
+    pub fn truncate(&mut self, length: usize) {
+        self.replace(length..self.text.len(), "", Style::PLAIN);
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.runs.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn parse(text: &str) -> Vec<Action> {
+        let mut parser = Parser::new();
+        text.chars().filter_map(|c| parser.advance(c)).collect()
+    }
+
+    #[test_case]
+    fn parser_splits_text_controls_and_sequences() {
+        let actions = parse("a\r\x1B[12;5H\x1B[?25l\x1B]0;title\x07\x1B7b");
+
+        assert_eq!(actions.len(), 6);
+        assert_eq!(actions[0], Action::Print('a'));
+        assert_eq!(actions[1], Action::Control('\r'));
+
+        let Action::Csi(position) = actions[2] else {
+            panic!("expected a control sequence");
+        };
+        assert_eq!(position.function, 'H');
+        assert_eq!(position.params(), [12, 5]);
+        assert_eq!(position.param(2, 1), 1);
+
+        let Action::Csi(hide_cursor) = actions[3] else {
+            panic!("expected a control sequence");
+        };
+        assert!(hide_cursor.private);
+
+        assert_eq!(actions[4], Action::Escape('7'));
+        assert_eq!(actions[5], Action::Print('b'));
+    }
+
+    #[test_case]
+    fn sgr_selects_colors_and_attributes() {
+        let mut style = Style::PLAIN;
+
+        style.apply_sgr(&[1, 31, 48, 5, 196]);
+        assert!(style.bold);
+        assert_eq!(style.foreground, Some(indexed_color(1)));
+        assert_eq!(style.background, Some(Color::rgb(255, 0, 0)));
+
+        style.apply_sgr(&[38, 2, 1, 2, 3, 22]);
+        assert!(!style.bold);
+        assert_eq!(style.foreground, Some(Color::rgb(1, 2, 3)));
+
+        style.apply_sgr(&[]);
+        assert_eq!(style, Style::PLAIN);
+    }
+
+    #[test_case]
+    fn styled_line_splits_and_merges_runs() {
+        let bold = Style {
+            bold: true,
+            ..Style::PLAIN
+        };
+        let mut line = StyledLine::new();
+
+        line.push_str("hello", Style::PLAIN);
+        line.push_str(" world", bold);
+        line.replace(1..3, "EY", bold);
+        assert_eq!(line.text(), "hEYlo world");
+
+        let runs: Vec<(&str, Style)> = line.runs().collect();
+        assert_eq!(
+            runs,
+            [
+                ("h", Style::PLAIN),
+                ("EY", bold),
+                ("lo", Style::PLAIN),
+                (" world", bold)
+            ]
+        );
+
+        line.replace(3..5, "LO", bold);
+        line.truncate(8);
+        let runs: Vec<(&str, Style)> = line.runs().collect();
+        assert_eq!(runs, [("h", Style::PLAIN), ("EYLO wo", bold)]);
+    }
+}
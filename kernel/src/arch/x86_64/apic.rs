@@ -0,0 +1,200 @@
+use core::ptr;
+
+use spin::Once;
+
+use super::cpu::{self, MSR_APIC_BASE};
+use crate::acpi::madt::{self, Polarity, TriggerMode};
+use crate::memory::paging;
+
+// Register offsets from the local APIC base
+const REGISTER_ID: u64 = 0x020;
+const REGISTER_TASK_PRIORITY: u64 = 0x080;
+const REGISTER_EOI: u64 = 0x0B0;
+const REGISTER_SPURIOUS: u64 = 0x0F0;
+const REGISTER_ERROR_STATUS: u64 = 0x280;
+const REGISTER_ICR_LOW: u64 = 0x300;
+const REGISTER_ICR_HIGH: u64 = 0x310;
+const REGISTER_LVT_TIMER: u64 = 0x320;
+const REGISTER_LVT_LINT0: u64 = 0x350;
+const REGISTER_LVT_LINT1: u64 = 0x360;
+const REGISTER_LVT_ERROR: u64 = 0x370;
+const REGISTER_TIMER_INITIAL_COUNT: u64 = 0x380;
+const REGISTER_TIMER_CURRENT_COUNT: u64 = 0x390;
+const REGISTER_TIMER_DIVIDE: u64 = 0x3E0;
+
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+
+// LVT bits
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL_TRIGGERED: u32 = 1 << 15;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+// Divide configuration value for dividing the timer's input clock by 16
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+// ICR bits
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_DELIVERY_STATUS: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// Vector of spurious local APIC interrupts, which must not be acknowledged.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Vector local APIC errors are reported on.
+pub const ERROR_VECTOR: u8 = 0xFE;
+
+/// Vector of the local APIC timer.
+pub const TIMER_VECTOR: u8 = 0x30;
+
+static LAPIC_BASE: Once<u64> = Once::new();
+
+fn read(register: u64) -> u32 {
+    let base = LAPIC_BASE.get().expect("local APIC not initialized");
+
+    unsafe { ptr::read_volatile((base + register) as *const u32) }
+}
+
+fn write(register: u64, value: u32) {
+    let base = LAPIC_BASE.get().expect("local APIC not initialized");
+
+    unsafe { ptr::write_volatile((base + register) as *mut u32, value) };
+}
+
+/// Returns the APIC id of the current CPU.
+pub fn id() -> u32 {
+    read(REGISTER_ID) >> 24
+}
+
+/// Signals the end of the interrupt currently being serviced.
+pub fn eoi() {
+    write(REGISTER_EOI, 0);
+}
+
+fn send_command(destination: u32, command: u32) {
+    cpu::without_interrupts(|| {
+        write(REGISTER_ICR_HIGH, destination << 24);
+        write(REGISTER_ICR_LOW, command | ICR_LEVEL_ASSERT);
+
+        while read(REGISTER_ICR_LOW) & ICR_DELIVERY_STATUS != 0 {
+            core::hint::spin_loop();
+        }
+    });
+}
+
+/// Sends the inter-processor interrupt `vector` to every other CPU.
+pub fn broadcast_ipi(vector: u8) {
+    send_command(0, vector as u32 | ICR_ALL_EXCLUDING_SELF);
+}
+
+/// Sends a non-maskable interrupt to every other CPU.
+pub fn broadcast_nmi() {
+    send_command(0, ICR_DELIVERY_NMI | ICR_ALL_EXCLUDING_SELF);
+}
+
+/// Starts the timer counting down from `initial_count`, masked, so its
+/// rate can be measured with [`timer_count`].
+pub fn start_timer_calibration(initial_count: u32) {
+    write(REGISTER_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(REGISTER_LVT_TIMER, LVT_MASKED | TIMER_VECTOR as u32);
+    write(REGISTER_TIMER_INITIAL_COUNT, initial_count);
+}
+
+/// Returns the timer's current count.
+pub fn timer_count() -> u32 {
+    read(REGISTER_TIMER_CURRENT_COUNT)
+}
+
+/// Fires [`TIMER_VECTOR`] every `initial_count` divided timer ticks.
+pub fn start_timer_periodic(initial_count: u32) {
+    write(REGISTER_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(REGISTER_LVT_TIMER, LVT_TIMER_PERIODIC | TIMER_VECTOR as u32);
+    write(REGISTER_TIMER_INITIAL_COUNT, initial_count);
+}
+
+/// Stops the timer.
+pub fn stop_timer() {
+    write(REGISTER_LVT_TIMER, LVT_MASKED);
+    write(REGISTER_TIMER_INITIAL_COUNT, 0);
+}
+
+/// Maps the local APIC registers. Called once, on the bootstrap processor.
+pub fn init() {
+    let madt = madt::get();
+
+    LAPIC_BASE.call_once(|| paging::map_mmio(madt.local_apic_address, 0x1000));
+
+    init_local();
+}
+
+/// Enables and configures the local APIC of the current CPU.
+pub fn init_local() {
+    unsafe {
+        let base = cpu::rdmsr(MSR_APIC_BASE);
+        cpu::wrmsr(MSR_APIC_BASE, base | APIC_BASE_ENABLE);
+    }
+
+    // Accept every interrupt priority
+    write(REGISTER_TASK_PRIORITY, 0);
+
+    write(REGISTER_LVT_TIMER, LVT_MASKED);
+    write(REGISTER_LVT_ERROR, ERROR_VECTOR as u32);
+
+    // LINT pins stay masked unless the MADT wires them to NMI
+    write(REGISTER_LVT_LINT0, LVT_MASKED);
+    write(REGISTER_LVT_LINT1, LVT_MASKED);
+
+    let madt = madt::get();
+    let apic_id = id();
+    let processor_id = madt
+        .local_apics
+        .iter()
+        .find(|local_apic| local_apic.apic_id == apic_id)
+        .map(|local_apic| local_apic.processor_id);
+
+    for nmi in &madt.local_apic_nmis {
+        let applies = match nmi.processor_id {
+            None => true,
+            Some(id) => Some(id as u32) == processor_id,
+        };
+
+        if !applies {
+            continue;
+        }
+
+        let mut lvt = LVT_DELIVERY_NMI;
+        if nmi.polarity == Polarity::ActiveLow {
+            lvt |= LVT_ACTIVE_LOW;
+        }
+        if nmi.trigger_mode == TriggerMode::Level {
+            lvt |= LVT_LEVEL_TRIGGERED;
+        }
+
+        let register = if nmi.lint == 0 {
+            REGISTER_LVT_LINT0
+        } else {
+            REGISTER_LVT_LINT1
+        };
+        write(register, lvt);
+    }
+
+    // Writing the error status register clears it
+    write(REGISTER_ERROR_STATUS, 0);
+    write(REGISTER_ERROR_STATUS, 0);
+
+    write(REGISTER_SPURIOUS, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+
+    // Drop anything left pending by firmware
+    eoi();
+}
+
+/// Reports a local APIC error. Installed as the handler of [`ERROR_VECTOR`].
+pub(super) fn error_interrupt(_frame: &mut super::interrupts::InterruptFrame) {
+    write(REGISTER_ERROR_STATUS, 0);
+    let status = read(REGISTER_ERROR_STATUS);
+
+    crate::serial_println!("Local APIC {} error: {:#x}", id(), status);
+}
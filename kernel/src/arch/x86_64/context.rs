@@ -0,0 +1,78 @@
+use core::arch::global_asm;
+
+// Callee-saved registers are pushed onto the old thread's stack, which is
+// then swapped for the new one's; everything else was already saved by the
+// compiler around the call. A new thread's stack is prepared to look like
+// one switched away from just before entering `context_start`, which calls
+// the entry point held in r12.
+global_asm!(
+    ".pushsection .text",
+    ".global context_switch",
+    "context_switch:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    "",
+    ".global context_start",
+    "context_start:",
+    "call r12",
+    "ud2",
+    ".popsection",
+);
+
+unsafe extern "C" {
+    fn context_switch(old_stack_pointer: *mut u64, new_stack_pointer: u64);
+    fn context_start();
+}
+
+// Registers popped by `context_switch` before it returns
+const SAVED_REGISTERS: usize = 6;
+
+/// Prepares `stack` so that switching to the returned stack pointer calls
+/// `entry` on it.
+pub fn init_stack(stack: &mut [u8], entry: extern "C" fn() -> !) -> u64 {
+    // `context_start` is entered with the stack 16 byte aligned, so its call
+    // leaves `entry` with the alignment the ABI expects
+    let top = stack.as_mut_ptr_range().end as u64 & !0xF;
+    let stack_pointer = top - (SAVED_REGISTERS as u64 + 1) * 8;
+
+    let frame = stack_pointer as *mut u64;
+    unsafe {
+        for register in 0..SAVED_REGISTERS {
+            frame.add(register).write(0);
+        }
+
+        // r12 is the fourth register popped, then comes the return address
+        frame.add(3).write(entry as usize as u64);
+        frame
+            .add(SAVED_REGISTERS)
+            .write(context_start as *const () as u64);
+    }
+
+    stack_pointer
+}
+
+/// Saves the current callee-saved registers and stack pointer to
+/// `old_stack_pointer` and resumes the context saved at `new_stack_pointer`.
+/// Returns when something switches back to the saved context.
+///
+/// # Safety
+///
+/// Interrupts must be disabled, and `new_stack_pointer` must come from
+/// [`init_stack`] or a previous switch away from a context that is still
+/// alive.
+pub unsafe fn switch(old_stack_pointer: *mut u64, new_stack_pointer: u64) {
+    unsafe { context_switch(old_stack_pointer, new_stack_pointer) };
+}
@@ -0,0 +1,192 @@
+use core::arch::asm;
+
+// Model specific registers
+pub const MSR_APIC_BASE: u32 = 0x1B;
+pub const MSR_PAT: u32 = 0x277;
+pub const MSR_EFER: u32 = 0xC000_0080;
+pub const MSR_GS_BASE: u32 = 0xC000_0101;
+
+// EFER bits
+pub const EFER_NXE: u64 = 1 << 11;
+
+// CR0 bits
+pub const CR0_WP: u64 = 1 << 16;
+
+/// Reads a model specific register.
+#[inline]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Writes a model specific register.
+#[inline]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    unsafe {
+        asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32, options(nostack, preserves_flags));
+    }
+}
+
+#[inline]
+pub fn read_cr0() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+#[inline]
+pub unsafe fn write_cr0(value: u64) {
+    unsafe {
+        asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+/// Switches to the page tables rooted at physical address `pml4`.
+#[inline]
+pub unsafe fn write_cr3(pml4: u64) {
+    unsafe {
+        asm!("mov cr3, {}", in(reg) pml4, options(nostack, preserves_flags));
+    }
+}
+
+/// Invalidates the TLB entry for the page containing `addr` on this CPU.
+#[inline]
+pub fn invlpg(addr: u64) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) addr, options(nostack, preserves_flags));
+    }
+}
+
+/// Reads the time stamp counter.
+#[inline]
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Halts the CPU until the next interrupt.
+#[inline]
+pub fn hlt() {
+    unsafe {
+        asm!("hlt", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Returns the faulting address of the last page fault.
+#[inline]
+pub fn read_cr2() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Operand of `lgdt` and `lidt`.
+#[repr(C, packed(2))]
+pub struct DescriptorTablePointer {
+    pub limit: u16,
+    pub base: u64,
+}
+
+/// Loads the global descriptor table.
+#[inline]
+pub unsafe fn lgdt(pointer: &DescriptorTablePointer) {
+    unsafe {
+        asm!("lgdt [{}]", in(reg) pointer, options(readonly, nostack, preserves_flags));
+    }
+}
+
+/// Loads the interrupt descriptor table.
+#[inline]
+pub unsafe fn lidt(pointer: &DescriptorTablePointer) {
+    unsafe {
+        asm!("lidt [{}]", in(reg) pointer, options(readonly, nostack, preserves_flags));
+    }
+}
+
+/// Loads the task register with the TSS descriptor at `selector`.
+#[inline]
+pub unsafe fn ltr(selector: u16) {
+    unsafe {
+        asm!("ltr {:x}", in(reg) selector, options(nostack, preserves_flags));
+    }
+}
+
+// RFLAGS bits
+const RFLAGS_IF: u64 = 1 << 9;
+
+/// Enables maskable interrupts on this CPU.
+#[inline]
+pub fn enable_interrupts() {
+    unsafe {
+        asm!("sti", options(nomem, nostack));
+    }
+}
+
+/// Disables maskable interrupts on this CPU.
+#[inline]
+pub fn disable_interrupts() {
+    unsafe {
+        asm!("cli", options(nomem, nostack));
+    }
+}
+
+/// Returns whether maskable interrupts are enabled on this CPU.
+#[inline]
+pub fn interrupts_enabled() -> bool {
+    read_rflags() & RFLAGS_IF != 0
+}
+
+/// Reads the flags register.
+#[inline]
+pub fn read_rflags() -> u64 {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    rflags
+}
+
+/// Disables interrupts, returning the flags register from before so
+/// [`restore_interrupts`] can put the interrupt state back.
+#[inline]
+pub fn save_and_disable_interrupts() -> u64 {
+    let rflags = read_rflags();
+    disable_interrupts();
+    rflags
+}
+
+/// Re-enables interrupts if they were enabled in `rflags`.
+#[inline]
+pub fn restore_interrupts(rflags: u64) {
+    if rflags & RFLAGS_IF != 0 {
+        enable_interrupts();
+    }
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous state after.
+#[inline]
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = interrupts_enabled();
+
+    if enabled {
+        disable_interrupts();
+    }
+
+    let result = f();
+
+    if enabled {
+        enable_interrupts();
+    }
+
+    result
+}
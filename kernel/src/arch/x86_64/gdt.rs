@@ -0,0 +1,169 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use core::arch::asm;
+
+use super::cpu::{self, DescriptorTablePointer};
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const TSS_SELECTOR: u16 = 0x18;
+
+/// Interrupt stack table slots, numbered as IDT entries refer to them.
+pub const DOUBLE_FAULT_IST: u8 = 1;
+pub const NMI_IST: u8 = 2;
+pub const MACHINE_CHECK_IST: u8 = 3;
+
+const IST_STACK_COUNT: usize = 3;
+
+// Large enough for the panic handler, which renders text on the console
+const IST_STACK_SIZE: usize = 64 * 1024;
+
+// 64-bit ring 0 code segment: present, executable, readable, long mode
+const KERNEL_CODE: u64 = 0x00AF_9A00_0000_FFFF;
+// Ring 0 data segment: present, writable
+const KERNEL_DATA: u64 = 0x00CF_9200_0000_FFFF;
+
+// Type of an available 64-bit TSS descriptor, with the present bit set
+const TSS_AVAILABLE: u64 = 0x89;
+
+/// 64-bit task state segment. The kernel only uses it for the interrupt
+/// stack table.
+#[repr(C, packed(4))]
+pub struct Tss {
+    reserved_1: u32,
+    pub privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    pub interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    iomap_base: u16,
+}
+
+impl Tss {
+    pub const fn new() -> Self {
+        Self {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            // No I/O permission bitmap
+            iomap_base: size_of::<Tss>() as u16,
+        }
+    }
+
+    /// Sets the stack the CPU switches to for IDT entries using `ist`.
+    pub fn set_ist(&mut self, ist: u8, stack_top: u64) {
+        self.interrupt_stack_table[ist as usize - 1] = stack_top;
+    }
+}
+
+/// Null, kernel code, kernel data and a two-slot TSS descriptor.
+#[repr(C, align(16))]
+pub struct Gdt {
+    entries: [u64; 5],
+}
+
+impl Gdt {
+    pub const fn new() -> Self {
+        Self {
+            entries: [0, KERNEL_CODE, KERNEL_DATA, 0, 0],
+        }
+    }
+
+    /// Loads this GDT, reloads the segment registers and loads `tss` into
+    /// the task register.
+    ///
+    /// # Safety
+    ///
+    /// Both tables must stay in place and unmodified for as long as this CPU
+    /// runs with them loaded.
+    pub unsafe fn load(&'static mut self, tss: &'static Tss) {
+        let base = tss as *const Tss as u64;
+        let limit = (size_of::<Tss>() - 1) as u64;
+
+        self.entries[3] = (limit & 0xFFFF)
+            | (base & 0xFF_FFFF) << 16
+            | TSS_AVAILABLE << 40
+            | (limit >> 16 & 0xF) << 48
+            | (base >> 24 & 0xFF) << 56;
+        self.entries[4] = base >> 32;
+
+        let pointer = DescriptorTablePointer {
+            limit: (size_of::<Gdt>() - 1) as u16,
+            base: self as *const Gdt as u64,
+        };
+
+        unsafe {
+            cpu::lgdt(&pointer);
+
+            // CS can only be reloaded by a far control transfer
+            asm!(
+                "push {code}",
+                "lea {target}, [rip + 2f]",
+                "push {target}",
+                "retfq",
+                "2:",
+                "mov ds, {data:x}",
+                "mov es, {data:x}",
+                "mov ss, {data:x}",
+                code = in(reg) KERNEL_CODE_SELECTOR as u64,
+                data = in(reg) KERNEL_DATA_SELECTOR as u64,
+                target = out(reg) _,
+                options(preserves_flags),
+            );
+
+            cpu::ltr(TSS_SELECTOR);
+        }
+    }
+}
+
+#[repr(C, align(16))]
+struct Stack([u8; IST_STACK_SIZE]);
+
+// Tables of the bootstrap processor, which exist before the heap does
+static mut BSP_GDT: Gdt = Gdt::new();
+static mut BSP_TSS: Tss = Tss::new();
+static mut BSP_IST_STACKS: [Stack; IST_STACK_COUNT] =
+    [const { Stack([0; IST_STACK_SIZE]) }; IST_STACK_COUNT];
+
+/// Installs the GDT and TSS on the bootstrap processor, with dedicated
+/// stacks for double faults, NMIs and machine checks.
+pub fn init() {
+    // Only the bootstrap processor runs this, once, before interrupts are
+    // enabled, so nothing else can reference these statics
+    let (gdt, tss, stacks) = (
+        &raw mut BSP_GDT,
+        &raw mut BSP_TSS,
+        &raw const BSP_IST_STACKS,
+    );
+    let (gdt, tss, stacks) = unsafe { (&mut *gdt, &mut *tss, &*stacks) };
+
+    install(gdt, tss, stacks.each_ref().map(|stack| &stack.0[..]));
+}
+
+/// Installs a freshly allocated GDT, TSS and set of IST stacks on an
+/// application processor. Needs the heap.
+pub fn init_ap() {
+    let stacks = [(); IST_STACK_COUNT].map(|()| &*vec![0u8; IST_STACK_SIZE].leak());
+
+    install(
+        Box::leak(Box::new(Gdt::new())),
+        Box::leak(Box::new(Tss::new())),
+        stacks,
+    );
+}
+
+fn install(gdt: &'static mut Gdt, tss: &'static mut Tss, stacks: [&[u8]; IST_STACK_COUNT]) {
+    for (ist, stack) in [DOUBLE_FAULT_IST, NMI_IST, MACHINE_CHECK_IST]
+        .into_iter()
+        .zip(stacks)
+    {
+        // Stacks grow down; keep the top 16 byte aligned
+        let top = stack.as_ptr_range().end as u64 & !0xF;
+        tss.set_ist(ist, top);
+    }
+
+    unsafe { gdt.load(tss) };
+}
@@ -0,0 +1,144 @@
+use core::arch::global_asm;
+
+use spin::Once;
+
+use super::cpu::{self, DescriptorTablePointer};
+use super::gdt::{self, KERNEL_CODE_SELECTOR};
+use super::interrupts;
+
+const VECTOR_COUNT: usize = 256;
+
+// Every entry stub is padded to this size, so stub `n` is at
+// `isr_stubs + n * STUB_SIZE`
+const STUB_SIZE: u64 = 16;
+
+// Present, ring 0, 64-bit interrupt gate (interrupts stay disabled in handlers)
+const INTERRUPT_GATE: u8 = 0x8E;
+
+// One stub per vector normalizes the stack to an `InterruptFrame`: vectors
+// for which the CPU doesn't push an error code push a zero in its place,
+// then every stub pushes its vector number and jumps to the common entry,
+// which saves the general purpose registers and calls the dispatcher.
+global_asm!(
+    ".pushsection .text",
+    ".global isr_stubs",
+    ".align 16",
+    "isr_stubs:",
+    ".set isr_vector, 0",
+    ".rept 256",
+    ".align 16",
+    ".if isr_vector == 8 || (isr_vector >= 10 && isr_vector <= 14) || isr_vector == 17 || isr_vector == 21 || isr_vector == 29 || isr_vector == 30",
+    ".else",
+    "push 0",
+    ".endif",
+    "push isr_vector",
+    "jmp isr_common",
+    ".set isr_vector, isr_vector + 1",
+    ".endr",
+    "",
+    "isr_common:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "cld",
+    // 22 quadwords were pushed onto the 16 byte aligned stack the CPU
+    // switched to, so the call below is correctly aligned
+    "mov rdi, rsp",
+    "call {dispatch}",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    // Drop the vector and error code
+    "add rsp, 16",
+    "iretq",
+    ".popsection",
+    dispatch = sym interrupts::dispatch,
+);
+
+unsafe extern "C" {
+    static isr_stubs: u8;
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct IdtEntry {
+    offset_low: u16,
+    selector: u16,
+    ist: u8,
+    type_attributes: u8,
+    offset_middle: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+impl IdtEntry {
+    fn new(handler: u64, ist: u8) -> Self {
+        Self {
+            offset_low: handler as u16,
+            selector: KERNEL_CODE_SELECTOR,
+            ist,
+            type_attributes: INTERRUPT_GATE,
+            offset_middle: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+#[repr(C, align(16))]
+struct Idt([IdtEntry; VECTOR_COUNT]);
+
+static IDT: Once<Idt> = Once::new();
+
+fn build() -> Idt {
+    let stubs = &raw const isr_stubs as u64;
+
+    Idt(core::array::from_fn(|vector| {
+        // Exceptions that can hit with a corrupt or exhausted stack get one
+        // of their own
+        let ist = match vector as u8 {
+            interrupts::DOUBLE_FAULT => gdt::DOUBLE_FAULT_IST,
+            interrupts::NON_MASKABLE_INTERRUPT => gdt::NMI_IST,
+            interrupts::MACHINE_CHECK => gdt::MACHINE_CHECK_IST,
+            _ => 0,
+        };
+
+        IdtEntry::new(stubs + vector as u64 * STUB_SIZE, ist)
+    }))
+}
+
+/// Loads the IDT on the current CPU, building it first if needed.
+pub fn load() {
+    let idt = IDT.call_once(build);
+
+    let pointer = DescriptorTablePointer {
+        limit: (size_of::<Idt>() - 1) as u16,
+        base: idt as *const Idt as u64,
+    };
+
+    unsafe { cpu::lidt(&pointer) };
+}
@@ -0,0 +1,268 @@
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Once;
+
+use super::{apic, cpu, ioapic, smp};
+use crate::acpi::madt;
+
+// Exception vectors referred to by name
+pub const NON_MASKABLE_INTERRUPT: u8 = 2;
+pub const BREAKPOINT: u8 = 3;
+pub const DOUBLE_FAULT: u8 = 8;
+pub const PAGE_FAULT: u8 = 14;
+pub const MACHINE_CHECK: u8 = 18;
+
+/// Number of vectors reserved for CPU exceptions.
+pub const EXCEPTION_COUNT: u8 = 32;
+
+/// Vector of legacy ISA IRQ 0; IRQ `n` is delivered on `IRQ_BASE + n`.
+pub const IRQ_BASE: u8 = 0x20;
+
+/// Handler for an interrupt vector. Runs with interrupts disabled; the end of
+/// interrupt is signalled after it returns.
+pub type Handler = fn(&mut InterruptFrame);
+
+// Handlers stored as function pointer addresses, zero when none is installed,
+// so they can be read from interrupt context without taking a lock
+static HANDLERS: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
+
+static PREEMPT_HOOK: Once<fn()> = Once::new();
+
+const EXCEPTION_NAMES: [&str; EXCEPTION_COUNT as usize] = [
+    "Divide Error",
+    "Debug",
+    "Non-Maskable Interrupt",
+    "Breakpoint",
+    "Overflow",
+    "Bound Range Exceeded",
+    "Invalid Opcode",
+    "Device Not Available",
+    "Double Fault",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS",
+    "Segment Not Present",
+    "Stack-Segment Fault",
+    "General Protection Fault",
+    "Page Fault",
+    "Reserved",
+    "x87 Floating-Point Exception",
+    "Alignment Check",
+    "Machine Check",
+    "SIMD Floating-Point Exception",
+    "Virtualization Exception",
+    "Control Protection Exception",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception",
+    "VMM Communication Exception",
+    "Security Exception",
+    "Reserved",
+];
+
+/// Register state saved on interrupt entry, laid out as the entry stubs in
+/// `idt.rs` push it.
+#[derive(Debug)]
+#[repr(C)]
+pub struct InterruptFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// Error code pushed by the CPU, or zero for vectors without one.
+    pub error_code: u64,
+    // Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl fmt::Display for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "RIP {:#018x}  CS  {:#06x}  RFLAGS {:#010x}",
+            self.rip, self.cs, self.rflags
+        )?;
+        writeln!(f, "RSP {:#018x}  SS  {:#06x}", self.rsp, self.ss)?;
+        writeln!(
+            f,
+            "RAX {:#018x}  RBX {:#018x}  RCX {:#018x}",
+            self.rax, self.rbx, self.rcx
+        )?;
+        writeln!(
+            f,
+            "RDX {:#018x}  RSI {:#018x}  RDI {:#018x}",
+            self.rdx, self.rsi, self.rdi
+        )?;
+        writeln!(
+            f,
+            "RBP {:#018x}  R8  {:#018x}  R9  {:#018x}",
+            self.rbp, self.r8, self.r9
+        )?;
+        writeln!(
+            f,
+            "R10 {:#018x}  R11 {:#018x}  R12 {:#018x}",
+            self.r10, self.r11, self.r12
+        )?;
+        write!(
+            f,
+            "R13 {:#018x}  R14 {:#018x}  R15 {:#018x}",
+            self.r13, self.r14, self.r15
+        )
+    }
+}
+
+fn page_fault_cause(error_code: u64) -> &'static str {
+    let present = error_code & 1 != 0;
+    let write = error_code & (1 << 1) != 0;
+    let fetch = error_code & (1 << 4) != 0;
+
+    match (present, write, fetch) {
+        (true, _, true) => ": instruction fetch from a non-executable page",
+        (false, _, true) => ": instruction fetch from an unmapped page",
+        (true, true, false) => ": write to a read-only page",
+        (false, true, false) => ": write to an unmapped page",
+        (true, false, false) => ": protection violation on read",
+        (false, false, false) => ": read from an unmapped page",
+    }
+}
+
+fn exception(frame: &mut InterruptFrame) {
+    let vector = frame.vector as u8;
+
+    // Breakpoints are used for debugging and resume after the int3
+    if vector == BREAKPOINT {
+        crate::serial_println!("Breakpoint at {:#x}", frame.rip);
+        return;
+    }
+
+    // Another CPU panicked and is stopping this one
+    if vector == NON_MASKABLE_INTERRUPT && smp::is_halting() {
+        loop {
+            cpu::disable_interrupts();
+            cpu::hlt();
+        }
+    }
+
+    let cause = if vector == PAGE_FAULT {
+        page_fault_cause(frame.error_code)
+    } else {
+        ""
+    };
+
+    panic!(
+        "CPU exception {} ({}{}), error code {:#x}, CR2 {:#x}\n{}",
+        vector,
+        EXCEPTION_NAMES[vector as usize],
+        cause,
+        frame.error_code,
+        cpu::read_cr2(),
+        frame,
+    );
+}
+
+/// Installs `handler` for `vector`, replacing any previous one.
+pub fn set_handler(vector: u8, handler: Handler) {
+    assert!(
+        vector >= EXCEPTION_COUNT,
+        "vector {vector} is reserved for CPU exceptions"
+    );
+
+    HANDLERS[vector as usize].store(handler as usize, Ordering::Release);
+}
+
+/// Removes the handler for `vector`.
+#[cfg(test)]
+pub fn remove_handler(vector: u8) {
+    HANDLERS[vector as usize].store(0, Ordering::Release);
+}
+
+/// Installs a function called after every interrupt has been acknowledged,
+/// where it may switch to another thread.
+pub fn set_preempt_hook(hook: fn()) {
+    PREEMPT_HOOK.call_once(|| hook);
+}
+
+/// Routes legacy ISA IRQ `irq` through the I/O APIC to the current CPU and
+/// installs `handler` for it, returning the vector it is delivered on.
+pub fn register_irq(irq: u8, handler: Handler) -> u8 {
+    let vector = IRQ_BASE + irq;
+    let (gsi, polarity, trigger_mode) = madt::get().isa_irq(irq);
+
+    set_handler(vector, handler);
+    ioapic::route(gsi, vector, apic::id(), polarity, trigger_mode);
+
+    vector
+}
+
+/// Called by the common interrupt entry stub for every vector.
+pub(super) extern "C" fn dispatch(frame: &mut InterruptFrame) {
+    let vector = frame.vector as u8;
+
+    if vector < EXCEPTION_COUNT {
+        exception(frame);
+        return;
+    }
+
+    // Spurious interrupts are not in service, so they get no EOI
+    if vector == apic::SPURIOUS_VECTOR {
+        return;
+    }
+
+    match HANDLERS[vector as usize].load(Ordering::Acquire) {
+        0 => crate::serial_println!("Unhandled interrupt on vector {vector}"),
+        handler => {
+            let handler: Handler = unsafe { mem::transmute(handler) };
+            handler(frame);
+        }
+    }
+
+    apic::eoi();
+
+    if let Some(hook) = PREEMPT_HOOK.get() {
+        hook();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn breakpoint_resumes() {
+        unsafe { core::arch::asm!("int3") };
+    }
+
+    #[test_case]
+    fn software_interrupt_reaches_handler() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        set_handler(0x40, |_| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        });
+        unsafe { core::arch::asm!("int 0x40") };
+        remove_handler(0x40);
+
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+}
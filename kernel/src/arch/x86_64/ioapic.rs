@@ -0,0 +1,115 @@
+use alloc::vec::Vec;
+use core::ptr;
+
+use spin::Once;
+
+use crate::acpi::madt::{self, Polarity, TriggerMode};
+use crate::memory::paging;
+use crate::sync::SpinLock;
+
+// Memory mapped index and data registers
+const REGISTER_SELECT: u64 = 0x00;
+const REGISTER_WINDOW: u64 = 0x10;
+
+// Indirect registers
+const REGISTER_VERSION: u32 = 0x01;
+const REGISTER_REDIRECTION_TABLE: u32 = 0x10;
+
+// Redirection entry bits
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+struct IoApic {
+    base: u64,
+    gsi_base: u32,
+    redirection_entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + REGISTER_SELECT) as *mut u32, register);
+            ptr::read_volatile((self.base + REGISTER_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + REGISTER_SELECT) as *mut u32, register);
+            ptr::write_volatile((self.base + REGISTER_WINDOW) as *mut u32, value);
+        }
+    }
+
+    fn write_redirection(&self, index: u32, entry: u64) {
+        let register = REGISTER_REDIRECTION_TABLE + index * 2;
+
+        // Write the destination first so the entry is never live half updated
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.redirection_entries).contains(&gsi)
+    }
+}
+
+// The index/data register pair makes every access a two step sequence
+static IO_APICS: Once<SpinLock<Vec<IoApic>>> = Once::new();
+
+fn io_apics() -> &'static SpinLock<Vec<IoApic>> {
+    IO_APICS.get().expect("I/O APICs not initialized")
+}
+
+/// Maps every I/O APIC in the MADT and masks all of their inputs.
+pub fn init() {
+    let madt = madt::get();
+
+    let io_apics = madt
+        .io_apics
+        .iter()
+        .map(|io_apic| {
+            let mut io_apic = IoApic {
+                base: paging::map_mmio(io_apic.address, 0x20),
+                gsi_base: io_apic.gsi_base,
+                redirection_entries: 0,
+            };
+
+            io_apic.redirection_entries = ((io_apic.read(REGISTER_VERSION) >> 16) & 0xFF) + 1;
+
+            for index in 0..io_apic.redirection_entries {
+                io_apic.write_redirection(index, REDIRECTION_MASKED);
+            }
+
+            io_apic
+        })
+        .collect();
+
+    IO_APICS.call_once(|| SpinLock::new("I/O APIC", io_apics));
+}
+
+/// Routes global system interrupt `gsi` to `vector` on the CPU with APIC id
+/// `destination`, and unmasks it.
+pub fn route(
+    gsi: u32,
+    vector: u8,
+    destination: u32,
+    polarity: Polarity,
+    trigger_mode: TriggerMode,
+) {
+    let io_apics = io_apics().lock();
+    let io_apic = io_apics
+        .iter()
+        .find(|io_apic| io_apic.handles(gsi))
+        .unwrap_or_else(|| panic!("No I/O APIC handles GSI {gsi}"));
+
+    let mut entry = vector as u64 | (destination as u64) << 56;
+    if polarity == Polarity::ActiveLow {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if trigger_mode == TriggerMode::Level {
+        entry |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+
+    io_apic.write_redirection(gsi - io_apic.gsi_base, entry);
+}
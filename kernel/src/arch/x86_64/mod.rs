@@ -0,0 +1,35 @@
+pub mod apic;
+pub mod context;
+pub mod cpu;
+pub mod gdt;
+pub mod idt;
+pub mod interrupts;
+pub mod ioapic;
+pub mod percpu;
+pub mod pic;
+pub mod port;
+pub mod smp;
+
+use crate::acpi::madt;
+
+/// Installs the GDT, TSS and IDT on the bootstrap processor.
+pub fn init() {
+    gdt::init();
+    idt::load();
+}
+
+/// Replaces the legacy PICs with the local and I/O APICs, sets up the
+/// bootstrap processor's per-CPU data and enables interrupts. Needs the heap
+/// and ACPI tables.
+pub fn init_interrupts() {
+    if madt::get().has_legacy_pics {
+        pic::disable();
+    }
+
+    apic::init();
+    percpu::init(0, apic::id());
+    ioapic::init();
+    interrupts::set_handler(apic::ERROR_VECTOR, apic::error_interrupt);
+
+    cpu::enable_interrupts();
+}
@@ -0,0 +1,73 @@
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::mem::offset_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64};
+
+use super::cpu::{self, MSR_GS_BASE};
+
+/// Data private to one CPU, reached through the GS base.
+#[repr(C)]
+pub struct PerCpu {
+    // Address of this structure, so it can be found with a single
+    // GS-relative load
+    this: u64,
+    /// Logical CPU number; the bootstrap processor is 0.
+    pub id: u32,
+    pub apic_id: u32,
+    /// Task running on this CPU, owned by the scheduler.
+    pub current_task: AtomicPtr<()>,
+    /// Task this CPU runs when nothing else is ready, owned by the scheduler.
+    pub idle_task: AtomicPtr<()>,
+    /// Task this CPU just switched away from, until the scheduler has put
+    /// it away.
+    pub previous_task: AtomicPtr<()>,
+    /// Set when the running task should be preempted on the way out of the
+    /// current interrupt.
+    pub need_resched: AtomicBool,
+    /// Spare storage for entry code that runs before a register is free.
+    pub scratch: AtomicU64,
+}
+
+/// Allocates the per-CPU data of the current CPU and points GS at it.
+///
+/// Needs the heap, and must run once per CPU before [`current`] is used.
+pub fn init(id: u32, apic_id: u32) {
+    let per_cpu = Box::leak(Box::new(PerCpu {
+        this: 0,
+        id,
+        apic_id,
+        current_task: AtomicPtr::new(ptr::null_mut()),
+        idle_task: AtomicPtr::new(ptr::null_mut()),
+        previous_task: AtomicPtr::new(ptr::null_mut()),
+        need_resched: AtomicBool::new(false),
+        scratch: AtomicU64::new(0),
+    }));
+    per_cpu.this = per_cpu as *const PerCpu as u64;
+
+    unsafe { cpu::wrmsr(MSR_GS_BASE, per_cpu.this) };
+}
+
+/// Returns whether [`init`] has run on the current CPU.
+pub fn is_initialized() -> bool {
+    unsafe { cpu::rdmsr(MSR_GS_BASE) != 0 }
+}
+
+/// Returns the per-CPU data of the current CPU.
+///
+/// The caller may be moved to another CPU right after this returns unless
+/// interrupts are disabled.
+#[inline]
+pub fn current() -> &'static PerCpu {
+    let this: u64;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{offset}]",
+            out(reg) this,
+            offset = const offset_of!(PerCpu, this),
+            options(nostack, preserves_flags, readonly),
+        );
+
+        &*(this as *const PerCpu)
+    }
+}
@@ -0,0 +1,46 @@
+use super::interrupts::IRQ_BASE;
+use super::port::{io_wait, outb};
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+// ICW1: initialization, ICW4 follows
+const ICW1_INIT: u8 = 0x11;
+// ICW4: 8086 mode
+const ICW4_8086: u8 = 0x01;
+
+// Vectors the PICs are moved to before masking: the ISA IRQ vectors the
+// I/O APIC uses as well. Spurious PIC interrupts can still arrive after
+// masking, and must not look like CPU exceptions or the APIC's own vectors
+// at the top of the range.
+const PIC1_OFFSET: u8 = IRQ_BASE;
+const PIC2_OFFSET: u8 = IRQ_BASE + 8;
+
+/// Remaps the legacy 8259 PICs out of the exception range and masks every
+/// line, leaving interrupt delivery to the APICs.
+pub fn disable() {
+    unsafe {
+        outb(PIC1_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(PIC2_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(PIC1_DATA, PIC1_OFFSET);
+        io_wait();
+        outb(PIC2_DATA, PIC2_OFFSET);
+        io_wait();
+        // The secondary PIC is cascaded on line 2 of the primary
+        outb(PIC1_DATA, 1 << 2);
+        io_wait();
+        outb(PIC2_DATA, 2);
+        io_wait();
+        outb(PIC1_DATA, ICW4_8086);
+        io_wait();
+        outb(PIC2_DATA, ICW4_8086);
+        io_wait();
+
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+    }
+}
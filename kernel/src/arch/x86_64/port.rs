@@ -0,0 +1,54 @@
+use core::arch::asm;
+
+/// Reads a byte from the given I/O port.
+#[inline]
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    unsafe {
+        asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Writes a byte to the given I/O port.
+#[inline]
+pub unsafe fn outb(port: u16, value: u8) {
+    unsafe {
+        asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Reads a word from the given I/O port.
+#[inline]
+pub unsafe fn inw(port: u16) -> u16 {
+    let value: u16;
+    unsafe {
+        asm!("in ax, dx", out("ax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Writes a word to the given I/O port.
+#[inline]
+pub unsafe fn outw(port: u16, value: u16) {
+    unsafe {
+        asm!("out dx, ax", in("dx") port, in("ax") value, options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Writes a doubleword to the given I/O port. Only the test runner's QEMU
+/// exit device needs it.
+#[cfg(test)]
+#[inline]
+pub unsafe fn outl(port: u16, value: u32) {
+    unsafe {
+        asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Waits roughly a microsecond by writing to an unused port, giving slow
+/// legacy devices time to respond.
+#[inline]
+pub unsafe fn io_wait() {
+    unsafe { outb(0x80, 0) };
+}
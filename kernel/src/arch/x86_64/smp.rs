@@ -0,0 +1,205 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use limine::mp::Cpu;
+use spin::Mutex;
+
+use super::{apic, cpu, gdt, idt, interrupts, percpu};
+use crate::boot;
+use crate::memory::{PAGE_SIZE, paging};
+use crate::task;
+use crate::time::{self, Instant};
+
+/// Vector other CPUs are asked to flush TLB entries on.
+pub const SHOOTDOWN_VECTOR: u8 = 0xF0;
+
+// How long the bootstrap processor waits for the others to come online
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(1);
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// CPUs whose local APIC is enabled, and which therefore receive broadcast
+// IPIs
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+// Application processors that finished starting up
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+// Application processors that reached their entry point, and whether the
+// bootstrap processor stopped waiting for the others
+struct Startup {
+    arrived: usize,
+    abandoned: bool,
+}
+
+static STARTUP: Mutex<Startup> = Mutex::new(Startup {
+    arrived: 0,
+    abandoned: false,
+});
+
+// Logical CPU numbers are handed out in the order processors start
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+// Serializes shootdowns, and CPUs coming online against them, so the number
+// of CPUs that must acknowledge a shootdown is exact. Unlike a `SpinLock` it
+// leaves interrupts enabled, so a CPU waiting for it still answers the
+// shootdown in progress
+static SHOOTDOWN_LOCK: Mutex<()> = Mutex::new(());
+static SHOOTDOWN_START: AtomicU64 = AtomicU64::new(0);
+static SHOOTDOWN_PAGES: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWN_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+// Set when a panicking CPU stops the others with an NMI
+static HALTING: AtomicBool = AtomicBool::new(false);
+
+/// Number of CPUs that are online.
+pub fn cpu_count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+// Asks every other CPU to flush the range and waits until all of them have
+fn shootdown(virt: u64, pages: usize) {
+    if cpu_count() == 1 {
+        return;
+    }
+
+    // Otherwise two CPUs shooting down at once could each wait forever for
+    // the other to acknowledge, and an interrupt handler could spin on the
+    // lock its own CPU holds
+    assert!(
+        cpu::interrupts_enabled(),
+        "TLB shootdown with interrupts disabled"
+    );
+
+    let _guard = SHOOTDOWN_LOCK.lock();
+
+    SHOOTDOWN_START.store(virt, Ordering::Relaxed);
+    SHOOTDOWN_PAGES.store(pages, Ordering::Relaxed);
+    SHOOTDOWN_PENDING.store(cpu_count() - 1, Ordering::Release);
+
+    apic::broadcast_ipi(SHOOTDOWN_VECTOR);
+
+    while SHOOTDOWN_PENDING.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+fn shootdown_interrupt(_frame: &mut interrupts::InterruptFrame) {
+    let start = SHOOTDOWN_START.load(Ordering::Relaxed);
+    let pages = SHOOTDOWN_PAGES.load(Ordering::Relaxed);
+
+    for page in 0..pages as u64 {
+        cpu::invlpg(start + page * PAGE_SIZE);
+    }
+
+    SHOOTDOWN_PENDING.fetch_sub(1, Ordering::Release);
+}
+
+/// Stops every other CPU. Used by the panic handler so the rest of the
+/// system can't interfere with the report.
+pub fn halt_others() {
+    if cpu_count() > 1 && !HALTING.swap(true, Ordering::SeqCst) {
+        apic::broadcast_nmi();
+    }
+}
+
+/// Returns whether an NMI is [`halt_others`] stopping this CPU.
+pub fn is_halting() -> bool {
+    HALTING.load(Ordering::SeqCst)
+}
+
+unsafe extern "C" fn ap_entry(cpu: &Cpu) -> ! {
+    // A processor arriving after the bootstrap processor gave up on it was
+    // counted as never using its boot stack, so it must not run
+    {
+        let mut startup = STARTUP.lock();
+
+        if startup.abandoned {
+            drop(startup);
+            cpu::disable_interrupts();
+            loop {
+                cpu::hlt();
+            }
+        }
+
+        startup.arrived += 1;
+    }
+
+    // Limine's page tables don't map the heap, so switch before allocating
+    paging::init_ap();
+    gdt::init_ap();
+    idt::load();
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    percpu::init(id, cpu.lapic_id);
+
+    // Once its local APIC is enabled this CPU receives shootdowns, so it
+    // must be counted in the same step
+    {
+        let _guard = SHOOTDOWN_LOCK.lock();
+
+        apic::init_local();
+        ONLINE.fetch_add(1, Ordering::Release);
+    }
+
+    time::start_tick();
+
+    log::info!("CPU {id} online (APIC id {})", cpu.lapic_id);
+    STARTED.fetch_add(1, Ordering::Release);
+
+    task::init_ap()
+}
+
+/// Starts every application processor Limine reported and waits for them
+/// to come online. Needs interrupts, the heap, calibrated timers and the
+/// scheduler.
+pub fn init() {
+    paging::set_shootdown_hook(shootdown);
+    interrupts::set_handler(SHOOTDOWN_VECTOR, shootdown_interrupt);
+
+    log::info!("CPU 0 online (APIC id {}, bootstrap)", apic::id());
+
+    let Some(response) = boot::MP_REQUEST.get_response() else {
+        log::warn!("SMP: no MP response, running on the bootstrap processor only");
+        return;
+    };
+
+    let application_processors = response
+        .cpus()
+        .iter()
+        .filter(|cpu| cpu.lapic_id != response.bsp_lapic_id());
+
+    let mut expected = 0;
+    for cpu in application_processors {
+        // Counted before it starts, so it can't leave its boot stack first
+        task::add_boot_stacks(1);
+        cpu.goto_address.write(ap_entry);
+        expected += 1;
+    }
+
+    // Sleeping rather than spinning lets the processors that already
+    // started run threads meanwhile
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while STARTED.load(Ordering::Acquire) < expected && Instant::now() < deadline {
+        task::sleep(STARTUP_POLL_INTERVAL);
+    }
+
+    let started = STARTED.load(Ordering::Acquire);
+    if started < expected {
+        log::warn!("SMP: only {started} of {expected} application processors started");
+
+        // Those still starting up will leave their boot stacks; the ones
+        // that never arrived won't, so stop waiting for them
+        let missing = {
+            let mut startup = STARTUP.lock();
+            startup.abandoned = true;
+            expected - startup.arrived
+        };
+
+        if missing > 0 {
+            log::warn!("SMP: giving up on {missing} application processors that never ran");
+            task::remove_boot_stacks(missing);
+        }
+    }
+
+    log::info!("SMP: {} CPUs online", cpu_count());
+}
@@ -1,6 +1,9 @@
+use alloc::string::{String, ToString};
+
 use limine::BaseRevision;
 use limine::request::{
-    FramebufferRequest, MemoryMapRequest, RequestsEndMarker, RequestsStartMarker,
+    DateAtBootRequest, ExecutableAddressRequest, ExecutableCmdlineRequest, FramebufferRequest,
+    HhdmRequest, MemoryMapRequest, MpRequest, RequestsEndMarker, RequestsStartMarker, RsdpRequest,
 };
 
 #[unsafe(link_section = ".requests_start_marker")]
@@ -12,8 +15,39 @@ static BASE_REVISION: BaseRevision = BaseRevision::new();
 #[unsafe(link_section = ".requests")]
 pub static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
 
+#[unsafe(link_section = ".requests")]
+pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
+
+#[unsafe(link_section = ".requests")]
+pub static DATE_AT_BOOT_REQUEST: DateAtBootRequest = DateAtBootRequest::new();
+
+#[unsafe(link_section = ".requests")]
+pub static EXECUTABLE_ADDRESS_REQUEST: ExecutableAddressRequest = ExecutableAddressRequest::new();
+
+#[unsafe(link_section = ".requests")]
+pub static EXECUTABLE_CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
+
 #[unsafe(link_section = ".requests")]
 pub static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 
+#[unsafe(link_section = ".requests")]
+pub static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+
+#[unsafe(link_section = ".requests")]
+pub static MP_REQUEST: MpRequest = MpRequest::new();
+
 #[unsafe(link_section = ".requests_end_marker")]
 static REQUESTS_END_MARKER: RequestsEndMarker = RequestsEndMarker::new();
+
+/// Value of a `name=value` option on the kernel command line, which is set
+/// with `cmdline:` in limine.conf. Copied out, since the command line is in
+/// bootloader memory that is reclaimed after boot.
+pub fn option(name: &str) -> Option<String> {
+    let response = EXECUTABLE_CMDLINE_REQUEST.get_response()?;
+    let cmdline = response.cmdline().to_str().ok()?;
+
+    cmdline
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix(name)?.strip_prefix('='))
+        .map(ToString::to_string)
+}
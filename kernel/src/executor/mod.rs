@@ -0,0 +1,225 @@
+//! A cooperative executor for kernel futures.
+//!
+//! Every task is polled on one kernel thread. A woken task's id is pushed to
+//! a lock-free queue, so wakers are safe to call from interrupt handlers;
+//! when there is nothing to poll the thread blocks until a waker or the
+//! earliest timer wakes it.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use crossbeam_queue::ArrayQueue;
+use spin::Once;
+
+use crate::arch::x86_64::cpu;
+use crate::sync::SpinLock;
+use crate::task::{self, Thread};
+use crate::time::Instant;
+
+pub mod timer;
+
+// Wake-ups beyond this many pending ones are dropped with a warning
+const WAKE_QUEUE_CAPACITY: usize = 256;
+
+/// Identifies a task for as long as the kernel runs; ids are not reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A future the executor drives to completion.
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self {
+            id: TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            future: Box::pin(future),
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context<'_>) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+// Ids of tasks to poll
+static WOKEN: Once<ArrayQueue<TaskId>> = Once::new();
+
+// Tasks spawned since the executor last looked
+static SPAWNED: SpinLock<Vec<Task>> = SpinLock::new("executor spawn queue", Vec::new());
+
+// The executor thread while it is blocked waiting for work
+static SLEEPER: SpinLock<Option<Arc<Thread>>> = SpinLock::new("executor sleeper", None);
+
+fn woken() -> &'static ArrayQueue<TaskId> {
+    WOKEN.get().expect("executor not initialized")
+}
+
+// Called after queueing work, from any thread or interrupt handler
+fn notify() {
+    if let Some(thread) = SLEEPER.lock().take() {
+        task::wake(thread);
+    }
+}
+
+fn queue(id: TaskId) {
+    if woken().push(id).is_err() {
+        log::warn!("executor: wake queue full, dropped wake-up of task {id}");
+    }
+}
+
+fn has_work() -> bool {
+    !woken().is_empty() || !SPAWNED.lock().is_empty()
+}
+
+struct TaskWaker {
+    id: TaskId,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        queue(self.id);
+        notify();
+    }
+}
+
+/// Queues `future` to run on the executor.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> TaskId {
+    let task = Task::new(future);
+    let id = task.id;
+
+    SPAWNED.lock().push(task);
+    notify();
+
+    id
+}
+
+struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    // Each task's waker is made once and reused for every poll
+    wakers: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    fn adopt_spawned(&mut self) {
+        let spawned = core::mem::take(&mut *SPAWNED.lock());
+
+        for task in spawned {
+            // A new task is polled once to get it going
+            queue(task.id);
+            self.tasks.insert(task.id, task);
+        }
+    }
+
+    fn run_woken(&mut self) {
+        while let Some(id) = woken().pop() {
+            // A task may be woken again after it finished
+            let Some(task) = self.tasks.get_mut(&id) else {
+                continue;
+            };
+
+            let waker = self
+                .wakers
+                .entry(id)
+                .or_insert_with(|| Waker::from(Arc::new(TaskWaker { id })));
+
+            if task.poll(&mut Context::from_waker(waker)).is_ready() {
+                self.tasks.remove(&id);
+                self.wakers.remove(&id);
+            }
+        }
+    }
+
+    // Blocks the executor thread until there is something to do
+    fn wait_for_work(&self) {
+        cpu::without_interrupts(|| {
+            let deadline = timer::next_deadline();
+
+            task::block_until(deadline, |thread| {
+                *SLEEPER.lock() = Some(thread.clone());
+
+                // Work queued before the executor registered found no one
+                // to wake, so check for it now that it has
+                if has_work() || deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                    task::wake(thread);
+                }
+            });
+
+            SLEEPER.lock().take();
+        });
+    }
+
+    fn run(&mut self) -> ! {
+        loop {
+            timer::fire_expired();
+            self.adopt_spawned();
+            self.run_woken();
+
+            self.wait_for_work();
+        }
+    }
+}
+
+/// Starts the executor on a thread of its own. Needs the scheduler.
+pub fn init() {
+    WOKEN.call_once(|| ArrayQueue::new(WAKE_QUEUE_CAPACITY));
+
+    task::spawn("executor", || {
+        Executor {
+            tasks: BTreeMap::new(),
+            wakers: BTreeMap::new(),
+        }
+        .run()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicBool;
+    use core::time::Duration;
+
+    #[test_case]
+    fn spawned_future_runs_after_sleeping() {
+        static DONE: AtomicBool = AtomicBool::new(false);
+
+        let start = Instant::now();
+
+        spawn(async {
+            timer::sleep(Duration::from_millis(20)).await;
+            DONE.store(true, Ordering::SeqCst);
+        });
+
+        while !DONE.load(Ordering::SeqCst) {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "task didn't finish"
+            );
+            task::yield_now();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
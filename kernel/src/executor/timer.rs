@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use crate::sync::SpinLock;
+use crate::time::Instant;
+
+// Wakers of pending sleeps and when to call them. Only the executor thread
+// fires them, on its way back from waiting for the earliest one.
+static TIMERS: SpinLock<Vec<(Instant, Waker)>> = SpinLock::new("executor timers", Vec::new());
+
+/// Future returned by [`sleep`] and [`sleep_until`].
+pub struct Sleep {
+    deadline: Instant,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let mut timers = TIMERS.lock();
+        let waker = context.waker();
+
+        // Polled again before its deadline: keep one entry, with the
+        // latest waker
+        match timers.iter_mut().find(|(deadline, registered)| {
+            *deadline == self.deadline && registered.will_wake(waker)
+        }) {
+            Some((_, registered)) => registered.clone_from(waker),
+            None => timers.push((self.deadline, waker.clone())),
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Completes once `duration` has passed. The executor thread is woken for
+/// it on a timer tick, so the wait is rounded up to the next tick.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Completes once `deadline` has passed.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline }
+}
+
+// Earliest deadline of a pending sleep
+pub(super) fn next_deadline() -> Option<Instant> {
+    TIMERS.lock().iter().map(|&(deadline, _)| deadline).min()
+}
+
+// Wakes every sleep whose deadline has passed
+pub(super) fn fire_expired() {
+    let now = Instant::now();
+
+    let expired: Vec<Waker> = {
+        let mut timers = TIMERS.lock();
+        let mut expired = Vec::new();
+
+        timers.retain(|(deadline, waker)| {
+            if *deadline > now {
+                return true;
+            }
+
+            expired.push(waker.clone());
+            false
+        });
+
+        expired
+    };
+
+    // Woken outside the lock, since waking takes the executor's own
+    for waker in expired {
+        waker.wake();
+    }
+}
@@ -0,0 +1,504 @@
+//! Linear framebuffers and the RAM back buffer drawing goes through.
+//!
+//! Video memory is slow to write and very slow to read, so the console draws
+//! into a [`BackBuffer`] in RAM instead and copies the region that changed
+//! to the framebuffer in one pass when it is done. The back buffer is always
+//! XRGB; pixels are converted to each framebuffer's own format on the way.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::{fmt, ptr};
+
+use cosmic_text::Color;
+use limine::framebuffer::{MemoryModel, VideoMode};
+use spin::Once;
+
+use crate::boot;
+
+/// Where a color channel's bits are in a pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channel {
+    pub size: u8,
+    pub shift: u8,
+}
+
+impl Channel {
+    // Places an 8-bit channel value, dropping low bits or adding zero ones
+    // to fit
+    fn encode(self, value: u32) -> u32 {
+        let value = if self.size < 8 {
+            value >> (8 - self.size)
+        } else {
+            value << (self.size - 8)
+        };
+
+        value << self.shift
+    }
+}
+
+/// How a framebuffer stores its pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u16,
+    pub red: Channel,
+    pub green: Channel,
+    pub blue: Channel,
+}
+
+impl PixelFormat {
+    /// 32 bits per pixel with blue in the low byte, the back buffer's format.
+    pub const XRGB8888: Self = Self {
+        bits_per_pixel: 32,
+        red: Channel { size: 8, shift: 16 },
+        green: Channel { size: 8, shift: 8 },
+        blue: Channel { size: 8, shift: 0 },
+    };
+
+    fn bytes_per_pixel(self) -> usize {
+        usize::from(self.bits_per_pixel).div_ceil(8)
+    }
+
+    // Converts an XRGB pixel to this format
+    fn encode(self, pixel: u32) -> u32 {
+        self.red.encode(pixel >> 16 & 0xFF)
+            | self.green.encode(pixel >> 8 & 0xFF)
+            | self.blue.encode(pixel & 0xFF)
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bpp, red {}@{}, green {}@{}, blue {}@{}",
+            self.bits_per_pixel,
+            self.red.size,
+            self.red.shift,
+            self.green.size,
+            self.green.shift,
+            self.blue.size,
+            self.blue.shift
+        )
+    }
+}
+
+/// A linear framebuffer in video memory, mapped write-combining.
+pub struct Framebuffer {
+    pub addr: *mut u8,
+    pub pitch: u64,
+    pub width: u64,
+    pub height: u64,
+    pub format: PixelFormat,
+}
+
+unsafe impl Send for Framebuffer {}
+unsafe impl Sync for Framebuffer {}
+
+impl Framebuffer {
+    /// Wraps a framebuffer Limine set up, unless its pixels are in a format
+    /// that can't be drawn: one that isn't RGB or isn't 16, 24 or 32 bits.
+    pub fn from_limine(framebuffer: &limine::framebuffer::Framebuffer) -> Option<Self> {
+        let format = PixelFormat {
+            bits_per_pixel: framebuffer.bpp(),
+            red: Channel {
+                size: framebuffer.red_mask_size(),
+                shift: framebuffer.red_mask_shift(),
+            },
+            green: Channel {
+                size: framebuffer.green_mask_size(),
+                shift: framebuffer.green_mask_shift(),
+            },
+            blue: Channel {
+                size: framebuffer.blue_mask_size(),
+                shift: framebuffer.blue_mask_shift(),
+            },
+        };
+
+        if framebuffer.memory_model() != MemoryModel::RGB
+            || !matches!(format.bits_per_pixel, 16 | 24 | 32)
+        {
+            return None;
+        }
+
+        Some(Self {
+            addr: framebuffer.addr(),
+            pitch: framebuffer.pitch(),
+            width: framebuffer.width(),
+            height: framebuffer.height(),
+            format,
+        })
+    }
+
+    // Writes XRGB `pixels` to row `y` from column `x`
+    fn write_row(&self, x: usize, y: usize, pixels: &[u32]) {
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let row = unsafe { self.addr.add(y * self.pitch as usize + x * bytes_per_pixel) };
+
+        // Most framebuffers take the back buffer's pixels as they are
+        if self.format == PixelFormat::XRGB8888 {
+            unsafe { ptr::copy_nonoverlapping(pixels.as_ptr(), row.cast::<u32>(), pixels.len()) };
+            return;
+        }
+
+        for (index, &pixel) in pixels.iter().enumerate() {
+            let bytes = self.format.encode(pixel).to_le_bytes();
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    bytes.as_ptr(),
+                    row.add(index * bytes_per_pixel),
+                    bytes_per_pixel,
+                );
+            }
+        }
+    }
+}
+
+/// A framebuffer Limine reported, whether or not it can be drawn to.
+pub struct FramebufferInfo {
+    pub width: u64,
+    pub height: u64,
+    /// `None` when its pixels are in a format that can't be drawn.
+    pub format: Option<PixelFormat>,
+    /// Video modes the firmware offered for it.
+    pub modes: Vec<VideoMode>,
+}
+
+// Kept by `all`, since Limine's responses are reclaimed after boot
+static REPORTED: Once<Vec<FramebufferInfo>> = Once::new();
+
+/// Every framebuffer Limine set up that can be drawn to, in the order it
+/// reported them.
+pub fn all() -> Vec<Framebuffer> {
+    let Some(response) = boot::FRAMEBUFFER_REQUEST.get_response() else {
+        return Vec::new();
+    };
+
+    let mut supported = Vec::new();
+    let mut reported = Vec::new();
+
+    for (index, framebuffer) in response.framebuffers().enumerate() {
+        let width = framebuffer.width();
+        let height = framebuffer.height();
+        let drawable = Framebuffer::from_limine(&framebuffer);
+
+        match &drawable {
+            Some(drawable) => {
+                log::info!("Framebuffer {index}: {width}x{height}, {}", drawable.format);
+            }
+            None => log::warn!(
+                "Framebuffer {index}: {width}x{height}, unsupported format ({} bpp), skipped",
+                framebuffer.bpp()
+            ),
+        }
+
+        reported.push(FramebufferInfo {
+            width,
+            height,
+            format: drawable.as_ref().map(|drawable| drawable.format),
+            modes: framebuffer
+                .modes()
+                .unwrap_or_default()
+                .iter()
+                .map(|&&mode| mode)
+                .collect(),
+        });
+        supported.extend(drawable);
+    }
+
+    REPORTED.call_once(|| reported);
+
+    supported
+}
+
+/// Every framebuffer Limine reported, in its order. Empty until [`all`]
+/// has run.
+pub fn reported() -> &'static [FramebufferInfo] {
+    REPORTED.get().map_or(&[], Vec::as_slice)
+}
+
+/// How a back buffer is shown on several framebuffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrangement {
+    /// Each shows all of it, so it is as large as the smallest of them.
+    Mirror,
+    /// They show it side by side, left to right, so it is as wide as all of
+    /// them together and as tall as the shortest.
+    Span,
+}
+
+// A rectangle of pixels, from (left, top) up to but excluding (right, bottom)
+#[derive(Clone, Copy)]
+struct Region {
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+}
+
+impl Region {
+    fn union(self, other: Region) -> Region {
+        Region {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+}
+
+/// A picture in RAM, in XRGB format, shown on one or more framebuffers.
+/// Drawing only changes the picture; [`present`](Self::present) copies the
+/// smallest rectangle holding every change since the last one to them.
+pub struct BackBuffer {
+    // Each framebuffer and the column of the picture its left edge shows
+    outputs: Vec<(Framebuffer, usize)>,
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    dirty: Option<Region>,
+}
+
+impl BackBuffer {
+    /// Makes a black back buffer shown on `fronts`, arranged as asked. The
+    /// framebuffers are cleared on the first present.
+    pub fn new(fronts: Vec<Framebuffer>, arrangement: Arrangement) -> Self {
+        assert!(!fronts.is_empty(), "back buffer without a framebuffer");
+
+        let widths = fronts.iter().map(|front| front.width as usize);
+        let width = match arrangement {
+            Arrangement::Mirror => widths.min().unwrap(),
+            Arrangement::Span => widths.sum(),
+        };
+        let height = fronts
+            .iter()
+            .map(|front| front.height as usize)
+            .min()
+            .unwrap();
+
+        let mut left = 0;
+        let outputs = fronts
+            .into_iter()
+            .map(|front| {
+                let output_left = left;
+                if arrangement == Arrangement::Span {
+                    left += front.width as usize;
+                }
+                (front, output_left)
+            })
+            .collect();
+
+        Self {
+            outputs,
+            pixels: vec![0; width * height],
+            width,
+            height,
+            dirty: Some(Region {
+                left: 0,
+                top: 0,
+                right: width,
+                bottom: height,
+            }),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Records that a rectangle, already clipped, has to be presented
+    fn mark_dirty(&mut self, region: Region) {
+        if region.left >= region.right || region.top >= region.bottom {
+            return;
+        }
+
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(region),
+            None => region,
+        });
+    }
+
+    /// Fills a rectangle with an XRGB color, clipped to the buffer.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let right = (x + width).min(self.width);
+        let bottom = (y + height).min(self.height);
+        if x >= right || y >= bottom {
+            return;
+        }
+
+        for row in y..bottom {
+            self.pixels[row * self.width + x..row * self.width + right].fill(color);
+        }
+
+        self.mark_dirty(Region {
+            left: x,
+            top: y,
+            right,
+            bottom,
+        });
+    }
+
+    /// Draws one pixel of a glyph, blending it with what is already there.
+    /// Pixels outside the buffer are skipped.
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32 {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let pixel = &mut self.pixels[y * self.width + x];
+
+        if color.a() == 255 {
+            // Opaque glyph pixel
+            *pixel = color.0 & 0xFFFFFF;
+        } else if color.a() > 0 {
+            // Transparent glyph pixel, blend with background
+            let bg_b = (*pixel & 0x0000FF) as u8;
+            let bg_g = ((*pixel & 0x00FF00) >> 8) as u8;
+            let bg_r = ((*pixel & 0xFF0000) >> 16) as u8;
+
+            let alpha_norm = color.a() as f32 / 255.0;
+            let blend =
+                |fg: u8, bg: u8| (fg as f32 * alpha_norm + bg as f32 * (1.0 - alpha_norm)) as u8;
+
+            // Standard alpha blending: C_out = C_fg * A_fg + C_bg * (1 - A_fg)
+            let out_r = blend(color.r(), bg_r);
+            let out_g = blend(color.g(), bg_g);
+            let out_b = blend(color.b(), bg_b);
+
+            *pixel = ((out_r as u32) << 16) | ((out_g as u32) << 8) | (out_b as u32);
+        } else {
+            // Fully transparent, so nothing changes
+            return;
+        }
+
+        self.mark_dirty(Region {
+            left: x,
+            top: y,
+            right: x + 1,
+            bottom: y + 1,
+        });
+    }
+
+    /// Moves the rows above `bottom` up by `distance`, dropping the top ones.
+    /// The rows uncovered at the bottom keep their old pixels.
+    pub fn scroll_up(&mut self, distance: usize, bottom: usize) {
+        let bottom = bottom.min(self.height);
+        if distance >= bottom {
+            return;
+        }
+
+        self.pixels
+            .copy_within(distance * self.width..bottom * self.width, 0);
+
+        self.mark_dirty(Region {
+            left: 0,
+            top: 0,
+            right: self.width,
+            bottom: bottom - distance,
+        });
+    }
+
+    /// Copies what changed since the last present to the framebuffer.
+    pub fn present(&mut self) {
+        let Some(dirty) = self.dirty.take() else {
+            return;
+        };
+
+        for (front, left) in &self.outputs {
+            // The part of the change this framebuffer shows
+            let start = dirty.left.max(*left);
+            let end = dirty.right.min(left + front.width as usize);
+            if start >= end {
+                continue;
+            }
+
+            for row in dirty.top..dirty.bottom {
+                let offset = row * self.width;
+                front.write_row(
+                    start - left,
+                    row,
+                    &self.pixels[offset + start..offset + end],
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn present_copies_only_what_changed() {
+        let mut memory = vec![0u32; 4 * 3];
+        let front = Framebuffer {
+            addr: memory.as_mut_ptr().cast(),
+            pitch: 4 * 4,
+            width: 4,
+            height: 3,
+            format: PixelFormat::XRGB8888,
+        };
+        let mut buffer = BackBuffer::new(vec![front], Arrangement::Mirror);
+        buffer.present();
+
+        // Changed behind the back buffer's back, so only presented regions
+        // are overwritten
+        memory.fill(0xAA);
+        buffer.fill_rect(1, 1, 2, 5, 0xFFFFFF);
+        buffer.present();
+        assert_eq!(
+            memory,
+            [
+                0xAA, 0xAA, 0xAA, 0xAA, //
+                0xAA, 0xFFFFFF, 0xFFFFFF, 0xAA, //
+                0xAA, 0xFFFFFF, 0xFFFFFF, 0xAA,
+            ]
+        );
+
+        buffer.scroll_up(1, 3);
+        buffer.present();
+        assert_eq!(
+            memory[..8],
+            [0, 0xFFFFFF, 0xFFFFFF, 0, 0, 0xFFFFFF, 0xFFFFFF, 0]
+        );
+        assert_eq!(memory[8..], [0xAA, 0xFFFFFF, 0xFFFFFF, 0xAA]);
+    }
+
+    #[test_case]
+    fn spans_framebuffers_of_different_formats() {
+        let mut left = vec![0u32; 2];
+        let mut right = vec![0u16; 2];
+        let rgb565 = PixelFormat {
+            bits_per_pixel: 16,
+            red: Channel { size: 5, shift: 11 },
+            green: Channel { size: 6, shift: 5 },
+            blue: Channel { size: 5, shift: 0 },
+        };
+        let fronts = vec![
+            Framebuffer {
+                addr: left.as_mut_ptr().cast(),
+                pitch: 2 * 4,
+                width: 2,
+                height: 1,
+                format: PixelFormat::XRGB8888,
+            },
+            Framebuffer {
+                addr: right.as_mut_ptr().cast(),
+                pitch: 2 * 2,
+                width: 2,
+                height: 1,
+                format: rgb565,
+            },
+        ];
+        let mut buffer = BackBuffer::new(fronts, Arrangement::Span);
+        assert_eq!(buffer.width(), 4);
+
+        buffer.fill_rect(1, 0, 2, 1, 0xFF8000);
+        buffer.present();
+        assert_eq!(left, [0, 0xFF8000]);
+        assert_eq!(right, [0xFC00, 0]);
+    }
+}
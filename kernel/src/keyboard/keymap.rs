@@ -0,0 +1,167 @@
+use super::{KeyCode, Modifiers};
+use crate::sync::SpinLock;
+
+/// Characters the keys of a layout type, without and with shift. Keys
+/// missing from the table type nothing.
+pub struct Keymap {
+    pub name: &'static str,
+    pub keys: &'static [(KeyCode, char, char)],
+}
+
+impl Keymap {
+    /// Returns the character `key` types with `modifiers` held, if any.
+    pub fn translate(&self, key: KeyCode, modifiers: Modifiers) -> Option<char> {
+        if let Some(character) = layout_independent(key, modifiers) {
+            return Some(character);
+        }
+
+        let &(_, normal, shifted) = self.keys.iter().find(|(code, _, _)| *code == key)?;
+
+        // Caps lock only affects letters, and shift undoes it
+        let shift = if normal.is_alphabetic() {
+            modifiers.shift() != modifiers.caps_lock
+        } else {
+            modifiers.shift()
+        };
+
+        let character = if shift { shifted } else { normal };
+
+        // Ctrl with a letter types the matching control character
+        if modifiers.ctrl() && character.is_ascii_alphabetic() {
+            return Some((character.to_ascii_uppercase() as u8 - b'@') as char);
+        }
+
+        Some(character)
+    }
+}
+
+// Keys that type the same character on every layout
+fn layout_independent(key: KeyCode, modifiers: Modifiers) -> Option<char> {
+    use KeyCode::*;
+
+    let digit = |digit| modifiers.num_lock.then_some(digit);
+
+    match key {
+        Enter | KeypadEnter => Some('\n'),
+        Tab => Some('\t'),
+        Backspace => Some('\x08'),
+        Escape => Some('\x1B'),
+        Space => Some(' '),
+        KeypadDivide => Some('/'),
+        KeypadMultiply => Some('*'),
+        KeypadMinus => Some('-'),
+        KeypadPlus => Some('+'),
+        // Without num lock the keypad moves the cursor instead
+        KeypadPeriod => digit('.'),
+        Keypad0 => digit('0'),
+        Keypad1 => digit('1'),
+        Keypad2 => digit('2'),
+        Keypad3 => digit('3'),
+        Keypad4 => digit('4'),
+        Keypad5 => digit('5'),
+        Keypad6 => digit('6'),
+        Keypad7 => digit('7'),
+        Keypad8 => digit('8'),
+        Keypad9 => digit('9'),
+        _ => None,
+    }
+}
+
+pub static US_QWERTY: Keymap = Keymap {
+    name: "us",
+    keys: &[
+        (KeyCode::Backtick, '`', '~'),
+        (KeyCode::Digit1, '1', '!'),
+        (KeyCode::Digit2, '2', '@'),
+        (KeyCode::Digit3, '3', '#'),
+        (KeyCode::Digit4, '4', '$'),
+        (KeyCode::Digit5, '5', '%'),
+        (KeyCode::Digit6, '6', '^'),
+        (KeyCode::Digit7, '7', '&'),
+        (KeyCode::Digit8, '8', '*'),
+        (KeyCode::Digit9, '9', '('),
+        (KeyCode::Digit0, '0', ')'),
+        (KeyCode::Minus, '-', '_'),
+        (KeyCode::Equals, '=', '+'),
+        (KeyCode::Q, 'q', 'Q'),
+        (KeyCode::W, 'w', 'W'),
+        (KeyCode::E, 'e', 'E'),
+        (KeyCode::R, 'r', 'R'),
+        (KeyCode::T, 't', 'T'),
+        (KeyCode::Y, 'y', 'Y'),
+        (KeyCode::U, 'u', 'U'),
+        (KeyCode::I, 'i', 'I'),
+        (KeyCode::O, 'o', 'O'),
+        (KeyCode::P, 'p', 'P'),
+        (KeyCode::LeftBracket, '[', '{'),
+        (KeyCode::RightBracket, ']', '}'),
+        (KeyCode::Backslash, '\\', '|'),
+        (KeyCode::A, 'a', 'A'),
+        (KeyCode::S, 's', 'S'),
+        (KeyCode::D, 'd', 'D'),
+        (KeyCode::F, 'f', 'F'),
+        (KeyCode::G, 'g', 'G'),
+        (KeyCode::H, 'h', 'H'),
+        (KeyCode::J, 'j', 'J'),
+        (KeyCode::K, 'k', 'K'),
+        (KeyCode::L, 'l', 'L'),
+        (KeyCode::Semicolon, ';', ':'),
+        (KeyCode::Quote, '\'', '"'),
+        (KeyCode::Z, 'z', 'Z'),
+        (KeyCode::X, 'x', 'X'),
+        (KeyCode::C, 'c', 'C'),
+        (KeyCode::V, 'v', 'V'),
+        (KeyCode::B, 'b', 'B'),
+        (KeyCode::N, 'n', 'N'),
+        (KeyCode::M, 'm', 'M'),
+        (KeyCode::Comma, ',', '<'),
+        (KeyCode::Period, '.', '>'),
+        (KeyCode::Slash, '/', '?'),
+        (KeyCode::NonUsBackslash, '\\', '|'),
+    ],
+};
+
+/// Every layout [`set_keymap`] can be given by name.
+pub static KEYMAPS: &[&Keymap] = &[&US_QWERTY];
+
+static CURRENT: SpinLock<&'static Keymap> = SpinLock::new("keymap", &US_QWERTY);
+
+/// The layout key events are translated with.
+pub fn current() -> &'static Keymap {
+    *CURRENT.lock()
+}
+
+/// Switches to the layout named `name`. Returns `false` if there is none.
+pub fn set_keymap(name: &str) -> bool {
+    match KEYMAPS.iter().find(|keymap| keymap.name == name) {
+        Some(keymap) => {
+            *CURRENT.lock() = keymap;
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn shift_and_caps_lock_select_characters() {
+        let shift = Modifiers {
+            left_shift: true,
+            ..Modifiers::NONE
+        };
+        let caps_lock = Modifiers {
+            caps_lock: true,
+            ..Modifiers::NONE
+        };
+
+        assert_eq!(US_QWERTY.translate(KeyCode::A, Modifiers::NONE), Some('a'));
+        assert_eq!(US_QWERTY.translate(KeyCode::A, shift), Some('A'));
+        assert_eq!(US_QWERTY.translate(KeyCode::A, caps_lock), Some('A'));
+        assert_eq!(US_QWERTY.translate(KeyCode::Digit1, caps_lock), Some('1'));
+        assert_eq!(US_QWERTY.translate(KeyCode::Digit1, shift), Some('!'));
+        assert_eq!(US_QWERTY.translate(KeyCode::F1, shift), None);
+    }
+}
@@ -0,0 +1,394 @@
+//! PS/2 keyboard input.
+//!
+//! The interrupt handler decodes scancodes into key events, tracks the
+//! modifier and lock keys, translates keys to characters with the current
+//! keymap and queues the events for async tasks to read with
+//! [`next_event`].
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use crossbeam_queue::ArrayQueue;
+use spin::Once;
+
+use crate::acpi::fadt;
+use crate::arch::x86_64::interrupts::{self, InterruptFrame};
+use crate::println;
+use crate::shell::{self, Command};
+use crate::sync::SpinLock;
+
+pub mod keymap;
+mod ps2;
+mod scancode;
+
+pub use scancode::ScancodeSet;
+
+// Legacy ISA IRQ of the first PS/2 port
+const KEYBOARD_IRQ: u8 = 1;
+
+// Events beyond this many unread ones are dropped
+const EVENT_QUEUE_CAPACITY: usize = 128;
+
+/// A physical key, named after what it types on a US layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+    Backtick,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Digit0,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    CapsLock,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Enter,
+    LeftShift,
+    /// The extra key next to left shift on ISO keyboards.
+    NonUsBackslash,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftCtrl,
+    LeftGui,
+    LeftAlt,
+    Space,
+    RightAlt,
+    RightGui,
+    Menu,
+    RightCtrl,
+    Insert,
+    Home,
+    PageUp,
+    Delete,
+    End,
+    PageDown,
+    Up,
+    Left,
+    Down,
+    Right,
+    NumLock,
+    KeypadDivide,
+    KeypadMultiply,
+    KeypadMinus,
+    KeypadPlus,
+    KeypadEnter,
+    KeypadPeriod,
+    Keypad0,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+}
+
+/// Modifier keys held and lock keys toggled on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    pub right_alt: bool,
+    pub left_gui: bool,
+    pub right_gui: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Self = Self {
+        left_shift: false,
+        right_shift: false,
+        left_ctrl: false,
+        right_ctrl: false,
+        left_alt: false,
+        right_alt: false,
+        left_gui: false,
+        right_gui: false,
+        caps_lock: false,
+        num_lock: false,
+        scroll_lock: false,
+    };
+
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    // Records a press or release, returning whether a lock key toggled.
+    // Repeats of a held lock key must be filtered out first
+    fn update(&mut self, key: KeyCode, pressed: bool) -> bool {
+        let held = match key {
+            KeyCode::LeftShift => &mut self.left_shift,
+            KeyCode::RightShift => &mut self.right_shift,
+            KeyCode::LeftCtrl => &mut self.left_ctrl,
+            KeyCode::RightCtrl => &mut self.right_ctrl,
+            KeyCode::LeftAlt => &mut self.left_alt,
+            KeyCode::RightAlt => &mut self.right_alt,
+            KeyCode::LeftGui => &mut self.left_gui,
+            KeyCode::RightGui => &mut self.right_gui,
+            _ => {
+                let lock = match key {
+                    KeyCode::CapsLock => &mut self.caps_lock,
+                    KeyCode::NumLock => &mut self.num_lock,
+                    KeyCode::ScrollLock => &mut self.scroll_lock,
+                    _ => return false,
+                };
+
+                // Lock keys toggle on the press
+                if pressed {
+                    *lock = !*lock;
+                }
+                return pressed;
+            }
+        };
+
+        *held = pressed;
+        false
+    }
+}
+
+/// A key press or release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: KeyCode,
+    /// `false` for a release. Keys held down repeat their press.
+    pub pressed: bool,
+    /// Modifiers in effect for this event, including its own key.
+    pub modifiers: Modifiers,
+    /// What the key types with the current keymap, for presses only.
+    pub character: Option<char>,
+}
+
+struct State {
+    decoder: scancode::Decoder,
+    modifiers: Modifiers,
+    // Lock keys held down, so their repeats don't toggle them again
+    locks_held: [bool; 3],
+}
+
+static STATE: SpinLock<State> = SpinLock::new(
+    "keyboard state",
+    State {
+        decoder: scancode::Decoder::new(ScancodeSet::Set1),
+        modifiers: Modifiers::NONE,
+        locks_held: [false; 3],
+    },
+);
+
+static EVENTS: Once<ArrayQueue<KeyEvent>> = Once::new();
+
+// Task waiting in `next_event`
+static WAKER: SpinLock<Option<Waker>> = SpinLock::new("keyboard waker", None);
+
+impl State {
+    fn handle(&mut self, byte: u8) -> Option<KeyEvent> {
+        let (key, pressed) = self.decoder.feed(byte)?;
+
+        let lock_index = match key {
+            KeyCode::CapsLock => Some(0),
+            KeyCode::NumLock => Some(1),
+            KeyCode::ScrollLock => Some(2),
+            _ => None,
+        };
+
+        let repeat = lock_index.is_some_and(|index| pressed && self.locks_held[index]);
+        if let Some(index) = lock_index {
+            self.locks_held[index] = pressed;
+        }
+
+        if !repeat && self.modifiers.update(key, pressed) {
+            let modifiers = self.modifiers;
+            ps2::set_leds(
+                modifiers.scroll_lock,
+                modifiers.num_lock,
+                modifiers.caps_lock,
+            );
+        }
+
+        Some(KeyEvent {
+            key,
+            pressed,
+            modifiers: self.modifiers,
+            character: pressed
+                .then(|| keymap::current().translate(key, self.modifiers))
+                .flatten(),
+        })
+    }
+}
+
+fn keyboard_interrupt(_frame: &mut InterruptFrame) {
+    // The byte must be read for the controller to raise the next interrupt
+    let byte = ps2::read_data();
+
+    if ps2::is_reply(byte) {
+        return;
+    }
+
+    let Some(event) = STATE.lock().handle(byte) else {
+        return;
+    };
+
+    let Some(events) = EVENTS.get() else {
+        return;
+    };
+
+    if events.push(event).is_err() {
+        crate::serial_println!("keyboard: event queue full, dropping input");
+        return;
+    }
+
+    if let Some(waker) = WAKER.lock().take() {
+        waker.wake();
+    }
+}
+
+/// Returns the next queued event without waiting.
+pub fn try_read_event() -> Option<KeyEvent> {
+    EVENTS.get()?.pop()
+}
+
+/// Future returned by [`next_event`].
+pub struct NextEvent {
+    _private: (),
+}
+
+impl Future for NextEvent {
+    type Output = KeyEvent;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<KeyEvent> {
+        if let Some(event) = try_read_event() {
+            return Poll::Ready(event);
+        }
+
+        // Registered before checking again, so an event arriving in between
+        // still wakes the task
+        *WAKER.lock() = Some(context.waker().clone());
+
+        match try_read_event() {
+            Some(event) => {
+                WAKER.lock().take();
+                Poll::Ready(event)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Completes with the next key event. Only one task should wait for events
+/// at a time.
+pub fn next_event() -> NextEvent {
+    NextEvent { _private: () }
+}
+
+fn keymap_command(args: &[&str]) {
+    match args {
+        [] => {
+            println!("current: {}", keymap::current().name);
+            for keymap in keymap::KEYMAPS {
+                println!("  {}", keymap.name);
+            }
+        }
+        [name] => {
+            if !keymap::set_keymap(name) {
+                println!("keymap: no keymap named {name}");
+            }
+        }
+        _ => println!("usage: keymap [name]"),
+    }
+}
+
+/// Initializes the PS/2 controller and keyboard and starts queueing key
+/// events from IRQ 1, unless the FADT says there is no PS/2 controller.
+/// Needs interrupts, timekeeping and ACPI.
+pub fn init() {
+    if fadt::get().is_some_and(|fadt| !fadt.has_8042) {
+        log::info!("Keyboard: no PS/2 controller");
+        return;
+    }
+
+    let set = match ps2::init() {
+        Ok(set) => set,
+        Err(error) => {
+            log::warn!("Keyboard: PS/2 initialization failed: {error}");
+            return;
+        }
+    };
+
+    STATE.lock().decoder = scancode::Decoder::new(set);
+    EVENTS.call_once(|| ArrayQueue::new(EVENT_QUEUE_CAPACITY));
+    interrupts::register_irq(KEYBOARD_IRQ, keyboard_interrupt);
+
+    shell::register(Command {
+        name: "keymap",
+        help: "show the keyboard layouts or switch to one",
+        run: keymap_command,
+    });
+
+    log::info!("Keyboard: PS/2, scancode {set:?}");
+}
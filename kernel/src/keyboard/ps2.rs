@@ -0,0 +1,222 @@
+use core::fmt;
+use core::time::Duration;
+
+use super::scancode::ScancodeSet;
+use crate::arch::x86_64::port::{inb, outb};
+use crate::time::Instant;
+
+// Bytes to and from the controller's devices
+const DATA: u16 = 0x60;
+// Reads give the status register, writes are controller commands
+const STATUS: u16 = 0x64;
+const COMMAND: u16 = 0x64;
+
+// Status register bits
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+// Controller commands
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_PORT2: u8 = 0xA7;
+const COMMAND_SELF_TEST: u8 = 0xAA;
+const COMMAND_TEST_PORT1: u8 = 0xAB;
+const COMMAND_DISABLE_PORT1: u8 = 0xAD;
+const COMMAND_ENABLE_PORT1: u8 = 0xAE;
+
+// Controller configuration byte bits
+const CONFIG_PORT1_INTERRUPT: u8 = 1 << 0;
+const CONFIG_PORT2_INTERRUPT: u8 = 1 << 1;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// Keyboard commands and replies
+const KEYBOARD_SET_LEDS: u8 = 0xED;
+const KEYBOARD_SCANCODE_SET: u8 = 0xF0;
+const KEYBOARD_ENABLE_SCANNING: u8 = 0xF4;
+const KEYBOARD_RESET: u8 = 0xFF;
+const KEYBOARD_ACK: u8 = 0xFA;
+const KEYBOARD_RESEND: u8 = 0xFE;
+const KEYBOARD_SELF_TEST_PASSED: u8 = 0xAA;
+
+// How long the controller and keyboard get to answer. A reset runs the
+// keyboard's self test, which takes much longer than anything else.
+const TIMEOUT: Duration = Duration::from_millis(50);
+const RESET_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The controller or keyboard didn't answer in time.
+    Timeout,
+    /// The controller's self test returned this instead of passing.
+    SelfTestFailed(u8),
+    /// The first port's interface test returned this error code.
+    PortTestFailed(u8),
+    /// The keyboard answered a command with this instead of acknowledging.
+    NotAcknowledged(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "timed out"),
+            Error::SelfTestFailed(reply) => write!(f, "controller self test failed ({reply:#04x})"),
+            Error::PortTestFailed(reply) => write!(f, "port test failed ({reply:#04x})"),
+            Error::NotAcknowledged(reply) => {
+                write!(f, "keyboard didn't acknowledge ({reply:#04x})")
+            }
+        }
+    }
+}
+
+fn status() -> u8 {
+    unsafe { inb(STATUS) }
+}
+
+fn wait_until(timeout: Duration, ready: impl Fn() -> bool) -> Result<(), Error> {
+    let deadline = Instant::now() + timeout;
+
+    while !ready() {
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+fn write_command(command: u8) -> Result<(), Error> {
+    wait_until(TIMEOUT, || status() & STATUS_INPUT_FULL == 0)?;
+    unsafe { outb(COMMAND, command) };
+
+    Ok(())
+}
+
+fn write_data(byte: u8) -> Result<(), Error> {
+    wait_until(TIMEOUT, || status() & STATUS_INPUT_FULL == 0)?;
+    unsafe { outb(DATA, byte) };
+
+    Ok(())
+}
+
+fn read_data_within(timeout: Duration) -> Result<u8, Error> {
+    wait_until(timeout, || status() & STATUS_OUTPUT_FULL != 0)?;
+
+    Ok(read_data())
+}
+
+/// Reads the byte the controller holds. Used by the interrupt handler,
+/// which only runs once there is one.
+pub fn read_data() -> u8 {
+    unsafe { inb(DATA) }
+}
+
+fn read_config() -> Result<u8, Error> {
+    write_command(COMMAND_READ_CONFIG)?;
+    read_data_within(TIMEOUT)
+}
+
+fn write_config(config: u8) -> Result<(), Error> {
+    write_command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+// Sends a command byte to the keyboard, resending it while asked to
+fn send_keyboard(byte: u8) -> Result<(), Error> {
+    for _ in 0..3 {
+        write_data(byte)?;
+
+        match read_data_within(TIMEOUT)? {
+            KEYBOARD_ACK => return Ok(()),
+            KEYBOARD_RESEND => continue,
+            reply => return Err(Error::NotAcknowledged(reply)),
+        }
+    }
+
+    Err(Error::NotAcknowledged(KEYBOARD_RESEND))
+}
+
+// Finds the set the keyboard's bytes reach us in, switching an untranslated
+// keyboard that uses set 3 to set 2
+fn scancode_set(config: u8) -> Result<ScancodeSet, Error> {
+    if config & CONFIG_TRANSLATION != 0 {
+        return Ok(ScancodeSet::Set1);
+    }
+
+    send_keyboard(KEYBOARD_SCANCODE_SET)?;
+    send_keyboard(0)?;
+
+    match read_data_within(TIMEOUT)? {
+        1 => Ok(ScancodeSet::Set1),
+        2 => Ok(ScancodeSet::Set2),
+        _ => {
+            send_keyboard(KEYBOARD_SCANCODE_SET)?;
+            send_keyboard(2)?;
+            Ok(ScancodeSet::Set2)
+        }
+    }
+}
+
+/// Resets and tests the controller and the keyboard on its first port,
+/// then enables the port's interrupt. The mouse port, if any, is left
+/// disabled. Returns the scancode set keys will arrive in.
+pub fn init() -> Result<ScancodeSet, Error> {
+    write_command(COMMAND_DISABLE_PORT1)?;
+    write_command(COMMAND_DISABLE_PORT2)?;
+
+    // Drop whatever the firmware left unread
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        read_data();
+    }
+
+    let config = read_config()? & !(CONFIG_PORT1_INTERRUPT | CONFIG_PORT2_INTERRUPT);
+    write_config(config)?;
+
+    write_command(COMMAND_SELF_TEST)?;
+    match read_data_within(TIMEOUT)? {
+        SELF_TEST_PASSED => {}
+        reply => return Err(Error::SelfTestFailed(reply)),
+    }
+
+    // Some controllers reset their configuration during the self test
+    write_config(config)?;
+
+    write_command(COMMAND_TEST_PORT1)?;
+    match read_data_within(TIMEOUT)? {
+        PORT_TEST_PASSED => {}
+        reply => return Err(Error::PortTestFailed(reply)),
+    }
+
+    write_command(COMMAND_ENABLE_PORT1)?;
+
+    send_keyboard(KEYBOARD_RESET)?;
+    match read_data_within(RESET_TIMEOUT)? {
+        KEYBOARD_SELF_TEST_PASSED => {}
+        reply => return Err(Error::NotAcknowledged(reply)),
+    }
+
+    let set = scancode_set(config)?;
+
+    send_keyboard(KEYBOARD_ENABLE_SCANNING)?;
+    write_config(config | CONFIG_PORT1_INTERRUPT)?;
+
+    Ok(set)
+}
+
+/// Returns whether `byte` is the keyboard answering a command rather than
+/// part of a scancode.
+pub fn is_reply(byte: u8) -> bool {
+    matches!(byte, KEYBOARD_ACK | KEYBOARD_RESEND)
+}
+
+/// Sets the keyboard's lock LEDs. The keyboard's acknowledgements arrive
+/// through the interrupt handler, which drops them.
+pub fn set_leds(scroll_lock: bool, num_lock: bool, caps_lock: bool) {
+    let leds = scroll_lock as u8 | (num_lock as u8) << 1 | (caps_lock as u8) << 2;
+
+    // Losing an LED update isn't worth reporting
+    let _ = write_data(KEYBOARD_SET_LEDS).and_then(|()| write_data(leds));
+}
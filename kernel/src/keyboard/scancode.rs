@@ -0,0 +1,388 @@
+use super::KeyCode;
+
+// Prefix of keys added after the original XT keyboard
+const EXTENDED: u8 = 0xE0;
+// Prefix of the Pause key's sequence, which has no release
+const PAUSE: u8 = 0xE1;
+// Set 2 prefix of a release; set 1 sets the top bit instead
+const SET2_RELEASE: u8 = 0xF0;
+
+// Bytes left in a Pause sequence after its prefix
+const SET1_PAUSE_LENGTH: u8 = 5;
+const SET2_PAUSE_LENGTH: u8 = 7;
+
+/// The encoding a keyboard sends keys in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// The original XT set, which the controller translates set 2 into by
+    /// default.
+    Set1,
+    /// The AT set every keyboard sends natively.
+    Set2,
+}
+
+/// Turns scancode bytes into key presses and releases.
+pub struct Decoder {
+    set: ScancodeSet,
+    extended: bool,
+    release: bool,
+    // Bytes of a Pause sequence still to be skipped
+    pause_remaining: u8,
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self {
+            set,
+            extended: false,
+            release: false,
+            pause_remaining: 0,
+        }
+    }
+
+    /// Feeds the next byte, returning the key and whether it was pressed
+    /// once a sequence is complete. Bytes of unknown keys are dropped.
+    pub fn feed(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        if self.pause_remaining > 0 {
+            self.pause_remaining -= 1;
+            return None;
+        }
+
+        match byte {
+            EXTENDED => {
+                self.extended = true;
+                return None;
+            }
+            PAUSE => {
+                self.pause_remaining = match self.set {
+                    ScancodeSet::Set1 => SET1_PAUSE_LENGTH,
+                    ScancodeSet::Set2 => SET2_PAUSE_LENGTH,
+                };
+                return Some((KeyCode::Pause, true));
+            }
+            SET2_RELEASE if self.set == ScancodeSet::Set2 => {
+                self.release = true;
+                return None;
+            }
+            _ => {}
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+
+        let (code, pressed) = match self.set {
+            ScancodeSet::Set1 => {
+                let code = if extended {
+                    set1_extended(byte & 0x7F)
+                } else {
+                    set1(byte & 0x7F)
+                };
+
+                (code, byte & 0x80 == 0)
+            }
+            ScancodeSet::Set2 => {
+                let release = core::mem::take(&mut self.release);
+                let code = if extended {
+                    set2_extended(byte)
+                } else {
+                    set2(byte)
+                };
+
+                (code, !release)
+            }
+        };
+
+        code.map(|code| (code, pressed))
+    }
+}
+
+fn set1(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Digit1,
+        0x03 => Digit2,
+        0x04 => Digit3,
+        0x05 => Digit4,
+        0x06 => Digit5,
+        0x07 => Digit6,
+        0x08 => Digit7,
+        0x09 => Digit8,
+        0x0A => Digit9,
+        0x0B => Digit0,
+        0x0C => Minus,
+        0x0D => Equals,
+        0x0E => Backspace,
+        0x0F => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1A => LeftBracket,
+        0x1B => RightBracket,
+        0x1C => Enter,
+        0x1D => LeftCtrl,
+        0x1E => A,
+        0x1F => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => Semicolon,
+        0x28 => Quote,
+        0x29 => Backtick,
+        0x2A => LeftShift,
+        0x2B => Backslash,
+        0x2C => Z,
+        0x2D => X,
+        0x2E => C,
+        0x2F => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Period,
+        0x35 => Slash,
+        0x36 => RightShift,
+        0x37 => KeypadMultiply,
+        0x38 => LeftAlt,
+        0x39 => Space,
+        0x3A => CapsLock,
+        0x3B => F1,
+        0x3C => F2,
+        0x3D => F3,
+        0x3E => F4,
+        0x3F => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Keypad7,
+        0x48 => Keypad8,
+        0x49 => Keypad9,
+        0x4A => KeypadMinus,
+        0x4B => Keypad4,
+        0x4C => Keypad5,
+        0x4D => Keypad6,
+        0x4E => KeypadPlus,
+        0x4F => Keypad1,
+        0x50 => Keypad2,
+        0x51 => Keypad3,
+        0x52 => Keypad0,
+        0x53 => KeypadPeriod,
+        0x56 => NonUsBackslash,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    })
+}
+
+// Codes after an 0xE0 prefix. The fake shifts some keys send along with
+// their code (0x2A and 0x36) map to nothing.
+fn set1_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x1C => KeypadEnter,
+        0x1D => RightCtrl,
+        0x35 => KeypadDivide,
+        0x37 => PrintScreen,
+        0x38 => RightAlt,
+        0x47 => Home,
+        0x48 => Up,
+        0x49 => PageUp,
+        0x4B => Left,
+        0x4D => Right,
+        0x4F => End,
+        0x50 => Down,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5B => LeftGui,
+        0x5C => RightGui,
+        0x5D => Menu,
+        _ => return None,
+    })
+}
+
+fn set2(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0A => F8,
+        0x0B => F6,
+        0x0C => F4,
+        0x0D => Tab,
+        0x0E => Backtick,
+        0x11 => LeftAlt,
+        0x12 => LeftShift,
+        0x14 => LeftCtrl,
+        0x15 => Q,
+        0x16 => Digit1,
+        0x1A => Z,
+        0x1B => S,
+        0x1C => A,
+        0x1D => W,
+        0x1E => Digit2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Digit4,
+        0x26 => Digit3,
+        0x29 => Space,
+        0x2A => V,
+        0x2B => F,
+        0x2C => T,
+        0x2D => R,
+        0x2E => Digit5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Digit6,
+        0x3A => M,
+        0x3B => J,
+        0x3C => U,
+        0x3D => Digit7,
+        0x3E => Digit8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Digit0,
+        0x46 => Digit9,
+        0x49 => Period,
+        0x4A => Slash,
+        0x4B => L,
+        0x4C => Semicolon,
+        0x4D => P,
+        0x4E => Minus,
+        0x52 => Quote,
+        0x54 => LeftBracket,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => RightShift,
+        0x5A => Enter,
+        0x5B => RightBracket,
+        0x5D => Backslash,
+        0x61 => NonUsBackslash,
+        0x66 => Backspace,
+        0x69 => Keypad1,
+        0x6B => Keypad4,
+        0x6C => Keypad7,
+        0x70 => Keypad0,
+        0x71 => KeypadPeriod,
+        0x72 => Keypad2,
+        0x73 => Keypad5,
+        0x74 => Keypad6,
+        0x75 => Keypad8,
+        0x76 => Escape,
+        0x77 => NumLock,
+        0x78 => F11,
+        0x79 => KeypadPlus,
+        0x7A => Keypad3,
+        0x7B => KeypadMinus,
+        0x7C => KeypadMultiply,
+        0x7D => Keypad9,
+        0x7E => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    })
+}
+
+// Codes after an 0xE0 prefix. The fake shifts some keys send along with
+// their code (0x12 and 0x59) map to nothing.
+fn set2_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x11 => RightAlt,
+        0x14 => RightCtrl,
+        0x1F => LeftGui,
+        0x27 => RightGui,
+        0x2F => Menu,
+        0x4A => KeypadDivide,
+        0x5A => KeypadEnter,
+        0x69 => End,
+        0x6B => Left,
+        0x6C => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => Down,
+        0x74 => Right,
+        0x75 => Up,
+        0x7A => PageDown,
+        0x7C => PrintScreen,
+        0x7D => PageUp,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(set: ScancodeSet, bytes: &[u8]) -> Option<(KeyCode, bool)> {
+        let mut decoder = Decoder::new(set);
+        let (last, prefix) = bytes.split_last().unwrap();
+
+        for &byte in prefix {
+            assert_eq!(decoder.feed(byte), None);
+        }
+
+        decoder.feed(*last)
+    }
+
+    #[test_case]
+    fn both_sets_decode_presses_and_releases() {
+        use ScancodeSet::*;
+
+        assert_eq!(decode(Set1, &[0x1E]), Some((KeyCode::A, true)));
+        assert_eq!(decode(Set1, &[0x9E]), Some((KeyCode::A, false)));
+        assert_eq!(decode(Set1, &[0xE0, 0x48]), Some((KeyCode::Up, true)));
+        assert_eq!(decode(Set1, &[0xE0, 0xC8]), Some((KeyCode::Up, false)));
+
+        assert_eq!(decode(Set2, &[0x1C]), Some((KeyCode::A, true)));
+        assert_eq!(decode(Set2, &[0xF0, 0x1C]), Some((KeyCode::A, false)));
+        assert_eq!(decode(Set2, &[0xE0, 0x75]), Some((KeyCode::Up, true)));
+        assert_eq!(
+            decode(Set2, &[0xE0, 0xF0, 0x75]),
+            Some((KeyCode::Up, false))
+        );
+    }
+
+    #[test_case]
+    fn pause_sequence_is_one_press() {
+        let mut decoder = Decoder::new(ScancodeSet::Set2);
+        let sequence = [0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77];
+
+        let events: usize = sequence
+            .iter()
+            .filter(|&&byte| decoder.feed(byte).is_some())
+            .count();
+
+        assert_eq!(events, 1);
+        assert_eq!(decoder.feed(0x1C), Some((KeyCode::A, true)));
+    }
+}
@@ -1,42 +1,80 @@
 #![no_main]
 #![no_std]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(crate::test::runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
 extern crate alloc;
 
-// Use alloc::string::String for owned strings and VecDeque for line buffer
+// VecDeque for the line buffer
 use alloc::collections::VecDeque;
-use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec; // For Vec<(&str, Attrs)>
 
 use core::arch::asm;
-use core::{iter, ptr};
+use core::future::Future;
+use core::iter;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
 // Import core::fmt::Write for the trait implementation
 use core::fmt::{self, Write};
 
 use core_maths::CoreFloat;
 use cosmic_text::{Attrs, Buffer, Color, FontSystem, Metrics, Shaping, SwashCache};
-use limine::memory_map::EntryType;
-use linked_list_allocator::LockedHeap;
-use spin::{Mutex, Once};
+use spin::Once;
 
-mod boot;
+use crate::ansi::StyledLine;
+use crate::framebuffer::{Arrangement, BackBuffer};
+use crate::sync::{Semaphore, SpinLock};
 
-#[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+mod acpi;
+mod ansi;
+mod arch;
+mod boot;
+mod executor;
+mod framebuffer;
+mod keyboard;
+mod memory;
+mod serial;
+mod shell;
+mod sync;
+mod task;
+#[cfg(test)]
+mod test;
+mod time;
 
 // Logger using the new println macro
 struct SimpleLogger;
 
 impl log::Log for SimpleLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= log::Level::Info
+        // The shell's log-level command changes the maximum level at run time
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
+            let uptime = time::uptime();
+
+            // Each level has its own color, selected with SGR sequences
+            let color = match record.level() {
+                log::Level::Error => "31",
+                log::Level::Warn => "33",
+                log::Level::Info => "32",
+                log::Level::Debug => "36",
+                log::Level::Trace => "90",
+            };
+
             // Use the global println! macro
-            println!("{} - {}", record.level(), record.args());
+            println!(
+                "[{:>5}.{:06}] \x1B[{color}m{}\x1B[0m - {}",
+                uptime.as_secs(),
+                uptime.subsec_micros(),
+                record.level(),
+                record.args()
+            );
         }
     }
 
@@ -45,40 +83,156 @@ impl log::Log for SimpleLogger {
 
 static LOGGER: SimpleLogger = SimpleLogger;
 
-// Framebuffer structure (as before)
-struct Framebuffer {
-    addr: *mut u8,
-    pitch: u64,
-    width: u64,
-    height: u64,
-    // We'll assume 32 bpp, XRGB format as common with Limine
-}
-
-unsafe impl Send for Framebuffer {}
-unsafe impl Sync for Framebuffer {}
-
 // Maximum number of lines to keep in the scrollback buffer
 const MAX_CONSOLE_LOGICAL_LINES: usize = 200;
 // Default font size and line height for the console
 const CONSOLE_FONT_SIZE: f32 = 16.0;
 const CONSOLE_LINE_HEIGHT: f32 = 18.0;
+// Width of the input line's cursor in pixels
+const CONSOLE_CURSOR_WIDTH: usize = 2;
+// Cursor movement stops at this column, so a bad sequence can't make the
+// next character pad its line with thousands of spaces
+const MAX_CONSOLE_COLUMNS: usize = 512;
+// Tab stops are every this many columns
+const CONSOLE_TAB_WIDTH: usize = 8;
+const DEFAULT_TEXT_COLOR: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+// Output that doesn't end a line waits this long to be drawn, so more of it
+// is drawn together
+const CONSOLE_FLUSH_DELAY: Duration = Duration::from_millis(20);
+
+// A line of the console and its shaped text, which is dropped whenever the
+// text changes so the next flush shapes it again
+struct ConsoleLine {
+    // Identifies the line across flushes, as its index changes when lines
+    // before it are dropped
+    id: u64,
+    text: StyledLine,
+    shaped: Option<ShapedLine>,
+}
+
+impl ConsoleLine {
+    fn new(text: StyledLine) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            text,
+            shaped: None,
+        }
+    }
+
+    // The text, for changing it
+    fn edit(&mut self) -> &mut StyledLine {
+        self.shaped = None;
+        &mut self.text
+    }
+
+    // Shapes the line, unless it hasn't changed since it last was
+    fn shape(&mut self, font_system: &mut FontSystem, metrics: Metrics, width: f32) -> &ShapedLine {
+        self.shaped
+            .get_or_insert_with(|| ShapedLine::new(font_system, metrics, width, &self.text))
+    }
+}
+
+struct ShapedLine {
+    buffer: Buffer,
+    // Background colors, indexed by glyph metadata minus one
+    backgrounds: Vec<Color>,
+    // In pixels, several rows' worth when the line wraps
+    height: usize,
+}
+
+impl ShapedLine {
+    fn new(font_system: &mut FontSystem, metrics: Metrics, width: f32, line: &StyledLine) -> Self {
+        let attrs = ansi::Style::PLAIN.attrs(DEFAULT_TEXT_COLOR);
+        let mut buffer = Buffer::new(font_system, metrics);
+
+        // Lines wrap at the width of the screen and are as tall as they need
+        buffer.set_size(font_system, Some(width), None);
+
+        let mut backgrounds = Vec::new();
+        let mut text_spans: Vec<(&str, Attrs)> = Vec::new();
+        push_styled_line(&mut text_spans, &mut backgrounds, line);
+
+        // An empty line has no runs, so provide an empty span to prevent
+        // panic in set_rich_text
+        if text_spans.is_empty() {
+            text_spans.push(("", attrs.clone()));
+        }
+
+        buffer.set_rich_text(font_system, text_spans, &attrs, Shaping::Advanced, None);
+
+        let height = buffer
+            .layout_runs()
+            .map(|run| run.line_height)
+            .sum::<f32>()
+            .max(metrics.line_height);
+
+        Self {
+            buffer,
+            backgrounds,
+            height: height.ceil() as usize,
+        }
+    }
+
+    // Finds where the cursor before byte `cursor` goes, as its x, top and
+    // height. Wrapped lines have several runs
+    fn cursor_position(&self, cursor: usize) -> (f32, f32, f32) {
+        let mut position = (0.0, 0.0, self.buffer.metrics().line_height);
+
+        for run in self.buffer.layout_runs() {
+            let mut x = 0.0;
+            for glyph in run.glyphs {
+                if glyph.start >= cursor {
+                    return (glyph.x, run.line_top, run.line_height);
+                }
+                x = glyph.x + glyph.w;
+            }
+
+            position = (x, run.line_top, run.line_height);
+        }
+
+        position
+    }
+}
+
+// Where the last flush drew a line
+#[derive(Clone, Copy)]
+struct DrawnLine {
+    id: u64,
+    top: usize,
+    height: usize,
+}
 
 struct Console {
-    framebuffer: Framebuffer,
+    // Drawing goes to the back buffer, which each flush presents
+    screen: BackBuffer,
     font_system: FontSystem,
     swash_cache: SwashCache,
-    text_buffer: Buffer,             // For rendering visible lines
-    logical_lines: VecDeque<String>, // Stores all lines, including scrollback
-    default_attrs: Attrs<'static>,
+    logical_lines: VecDeque<ConsoleLine>, // Stores all lines, including scrollback
+    // Escape sequence state, and the attributes SGR sequences selected
+    parser: ansi::Parser,
+    style: ansi::Style,
+    // Line the cursor is on, as an index into logical_lines, and its column
+    // in characters. Escape sequences move it within the visible lines
+    cursor_line: usize,
+    cursor_column: usize,
+    saved_cursor: (usize, usize),
     font_metrics: Metrics,
     max_visible_lines: usize,
+    // Line being edited, pinned below the output, and its cursor's byte index
+    input_line: Option<(ConsoleLine, usize)>,
+    // What the screen shows, top to bottom
+    drawn: Vec<DrawnLine>,
+    // Set when output ends a line, which is drawn without waiting
+    line_ended: bool,
 }
 
-static CONSOLE: Once<Mutex<Console>> = Once::new();
+static CONSOLE: Once<SpinLock<Console>> = Once::new();
 
 impl Console {
-    pub fn new(framebuffer: Framebuffer) -> Self {
-        let mut font_system = FontSystem::new_with_fonts(iter::once(
+    pub fn new(screen: BackBuffer) -> Self {
+        let font_system = FontSystem::new_with_fonts(iter::once(
             cosmic_text::fontdb::Source::Binary(Arc::from(include_bytes!(
                 "../../assets/fonts/RobotoMono-SemiBold.ttf" // Ensure this path is correct
             ))),
@@ -86,190 +240,446 @@ impl Console {
 
         let swash_cache = SwashCache::new();
         let font_metrics = Metrics::new(CONSOLE_FONT_SIZE, CONSOLE_LINE_HEIGHT);
-        let mut text_buffer = Buffer::new(&mut font_system, font_metrics);
-
-        // Set the layout size of the cosmic_text buffer to the framebuffer dimensions
-        text_buffer.set_size(
-            &mut font_system,
-            Some(framebuffer.width as f32),
-            Some(framebuffer.height as f32),
-        );
 
         // Calculate how many lines can be visible
         let mut max_visible_lines =
-            (framebuffer.height as f32 / font_metrics.line_height).floor() as usize;
+            (screen.height() as f32 / font_metrics.line_height).floor() as usize;
         if max_visible_lines == 0 {
             max_visible_lines = 1; // Ensure at least one line can be shown
         }
 
-        let default_attrs = Attrs::new().color(Color::rgb(0xFF, 0xFF, 0xFF)); // Default white text
-
         let mut logical_lines = VecDeque::with_capacity(MAX_CONSOLE_LOGICAL_LINES);
-        logical_lines.push_back(String::new()); // Start with one empty line
+        logical_lines.push_back(ConsoleLine::new(StyledLine::new())); // Start with one empty line
 
         Self {
-            framebuffer,
+            screen,
             font_system,
             swash_cache,
-            text_buffer,
             logical_lines,
-            default_attrs,
+            parser: ansi::Parser::new(),
+            style: ansi::Style::PLAIN,
+            cursor_line: 0,
+            cursor_column: 0,
+            saved_cursor: (0, 0),
             font_metrics,
             max_visible_lines,
+            input_line: None,
+            drawn: Vec::new(),
+            line_ended: false,
         }
     }
 
-    // Clears the framebuffer to black
-    fn clear_framebuffer(&mut self) {
-        let screen_size_bytes = self.framebuffer.pitch * self.framebuffer.height;
-        unsafe {
-            ptr::write_bytes(self.framebuffer.addr, 0x00, screen_size_bytes as usize);
-        }
+    /// Shows `line` below the output with a cursor before byte `cursor`, or
+    /// removes the input line.
+    pub fn set_input_line(&mut self, line: Option<(&StyledLine, usize)>) {
+        self.input_line = line.map(|(line, cursor)| (ConsoleLine::new(line.clone()), cursor));
     }
 
-    /// Sets the default color for text printed to the console.
-    pub fn set_default_color(&mut self, color: Color) {
-        self.default_attrs = Attrs::new().color(color);
+    /// Drops all output, including the scrollback.
+    pub fn clear(&mut self) {
+        self.logical_lines.clear();
+        self.logical_lines
+            .push_back(ConsoleLine::new(StyledLine::new()));
+        self.cursor_line = 0;
+        self.cursor_column = 0;
+        self.saved_cursor = (0, 0);
     }
 
-    /// Resets the default color to white.
-    pub fn reset_default_color(&mut self) {
-        self.default_attrs = Attrs::new().color(Color::rgb(0xFF, 0xFF, 0xFF));
+    // Index of the first visible line, which escape sequences count rows from
+    fn screen_start(&self) -> usize {
+        self.logical_lines
+            .len()
+            .saturating_sub(self.max_visible_lines)
+    }
+
+    // Moves the cursor to the start of the next line, adding one at the end
+    fn new_line(&mut self) {
+        self.cursor_line += 1;
+        self.cursor_column = 0;
+        self.line_ended = true;
+
+        if self.cursor_line == self.logical_lines.len() {
+            if self.logical_lines.len() >= MAX_CONSOLE_LOGICAL_LINES {
+                self.logical_lines.pop_front(); // Maintain scrollback limit
+                self.cursor_line -= 1;
+                self.saved_cursor.0 = self.saved_cursor.0.saturating_sub(1);
+            }
+            self.logical_lines
+                .push_back(ConsoleLine::new(StyledLine::new()));
+        }
     }
 
-    // Renders the current visible lines to the framebuffer
-    pub fn flush_and_redraw(&mut self) {
-        self.clear_framebuffer();
+    // Moves the cursor to a visible row and column, both counted from 0.
+    // Rows past the last line add empty ones, as on a terminal's blank screen
+    fn move_cursor(&mut self, row: usize, column: usize) {
+        let row = row.min(self.max_visible_lines - 1);
+        let line = self.screen_start() + row;
 
-        // Determine the slice of logical_lines to display
-        let display_line_count = self.logical_lines.len().min(self.max_visible_lines);
-        let start_index = self.logical_lines.len() - display_line_count;
+        while self.logical_lines.len() <= line {
+            self.logical_lines
+                .push_back(ConsoleLine::new(StyledLine::new()));
+        }
 
-        let mut text_spans: Vec<(&str, Attrs)> = Vec::new();
+        self.cursor_line = line;
+        self.cursor_column = column.min(MAX_CONSOLE_COLUMNS);
+    }
+
+    // Visible row of the cursor, counted from 0
+    fn cursor_row(&self) -> usize {
+        self.cursor_line.saturating_sub(self.screen_start())
+    }
 
-        for i in 0..display_line_count {
-            let line_index_in_deque = start_index + i;
-            if let Some(line_str) = self.logical_lines.get(line_index_in_deque) {
-                text_spans.push((line_str.as_str(), self.default_attrs.clone()));
-                // Add a newline for all but the conceptual "last line" being fed to set_rich_text,
-                // if there are more lines to come or if it's not the very last line of all logical lines.
-                // cosmic-text handles wrapping, so we primarily add \n to separate distinct logical lines.
-                if i < display_line_count - 1 {
-                    // If not the last line being pushed to spans
-                    text_spans.push(("\n", self.default_attrs.clone()));
+    // Writes a character over the one under the cursor, padding the line
+    // with spaces if the cursor is past its end
+    fn put_char(&mut self, character: char) {
+        let column = self.cursor_column;
+        let line = self.logical_lines[self.cursor_line].edit();
+        let mut encoded = [0; 4];
+        let encoded = character.encode_utf8(&mut encoded);
+
+        match line.text().char_indices().nth(column) {
+            Some((start, existing)) => {
+                line.replace(start..start + existing.len_utf8(), encoded, self.style);
+            }
+            None => {
+                let length = line.text().chars().count();
+                if column > length {
+                    line.push_str(&" ".repeat(column - length), ansi::Style::PLAIN);
                 }
+                line.push_str(encoded, self.style);
             }
         }
 
-        // If there are no lines to display (e.g., after clearing everything),
-        // provide an empty span to prevent panic in set_rich_text.
-        if text_spans.is_empty() {
-            text_spans.push(("", self.default_attrs.clone()));
+        self.cursor_column += 1;
+    }
+
+    // Erases part of the cursor's line: 0 from the cursor on, 1 up to and
+    // including the cursor, 2 all of it
+    fn erase_in_line(&mut self, mode: u16) {
+        let column = self.cursor_column;
+        let line = self.logical_lines[self.cursor_line].edit();
+        let text = line.text();
+        let split = text.char_indices().nth(column).map(|(index, _)| index);
+
+        match mode {
+            0 => {
+                if let Some(split) = split {
+                    line.truncate(split);
+                }
+            }
+            1 => {
+                let end = split.map_or(text.len(), |split| {
+                    split + text[split..].chars().next().map_or(0, char::len_utf8)
+                });
+                let erased = " ".repeat(text[..end].chars().count());
+                line.replace(0..end, &erased, ansi::Style::PLAIN);
+            }
+            2 => line.clear(),
+            _ => {}
         }
+    }
 
-        self.text_buffer.set_rich_text(
-            &mut self.font_system,
-            text_spans,
-            &self.default_attrs, // Base attributes for the buffer
-            Shaping::Advanced,
-            None, // metadata_map
-        );
+    // Erases part of the screen: 0 from the cursor on, 1 up to and including
+    // the cursor, 2 all of it, 3 the scrollback above it
+    fn erase_in_display(&mut self, mode: u16) {
+        let screen_start = self.screen_start();
+        let (before, after) = (screen_start..self.cursor_line, self.cursor_line + 1..);
+
+        match mode {
+            0 => {
+                self.erase_in_line(0);
+                self.logical_lines
+                    .range_mut(after)
+                    .for_each(|line| line.edit().clear());
+            }
+            1 => {
+                self.erase_in_line(1);
+                self.logical_lines
+                    .range_mut(before)
+                    .for_each(|line| line.edit().clear());
+            }
+            2 => self
+                .logical_lines
+                .range_mut(screen_start..)
+                .for_each(|line| line.edit().clear()),
+            3 => {
+                self.logical_lines.drain(..screen_start);
+                self.cursor_line = self.cursor_line.saturating_sub(screen_start);
+                self.saved_cursor.0 = self.saved_cursor.0.saturating_sub(screen_start);
+            }
+            _ => {}
+        }
+    }
 
-        // Prepare framebuffer details for the drawing closure
-        let fb_addr = self.framebuffer.addr;
-        let fb_pitch = self.framebuffer.pitch;
-        let fb_width = self.framebuffer.width;
-        let fb_height = self.framebuffer.height;
-
-        // Drawing closure - captures framebuffer details
-        let drawing_closure = |x_px: i32, y_px: i32, w_px: u32, h_px: u32, color: Color| {
-            // Limine's typical framebuffer format is XRGB8888 (32-bit).
-            // R is at bits 16-23, G at 8-15, B at 0-7.
-            let pixel_val_rgb =
-                ((color.r() as u32) << 16) | ((color.g() as u32) << 8) | (color.b() as u32);
-
-            for ry in 0..h_px {
-                // Iterate over each pixel of the glyph
-                for rx in 0..w_px {
-                    let screen_x = x_px + rx as i32;
-                    let screen_y = y_px + ry as i32;
-
-                    if screen_x < 0
-                        || screen_x >= fb_width as i32
-                        || screen_y < 0
-                        || screen_y >= fb_height as i32
-                    {
-                        continue; // Skip pixels outside framebuffer bounds
-                    }
+    fn control(&mut self, character: char) {
+        match character {
+            // Output follows the usual newline convention, which includes a
+            // carriage return
+            '\n' => self.new_line(),
+            '\r' => self.cursor_column = 0,
+            '\t' => {
+                let next_stop = (self.cursor_column / CONSOLE_TAB_WIDTH + 1) * CONSOLE_TAB_WIDTH;
+                self.cursor_column = next_stop.min(MAX_CONSOLE_COLUMNS);
+            }
+            '\x08' => self.cursor_column = self.cursor_column.saturating_sub(1),
+            _ => {}
+        }
+    }
 
-                    let offset = (screen_y as u64 * fb_pitch) + (screen_x as u64 * 4); // 4 bytes per pixel
-                    let pixel_ptr = unsafe { fb_addr.add(offset as usize).cast::<u32>() };
-
-                    if color.a() == 255 {
-                        // Opaque glyph pixel
-                        unsafe { pixel_ptr.write_volatile(pixel_val_rgb) };
-                    } else if color.a() > 0 {
-                        // Transparent glyph pixel, blend with background
-                        let bg_pixel_val = unsafe { pixel_ptr.read_volatile() };
-
-                        // Extract RGB from background (XRGB format)
-                        let bg_b = (bg_pixel_val & 0x0000FF) as u8;
-                        let bg_g = ((bg_pixel_val & 0x00FF00) >> 8) as u8;
-                        let bg_r = ((bg_pixel_val & 0xFF0000) >> 16) as u8;
-
-                        let fg_r = color.r();
-                        let fg_g = color.g();
-                        let fg_b = color.b();
-                        let alpha_norm = color.a() as f32 / 255.0;
-
-                        // Standard alpha blending: C_out = C_fg * A_fg + C_bg * (1 - A_fg)
-                        let out_r =
-                            (fg_r as f32 * alpha_norm + bg_r as f32 * (1.0 - alpha_norm)) as u8;
-                        let out_g =
-                            (fg_g as f32 * alpha_norm + bg_g as f32 * (1.0 - alpha_norm)) as u8;
-                        let out_b =
-                            (fg_b as f32 * alpha_norm + bg_b as f32 * (1.0 - alpha_norm)) as u8;
-
-                        let blended_pixel_val_rgb =
-                            ((out_r as u32) << 16) | ((out_g as u32) << 8) | (out_b as u32);
-                        unsafe { pixel_ptr.write_volatile(blended_pixel_val_rgb) };
-                    }
-                    // If color.a() == 0, glyph pixel is fully transparent, so do nothing.
-                }
+    fn control_sequence(&mut self, csi: &ansi::Csi) {
+        // DEC private modes, such as cursor visibility, don't apply here
+        if csi.private {
+            return;
+        }
+
+        let count = csi.param(0, 1) as usize;
+        let (row, column) = (self.cursor_row(), self.cursor_column);
+
+        match csi.function {
+            'A' => self.move_cursor(row.saturating_sub(count), column),
+            'B' => self.move_cursor(row + count, column),
+            'C' => self.move_cursor(row, column + count),
+            'D' => self.move_cursor(row, column.saturating_sub(count)),
+            'E' => self.move_cursor(row + count, 0),
+            'F' => self.move_cursor(row.saturating_sub(count), 0),
+            'G' => self.move_cursor(row, count - 1),
+            'd' => self.move_cursor(count - 1, column),
+            'H' | 'f' => self.move_cursor(count - 1, csi.param(1, 1) as usize - 1),
+            'J' => self.erase_in_display(csi.param(0, 0)),
+            'K' => self.erase_in_line(csi.param(0, 0)),
+            'm' => self.style.apply_sgr(csi.params()),
+            's' => self.saved_cursor = (self.cursor_line, self.cursor_column),
+            'u' => (self.cursor_line, self.cursor_column) = self.saved_cursor,
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, character: char) {
+        match character {
+            '7' => self.saved_cursor = (self.cursor_line, self.cursor_column),
+            '8' => (self.cursor_line, self.cursor_column) = self.saved_cursor,
+            // Full reset
+            'c' => {
+                self.clear();
+                self.style = ansi::Style::PLAIN;
+            }
+            _ => {}
+        }
+    }
+
+    // Draws a line at `placement`, over whatever was there. `index` is into
+    // logical_lines, or None for the input line, which gets a cursor
+    fn draw_line(&mut self, index: Option<usize>, placement: DrawnLine) {
+        let (line, cursor) = match index {
+            Some(index) => (&self.logical_lines[index], None),
+            None => {
+                let (line, cursor) = self.input_line.as_ref().unwrap();
+                (line, Some(*cursor))
             }
         };
+        let shaped = line.shaped.as_ref().expect("console line not shaped");
+        let screen = &mut self.screen;
+        let (top, bottom) = (placement.top, placement.top + placement.height);
+
+        screen.fill_rect(0, top, screen.width(), placement.height, 0x000000);
+
+        // Fill behind the glyphs of runs with a background before drawing
+        // them, so they blend with it
+        for run in shaped.buffer.layout_runs() {
+            for glyph in run.glyphs.iter().filter(|glyph| glyph.metadata > 0) {
+                let color: Color = shaped.backgrounds[glyph.metadata - 1];
+                screen.fill_rect(
+                    glyph.x as usize,
+                    top + run.line_top as usize,
+                    glyph.w.ceil() as usize,
+                    run.line_height as usize,
+                    color.0 & 0xFFFFFF,
+                );
+            }
+        }
 
-        self.text_buffer.draw(
+        // Glyphs reaching past the line are cut off, so they can't draw
+        // over its neighbours
+        shaped.buffer.draw(
             &mut self.font_system,
             &mut self.swash_cache,
             Color::rgba(0, 0, 0, 0), // Transparent background for text layout areas
-            drawing_closure,
+            |x, y, width, height, color| {
+                for row in y..y + height as i32 {
+                    let screen_row = top as i32 + row;
+                    if screen_row < top as i32 || screen_row >= bottom as i32 {
+                        continue;
+                    }
+                    for column in x..x + width as i32 {
+                        screen.blend_pixel(column, screen_row, color);
+                    }
+                }
+            },
         );
+
+        if let Some(cursor) = cursor {
+            let (x, cursor_top, height) = shaped.cursor_position(cursor);
+            screen.fill_rect(
+                x as usize,
+                top + cursor_top as usize,
+                CONSOLE_CURSOR_WIDTH,
+                height as usize,
+                0xFFFFFF,
+            );
+        }
+    }
+
+    /// Draws what changed since the last flush. Only lines whose text
+    /// changed are shaped again, and only lines that changed or moved are
+    /// drawn; when output scrolls, the lines still on screen are moved up
+    /// instead of being drawn again.
+    pub fn flush(&mut self) {
+        self.line_ended = false;
+
+        let screen_height = self.screen.height();
+        let width = self.screen.width() as f32;
+
+        // The input line takes the place of the empty lines from the cursor on
+        let mut output_lines = self.logical_lines.len();
+        if self.input_line.is_some() {
+            while output_lines > self.cursor_line
+                && self.logical_lines[output_lines - 1].text.is_empty()
+            {
+                output_lines -= 1;
+            }
+        }
+
+        // Shape lines from the bottom up until the screen is full. Each is
+        // its index (None for the input line), its height and whether it
+        // was shaped just now
+        let input = self.input_line.as_mut().map(|(line, _)| (None, line));
+        let output = self
+            .logical_lines
+            .range_mut(..output_lines)
+            .enumerate()
+            .rev()
+            .map(|(index, line)| (Some(index), line));
+
+        let mut visible = Vec::new();
+        let mut used_height = 0;
+        for (index, line) in input.into_iter().chain(output) {
+            let fresh = line.shaped.is_none();
+            let height = line
+                .shape(&mut self.font_system, self.font_metrics, width)
+                .height;
+
+            // A single line taller than the screen is cut off at its bottom
+            if used_height + height > screen_height && !visible.is_empty() {
+                break;
+            }
+            used_height += height;
+            visible.push((index, line.id, height, fresh));
+        }
+
+        // Lines scrolled out of view don't keep their shaped text around,
+        // except the one just above the screen, which the next flush
+        // measures again
+        let first_visible = visible
+            .iter()
+            .filter_map(|&(index, ..)| index)
+            .next_back()
+            .unwrap_or(output_lines);
+        for line in self
+            .logical_lines
+            .range_mut(..first_visible.saturating_sub(1))
+        {
+            line.shaped = None;
+        }
+
+        // Lay the lines out from the top of the screen
+        let mut frame = Vec::with_capacity(visible.len());
+        let mut top = 0;
+        for &(index, id, height, fresh) in visible.iter().rev() {
+            frame.push((index, DrawnLine { id, top, height }, fresh));
+            top += height;
+        }
+        let bottom = top.min(screen_height);
+
+        let previous = core::mem::take(&mut self.drawn);
+        let previous_bottom = previous
+            .last()
+            .map_or(0, |line| line.top + line.height)
+            .min(screen_height);
+        let find_previous = |id| previous.iter().find(|line: &&DrawnLine| line.id == id);
+
+        // Lines that are still on screen unchanged have moved up by however
+        // much was scrolled, so move their pixels along with them
+        let scroll = frame
+            .iter()
+            .filter(|(_, _, fresh)| !fresh)
+            .find_map(|(_, line, _)| {
+                find_previous(line.id).map(|drawn| drawn.top.saturating_sub(line.top))
+            })
+            .unwrap_or(0);
+        if scroll > 0 {
+            self.screen.scroll_up(scroll, previous_bottom);
+        }
+
+        for &(index, line, fresh) in &frame {
+            let in_place = !fresh
+                && find_previous(line.id).is_some_and(|drawn| {
+                    drawn.top.checked_sub(scroll) == Some(line.top) && drawn.height == line.height
+                });
+
+            if !in_place {
+                self.draw_line(index, line);
+            }
+        }
+
+        // Clear what was drawn below the lines now on screen, which
+        // includes what scrolling left behind
+        if bottom < previous_bottom {
+            self.screen.fill_rect(
+                0,
+                bottom,
+                self.screen.width(),
+                previous_bottom - bottom,
+                0x000000,
+            );
+        }
+
+        self.drawn = frame.into_iter().map(|(_, line, _)| line).collect();
+        self.screen.present();
+    }
+}
+
+// Appends the runs of `line` to `spans`. Runs with a background are tagged
+// with its index in `backgrounds` plus one, which their glyphs carry as
+// metadata
+fn push_styled_line<'a>(
+    spans: &mut Vec<(&'a str, Attrs<'static>)>,
+    backgrounds: &mut Vec<Color>,
+    line: &'a StyledLine,
+) {
+    for (text, style) in line.runs() {
+        let mut attrs = style.attrs(DEFAULT_TEXT_COLOR);
+
+        if let Some(background) = style.background_color(DEFAULT_TEXT_COLOR) {
+            backgrounds.push(background);
+            attrs = attrs.metadata(backgrounds.len());
+        }
+
+        spans.push((text, attrs));
     }
 }
 
 // Implement core::fmt::Write for our Console
 impl Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Escape sequences may be split across writes, so the parser keeps
+        // its state between them
         for ch in s.chars() {
-            if ch == '\n' {
-                // If current line is full or we explicitly hit newline, add a new line
-                if self.logical_lines.len() >= MAX_CONSOLE_LOGICAL_LINES {
-                    self.logical_lines.pop_front(); // Maintain scrollback limit
-                }
-                self.logical_lines.push_back(String::new());
-            } else if ch.is_control() {
-                // Handle other control characters if needed (e.g., backspace, tabs)
-                // For now, ignore them or print a placeholder
-                if ch == '\r' { /* ignore carriage return for now, or handle as newline */ }
-                // else { self.logical_lines.back_mut().unwrap().push('?'); } // Placeholder for unhandled control chars
-            } else {
-                // Add character to the current line
-                if self.logical_lines.is_empty() {
-                    // Should not happen if initialized with one line
-                    self.logical_lines.push_back(String::new());
-                }
-                self.logical_lines.back_mut().unwrap().push(ch);
+            match self.parser.advance(ch) {
+                Some(ansi::Action::Print(ch)) => self.put_char(ch),
+                Some(ansi::Action::Control(ch)) => self.control(ch),
+                Some(ansi::Action::Csi(csi)) => self.control_sequence(&csi),
+                Some(ansi::Action::Escape(ch)) => self.escape(ch),
+                None => {}
             }
         }
         Ok(())
@@ -291,122 +701,280 @@ macro_rules! println {
 // Helper function for print macros to lock the console and write
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    // Mirror everything to the serial port first, so output is captured even
+    // before the framebuffer console exists
+    serial::_print(args);
+
+    if let Some(console_mutex) = CONSOLE.get() {
+        let line_ended = {
+            let mut console_guard = console_mutex.lock();
+            // The Write trait takes care of appending to logical_lines
+            console_guard.write_fmt(args).unwrap();
+
+            // Finished lines are drawn right away, and anything else by the
+            // flush task shortly after
+            let line_ended = console_guard.line_ended;
+            if line_ended {
+                console_guard.flush();
+            }
+            line_ended
+        };
+
+        if !line_ended {
+            request_flush();
+        }
+    }
+    // If console is not initialized, the output only reaches the serial port
+}
+
+/// Draws any console output that hasn't been yet. Output that ends a line
+/// is drawn as it is printed, and the rest shortly after, so this is only
+/// needed to show a partial line right away.
+pub fn flush_console() {
+    if let Some(console_mutex) = CONSOLE.get() {
+        FLUSH_PENDING.store(false, Ordering::Relaxed);
+        console_mutex.lock().flush();
+    }
+}
+
+/// Shows `line` pinned below the console output with a cursor before byte
+/// `cursor`, or removes the input line. Only the framebuffer console has one.
+pub fn set_input_line(line: Option<(&StyledLine, usize)>) {
     if let Some(console_mutex) = CONSOLE.get() {
         let mut console_guard = console_mutex.lock();
-        // The Write trait takes care of appending to logical_lines
-        console_guard.write_fmt(args).unwrap();
-        // Redraw the console after the print operation
-        console_guard.flush_and_redraw();
+        console_guard.set_input_line(line);
+        console_guard.flush();
+    }
+}
+
+// Set when output is waiting for the flush task, which waits on the waker
+static FLUSH_PENDING: AtomicBool = AtomicBool::new(false);
+static FLUSH_WAKER: SpinLock<Option<Waker>> = SpinLock::new("console flush waker", None);
+
+fn request_flush() {
+    if !FLUSH_PENDING.swap(true, Ordering::Relaxed)
+        && let Some(waker) = FLUSH_WAKER.lock().take()
+    {
+        waker.wake();
+    }
+}
+
+// Completes once output is waiting to be drawn
+struct PendingFlush;
+
+impl Future for PendingFlush {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        // Registered before checking, so a request in between isn't missed
+        *FLUSH_WAKER.lock() = Some(context.waker().clone());
+
+        if FLUSH_PENDING.load(Ordering::Relaxed) {
+            FLUSH_WAKER.lock().take();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+// Draws output that doesn't end a line, a little after it was printed
+async fn flush_pending_output() {
+    loop {
+        PendingFlush.await;
+        executor::timer::sleep(CONSOLE_FLUSH_DELAY).await;
+
+        // Output that ended a line may have drawn everything meanwhile,
+        // but flushing again is cheap when nothing changed
+        flush_console();
     }
-    // If console is not initialized, output might be lost (e.g. very early panic)
 }
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn kmain() -> ! {
-    // Initialize memory allocator
-    let memory_map_response = boot::MEMORY_MAP_REQUEST
-        .get_response()
-        .expect("Failed to get memory map");
-
-    let (base, len) = memory_map_response
-        .entries()
-        .iter()
-        .filter(|entry| entry.entry_type == EntryType::USABLE)
-        .max_by_key(|entry| entry.length)
-        .map(|entry| (entry.base, entry.length))
-        .expect("No usable memory region found");
-
-    let base_ptr = ptr::with_exposed_provenance_mut(base as usize);
-    unsafe {
-        ALLOCATOR.lock().init(base_ptr, len as usize);
-    }
-
-    // Initialize Framebuffer
-    let framebuffer_response = boot::FRAMEBUFFER_REQUEST
-        .get_response()
-        .expect("Failed to get framebuffer response");
-    let limine_fb = framebuffer_response
-        .framebuffers()
-        .next()
-        .expect("No framebuffer available");
-
-    let kernel_framebuffer = Framebuffer {
-        addr: limine_fb.addr(),
-        pitch: limine_fb.pitch(),
-        width: limine_fb.width(),
-        height: limine_fb.height(),
-    };
-
-    // Initialize Console
-    CONSOLE.call_once(|| Mutex::new(Console::new(kernel_framebuffer)));
+    // Initialize the serial port first so early panics have somewhere to go
+    serial::init();
 
-    // Initialize Logger
+    // The logger prints through println!, which reaches the serial port even
+    // before the console exists
     log::set_logger(&LOGGER)
         .map(|()| log::set_max_level(log::LevelFilter::Info))
         .unwrap();
 
-    // Test printing
-    println!("Hello from the kernel!");
-    println!(
-        "This is line 2. Framebuffer: {}x{}",
-        limine_fb.width(),
-        limine_fb.height()
-    );
-    log::info!("This is an info log message.");
+    // Install our own descriptor tables so CPU exceptions are reported
+    // instead of triple faulting
+    arch::x86_64::init();
 
-    let mut counter = 0;
-    loop {
-        println!("Counter: {}", counter);
-        counter += 1;
-        // Simple delay loop (very basic, not for production)
-        for _ in 0..10_000_000 {
-            unsafe {
-                asm!("nop");
-            }
-        }
-        if counter > 5 {
-            // Print a few lines then panic
-            break;
-        }
-    }
+    // Initialize physical and virtual memory management and the heap
+    memory::init();
+
+    // Discover the interrupt controllers and take over interrupt delivery
+    acpi::init();
+    arch::x86_64::init_interrupts();
+    acpi::dump();
 
-    panic!("Kernel main finished (intentional panic).");
+    // Calibrate the clocks and start the periodic tick
+    time::init();
 
-    // Loop forever (hlt) - panic above will prevent reaching here directly
-    // loop {
-    //     unsafe {
-    //         asm!("hlt");
-    //     }
-    // }
+    // The console shows the same on every display, unless the command line
+    // has displays=span to spread it across them
+    let framebuffers = framebuffer::all();
+    assert!(!framebuffers.is_empty(), "No framebuffer available");
+
+    let arrangement = match boot::option("displays").as_deref() {
+        None | Some("mirror") => Arrangement::Mirror,
+        Some("span") => Arrangement::Span,
+        Some(other) => {
+            log::warn!("Unknown displays={other}, mirroring the console");
+            Arrangement::Mirror
+        }
+    };
+    let screen = BackBuffer::new(framebuffers, arrangement);
+    let (screen_width, screen_height) = (screen.width(), screen.height());
+
+    // Initialize Console
+    CONSOLE.call_once(|| SpinLock::new("console", Console::new(screen)));
+
+    // Start scheduling threads, then bring up the other CPUs now that their
+    // messages can reach the console
+    task::init();
+    arch::x86_64::smp::init();
+
+    // Run futures on their own thread, and start taking keyboard and
+    // serial input
+    executor::init();
+    executor::spawn(flush_pending_output());
+    serial::init_input();
+    keyboard::init();
+
+    // Test printing, from a thread of its own. The tests and the shell wait
+    // for it, so their output comes after
+    static GREETED: Semaphore = Semaphore::new(0);
+
+    task::spawn("hello", move || {
+        println!("Hello from the kernel!");
+        println!("This is line 2. Console: {screen_width}x{screen_height}");
+        log::info!("This is an info log message.");
+        log::info!("Physical memory: {}", memory::frame::stats());
+        log::info!("Heap: {}", memory::heap::stats());
+        log::info!("Date: {}", time::now());
+
+        GREETED.release();
+    });
+    GREETED.acquire();
+
+    // Hand over to the test runner instead of the shell when built with `cargo test`
+    #[cfg(test)]
+    test_main();
+
+    // The shell runs on the executor from here. Nothing uses Limine's
+    // responses any more, so bootloader memory is reclaimed once this thread
+    // has left its stack
+    shell::init();
+    task::exit()
 }
 
 #[cfg(target_os = "none")]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
-    // Attempt to print panic info to console, if CONSOLE is initialized
-    if CONSOLE.get().is_some() {
-        // Change color to red for panic message
-        if let Some(console_mutex) = CONSOLE.get() {
-            let mut console_guard = console_mutex.lock();
-            console_guard.set_default_color(Color::rgb(0xFF, 0x20, 0x20)); // Red
-        }
+    // Set by the first panic so a panic raised while reporting it doesn't recurse
+    static PANICKING: AtomicBool = AtomicBool::new(false);
 
-        println!("\n--- KERNEL PANIC ---");
-        println!("{info}");
+    // A panic inside a test is a test failure, so report it and exit QEMU
+    #[cfg(test)]
+    test::fail(info);
 
-        // Reset color if desired, though system is halting
-        if let Some(console_mutex) = CONSOLE.get() {
-            let mut console_guard = console_mutex.lock();
-            console_guard.reset_default_color();
+    // Reporting the first panic failed (e.g. the console ran out of heap
+    // memory), so fall back to the serial port, which never allocates
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        let mut serial = serial::panic_writer();
+        let _ = writeln!(serial, "\n--- NESTED KERNEL PANIC ---");
+        let _ = writeln!(serial, "{info}");
+
+        loop {
+            unsafe {
+                asm!("cli", "hlt");
+            }
         }
-    } else {
-        // Fallback if console is not available (e.g., very early panic)
-        // One could try writing to serial port here if available.
     }
 
+    // Stop the other CPUs so they can't print over the report
+    arch::x86_64::smp::halt_others();
+
+    // The panic may have been raised while printing, on this CPU or on one
+    // that was just halted, and the code holding the lock will never resume,
+    // so take the serial port and the console over
+    if serial::SERIAL.is_locked() {
+        unsafe { serial::SERIAL.force_unlock() };
+    }
+    if let Some(console_mutex) = CONSOLE.get()
+        && console_mutex.is_locked()
+    {
+        unsafe { console_mutex.force_unlock() };
+    }
+
+    // println! always reaches the serial port, so even very early panics
+    // (before the console exists) are visible. The escape sequence also ends
+    // any left unfinished, and only the report is red
+    println!("\x1B[0;1;31m\n--- KERNEL PANIC ---");
+    println!("{info}\x1B[0m");
+
     loop {
         unsafe {
             asm!("cli", "hlt");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::framebuffer::{Framebuffer, PixelFormat};
+
+    const WIDTH: usize = 240;
+    const ROW_HEIGHT: usize = CONSOLE_LINE_HEIGHT as usize;
+
+    #[test_case]
+    fn appending_a_line_redraws_only_the_new_row() {
+        let mut memory = vec![0u32; WIDTH * 3 * ROW_HEIGHT];
+        let front = Framebuffer {
+            addr: memory.as_mut_ptr().cast(),
+            pitch: (WIDTH * 4) as u64,
+            width: WIDTH as u64,
+            height: (3 * ROW_HEIGHT) as u64,
+            format: PixelFormat::XRGB8888,
+        };
+        let mut console = Console::new(BackBuffer::new(vec![front], Arrangement::Mirror));
+
+        writeln!(console, "first").unwrap();
+        console.flush();
+
+        // Shaping the first line again would drop this extra background
+        let tag = Color::rgb(1, 2, 3);
+        console.logical_lines[0]
+            .shaped
+            .as_mut()
+            .unwrap()
+            .backgrounds
+            .push(tag);
+
+        // Changed behind the console's back, so only rows drawn again are
+        // overwritten
+        memory.fill(0xAA);
+        writeln!(console, "second").unwrap();
+        console.flush();
+
+        let (first_row, second_row) = memory.split_at(ROW_HEIGHT * WIDTH);
+        assert!(first_row.iter().all(|&pixel| pixel == 0xAA));
+        assert!(
+            second_row[..ROW_HEIGHT * WIDTH]
+                .iter()
+                .any(|&pixel| pixel != 0xAA)
+        );
+
+        let shaped = console.logical_lines[0].shaped.as_ref().unwrap();
+        assert_eq!(shaped.backgrounds.last(), Some(&tag));
+    }
+}
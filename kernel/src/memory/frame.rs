@@ -0,0 +1,311 @@
+use core::fmt;
+use core::slice;
+
+use limine::memory_map::{Entry, EntryType};
+use spin::Once;
+
+use super::{PAGE_SIZE, phys_to_virt};
+use crate::sync::SpinLock;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+// Upper bound on how many bootloader-reclaimable regions we remember until
+// they can be handed back; firmware memory maps rarely have more than a few.
+const MAX_RECLAIMABLE_REGIONS: usize = 64;
+
+/// Frame usage counters, in frames.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStats {
+    /// Frames managed by the allocator, including those still held by the bootloader.
+    pub total: usize,
+    pub free: usize,
+    pub used: usize,
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |frames: usize| frames as u64 * PAGE_SIZE / (1024 * 1024);
+
+        write!(
+            f,
+            "{} frames ({} MiB) total, {} ({} MiB) free, {} ({} MiB) used",
+            self.total,
+            mib(self.total),
+            self.free,
+            mib(self.free),
+            self.used,
+            mib(self.used),
+        )
+    }
+}
+
+/// Bitmap allocator for physical frames. A set bit means the frame is in use
+/// (or does not exist).
+pub struct FrameAllocator {
+    bitmap: &'static mut [u64],
+    frame_count: usize,
+    total: usize,
+    free: usize,
+    // Where the next search starts, so allocations don't rescan low memory
+    next: usize,
+    reclaimable: [(u64, u64); MAX_RECLAIMABLE_REGIONS],
+    reclaimable_len: usize,
+}
+
+static FRAME_ALLOCATOR: Once<SpinLock<FrameAllocator>> = Once::new();
+
+impl FrameAllocator {
+    /// Builds the allocator from Limine's memory map.
+    ///
+    /// Usable entries become free frames immediately. Bootloader-reclaimable
+    /// entries are counted but stay allocated until
+    /// [`reclaim_bootloader_memory`](Self::reclaim_bootloader_memory).
+    pub fn new(entries: &[&Entry]) -> Self {
+        let managed = |entry: &&&Entry| {
+            entry.entry_type == EntryType::USABLE
+                || entry.entry_type == EntryType::BOOTLOADER_RECLAIMABLE
+        };
+
+        let highest_address = entries
+            .iter()
+            .filter(managed)
+            .map(|entry| entry.base + entry.length)
+            .max()
+            .expect("No usable memory region found");
+
+        let frame_count = highest_address.div_ceil(PAGE_SIZE) as usize;
+        let words = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_bytes = (words * size_of::<u64>()) as u64;
+
+        // Store the bitmap itself in the first usable region large enough
+        let bitmap_base = entries
+            .iter()
+            .find(|entry| entry.entry_type == EntryType::USABLE && entry.length >= bitmap_bytes)
+            .map(|entry| entry.base)
+            .expect("No usable memory region large enough for the frame bitmap");
+
+        let bitmap =
+            unsafe { slice::from_raw_parts_mut(phys_to_virt(bitmap_base) as *mut u64, words) };
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = Self {
+            bitmap,
+            frame_count,
+            total: 0,
+            free: 0,
+            next: 0,
+            reclaimable: [(0, 0); MAX_RECLAIMABLE_REGIONS],
+            reclaimable_len: 0,
+        };
+
+        for entry in entries.iter().filter(managed) {
+            let first = (entry.base / PAGE_SIZE) as usize;
+            let count = (entry.length / PAGE_SIZE) as usize;
+
+            if entry.entry_type == EntryType::USABLE {
+                allocator.set_range(first, count, false);
+                allocator.free += count;
+            } else if allocator.reclaimable_len < MAX_RECLAIMABLE_REGIONS {
+                allocator.reclaimable[allocator.reclaimable_len] = (entry.base, entry.length);
+                allocator.reclaimable_len += 1;
+            } else {
+                // Never handed over, so not counted as managed either
+                log::warn!(
+                    "Frame allocator: too many reclaimable regions, leaving {} KiB at {:#x} unused",
+                    entry.length / 1024,
+                    entry.base
+                );
+                continue;
+            }
+
+            allocator.total += count;
+        }
+
+        let bitmap_frames = bitmap_bytes.div_ceil(PAGE_SIZE) as usize;
+        allocator.set_range((bitmap_base / PAGE_SIZE) as usize, bitmap_frames, true);
+        allocator.free -= bitmap_frames;
+
+        allocator
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn set_range(&mut self, first: usize, count: usize, used: bool) {
+        for frame in first..first + count {
+            let word = &mut self.bitmap[frame / BITS_PER_WORD];
+            let bit = 1 << (frame % BITS_PER_WORD);
+
+            if used {
+                *word |= bit;
+            } else {
+                *word &= !bit;
+            }
+        }
+    }
+
+    // Looks for `count` free frames starting at a multiple of `align` within
+    // [start, end), returning the first frame of the run.
+    fn find_run(&self, start: usize, end: usize, count: usize, align: usize) -> Option<usize> {
+        let mut candidate = start.next_multiple_of(align);
+
+        'search: while candidate + count <= end {
+            // Skip fully used words quickly for the common single-frame case
+            if count == 1 && align == 1 && self.bitmap[candidate / BITS_PER_WORD] == u64::MAX {
+                candidate = (candidate / BITS_PER_WORD + 1) * BITS_PER_WORD;
+                continue;
+            }
+
+            let mut frame = candidate;
+            while frame < candidate + count {
+                if self.is_used(frame) {
+                    candidate = (frame + 1).next_multiple_of(align);
+                    continue 'search;
+                }
+                frame += 1;
+            }
+
+            return Some(candidate);
+        }
+
+        None
+    }
+
+    /// Allocates `count` physically contiguous frames whose first frame is
+    /// aligned to `align` frames (a power of two), returning its physical address.
+    pub fn allocate(&mut self, count: usize, align: usize) -> Option<u64> {
+        assert!(count > 0, "cannot allocate zero frames");
+        assert!(
+            align.is_power_of_two(),
+            "frame alignment must be a power of two"
+        );
+
+        if count > self.free {
+            return None;
+        }
+
+        let first = self
+            .find_run(self.next, self.frame_count, count, align)
+            .or_else(|| self.find_run(0, self.frame_count, count, align))?;
+
+        self.set_range(first, count, true);
+        self.free -= count;
+        self.next = first + count;
+
+        Some(first as u64 * PAGE_SIZE)
+    }
+
+    /// Frees `count` frames starting at physical address `base`.
+    pub fn free(&mut self, base: u64, count: usize) {
+        assert!(
+            base.is_multiple_of(PAGE_SIZE),
+            "freeing unaligned frame {base:#x}"
+        );
+
+        let first = (base / PAGE_SIZE) as usize;
+
+        for frame in first..first + count {
+            assert!(
+                self.is_used(frame),
+                "double free of frame {:#x}",
+                frame as u64 * PAGE_SIZE
+            );
+        }
+
+        self.set_range(first, count, false);
+        self.free += count;
+        self.next = self.next.min(first);
+    }
+
+    /// Hands bootloader-reclaimable memory over to the allocator.
+    ///
+    /// Must only be called once nothing references Limine's responses, page
+    /// tables or boot stacks any more.
+    pub fn reclaim_bootloader_memory(&mut self) {
+        for index in 0..self.reclaimable_len {
+            let (base, length) = self.reclaimable[index];
+            let count = (length / PAGE_SIZE) as usize;
+
+            self.set_range((base / PAGE_SIZE) as usize, count, false);
+            self.free += count;
+        }
+
+        self.reclaimable_len = 0;
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            free: self.free,
+            used: self.total - self.free,
+        }
+    }
+}
+
+fn allocator() -> &'static SpinLock<FrameAllocator> {
+    FRAME_ALLOCATOR
+        .get()
+        .expect("frame allocator not initialized")
+}
+
+/// Initializes the global frame allocator from Limine's memory map.
+pub fn init(entries: &[&Entry]) {
+    FRAME_ALLOCATOR.call_once(|| SpinLock::new("frame allocator", FrameAllocator::new(entries)));
+}
+
+/// Allocates `count` physically contiguous frames whose first frame is
+/// aligned to `align` frames, for devices that need a contiguous buffer.
+pub fn allocate(count: usize, align: usize) -> Option<u64> {
+    allocator().lock().allocate(count, align)
+}
+
+/// Allocates a single frame, returning its physical address.
+pub fn allocate_frame() -> Option<u64> {
+    allocate(1, 1)
+}
+
+/// Returns `count` frames starting at `base` to the allocator.
+pub fn free(base: u64, count: usize) {
+    allocator().lock().free(base, count);
+}
+
+/// Hands the memory the bootloader used over to the allocator. Called once
+/// nothing uses Limine's responses or boot stacks any more.
+pub fn reclaim_bootloader_memory() {
+    let mut allocator = allocator().lock();
+    let free = allocator.free;
+
+    allocator.reclaim_bootloader_memory();
+
+    let reclaimed = allocator.free - free;
+    drop(allocator);
+
+    log::info!(
+        "Reclaimed {} KiB of bootloader memory",
+        reclaimed as u64 * PAGE_SIZE / 1024
+    );
+}
+
+pub fn stats() -> FrameStats {
+    allocator().lock().stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn allocate_aligned_run() {
+        // Held throughout, so other CPUs can't allocate in between
+        let mut allocator = allocator().lock();
+        let before = allocator.stats();
+
+        let base = allocator.allocate(8, 8).expect("out of frames");
+        assert_eq!(base % (8 * PAGE_SIZE), 0);
+        assert_eq!(allocator.stats().free, before.free - 8);
+
+        allocator.free(base, 8);
+        assert_eq!(allocator.stats().free, before.free);
+    }
+}
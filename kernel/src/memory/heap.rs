@@ -0,0 +1,336 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::NonNull;
+
+use linked_list_allocator::Heap;
+
+use super::paging::{self, PageFlags};
+use super::{PAGE_SIZE, frame};
+use crate::sync::SpinLock;
+
+/// Start of the virtual range reserved for the kernel heap. It sits well
+/// above the HHDM, which only spans as much as the machine's physical memory.
+pub const HEAP_START: u64 = 0xFFFF_D000_0000_0000;
+
+/// Largest size the heap may grow to.
+pub const HEAP_MAX_SIZE: usize = 4 * 1024 * 1024 * 1024;
+
+// Mapped when the heap is initialized
+const HEAP_INITIAL_SIZE: usize = 1024 * 1024;
+
+// The heap grows by at least this much at a time, so a run of small
+// allocations doesn't map one page per call
+const HEAP_GROW_STEP: usize = 1024 * 1024;
+
+/// Heap usage counters, in bytes unless noted otherwise.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    /// Bytes currently mapped for the heap.
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Highest `used` value seen so far.
+    pub peak: usize,
+    /// Number of live allocations.
+    pub allocations: usize,
+    /// Number of times the heap was grown.
+    pub grows: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kib = |bytes: usize| bytes / 1024;
+
+        write!(
+            f,
+            "{} KiB mapped, {} KiB used ({} KiB peak), {} KiB free, {} allocations, grown {} times",
+            kib(self.size),
+            kib(self.used),
+            kib(self.peak),
+            kib(self.free),
+            self.allocations,
+            self.grows,
+        )
+    }
+}
+
+/// A failed heap allocation and the state of memory when it failed. It
+/// formats without allocating, so it can be printed with the heap exhausted.
+#[derive(Clone, Copy, Debug)]
+pub struct OutOfMemory {
+    pub layout: Layout,
+    pub heap: HeapStats,
+    pub frames: frame::FrameStats,
+}
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Out of kernel heap memory allocating {} bytes aligned to {}\nHeap: {}\nPhysical memory: {}",
+            self.layout.size(),
+            self.layout.align(),
+            self.heap,
+            self.frames,
+        )
+    }
+}
+
+struct HeapState {
+    heap: Heap,
+    max_size: usize,
+    peak: usize,
+    allocations: usize,
+    grows: usize,
+}
+
+impl HeapState {
+    const fn new(max_size: usize) -> Self {
+        Self {
+            heap: Heap::empty(),
+            max_size,
+            peak: 0,
+            allocations: 0,
+            grows: 0,
+        }
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let mut result = self.heap.allocate_first_fit(layout);
+
+        // Free space at the top may be too fragmented or too small, so grow
+        // by the whole request plus alignment slack and try once more
+        if result.is_err() && self.grow(layout.size() + layout.align()) {
+            result = self.heap.allocate_first_fit(layout);
+        }
+
+        let allocation = result.ok()?;
+
+        self.allocations += 1;
+        self.peak = self.peak.max(self.heap.used());
+
+        Some(allocation)
+    }
+
+    // `ptr` must come from `allocate` on this heap with the same `layout`
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.heap.deallocate(ptr, layout) };
+        self.allocations -= 1;
+    }
+
+    // Maps enough fresh frames at the top of the heap to hold at least
+    // `bytes` more, returning false when the range or physical memory is
+    // exhausted.
+    fn grow(&mut self, bytes: usize) -> bool {
+        let by = bytes
+            .max(HEAP_GROW_STEP)
+            .next_multiple_of(PAGE_SIZE as usize);
+
+        if self.heap.size() + by > self.max_size {
+            return false;
+        }
+
+        let top = self.heap.top() as u64;
+
+        if !map_pages(top, by) {
+            return false;
+        }
+
+        unsafe { self.heap.extend(by) };
+        self.grows += 1;
+
+        true
+    }
+
+    fn out_of_memory(&self, layout: Layout) -> OutOfMemory {
+        OutOfMemory {
+            layout,
+            heap: self.stats(),
+            frames: frame::stats(),
+        }
+    }
+
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.heap.size(),
+            used: self.heap.used(),
+            free: self.heap.free(),
+            peak: self.peak,
+            allocations: self.allocations,
+            grows: self.grows,
+        }
+    }
+}
+
+// Backs `[virt, virt + size)` with newly allocated frames. Pages mapped
+// before running out of frames are left in place; they still belong to the
+// heap's reserved range and are reused by the next successful grow.
+fn map_pages(virt: u64, size: usize) -> bool {
+    let flags = PageFlags::WRITABLE | PageFlags::NO_EXECUTE | PageFlags::GLOBAL;
+
+    for page in (virt..virt + size as u64).step_by(PAGE_SIZE as usize) {
+        if paging::translate(page).is_some() {
+            continue;
+        }
+
+        let Some(phys) = frame::allocate_frame() else {
+            return false;
+        };
+
+        if paging::map(page, phys, flags).is_err() {
+            frame::free(phys, 1);
+            return false;
+        }
+    }
+
+    true
+}
+
+static HEAP: SpinLock<HeapState> = SpinLock::new("heap", HeapState::new(HEAP_MAX_SIZE));
+
+/// Allocates `layout` from the heap, growing it if needed. Used by the slab
+/// allocator for objects too large for its size classes.
+///
+/// Panics with an [`OutOfMemory`] report when the heap can't grow any
+/// further.
+pub fn allocate(layout: Layout) -> *mut u8 {
+    let mut state = HEAP.lock();
+
+    if let Some(allocation) = state.allocate(layout) {
+        return allocation.as_ptr();
+    }
+
+    let report = state.out_of_memory(layout);
+    drop(state);
+
+    out_of_memory(&report)
+}
+
+// The panic handler prints through the console, which allocates, so the
+// report goes to the serial port first in case the panic can't be shown
+fn out_of_memory(report: &OutOfMemory) -> ! {
+    crate::serial_println!("{report}");
+    panic!("{report}");
+}
+
+/// Returns an allocation made by [`allocate`] to the heap.
+///
+/// # Safety
+///
+/// `ptr` must come from [`allocate`] with the same `layout`.
+pub unsafe fn deallocate(ptr: *mut u8, layout: Layout) {
+    if let Some(ptr) = NonNull::new(ptr) {
+        unsafe { HEAP.lock().deallocate(ptr, layout) };
+    }
+}
+
+/// Maps the initial heap and hands it to the allocator.
+pub fn init() {
+    assert!(
+        map_pages(HEAP_START, HEAP_INITIAL_SIZE),
+        "Failed to map the kernel heap"
+    );
+
+    let mut state = HEAP.lock();
+
+    unsafe { state.heap.init(HEAP_START as *mut u8, HEAP_INITIAL_SIZE) };
+}
+
+pub fn stats() -> HeapStats {
+    HEAP.lock().stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A heap of its own, in the unused range above the kernel heap, so
+    // allocations made elsewhere can't change what the tests observe
+    const TEST_HEAP_START: u64 = HEAP_START + HEAP_MAX_SIZE as u64;
+    const TEST_HEAP_INITIAL_SIZE: usize = 4 * PAGE_SIZE as usize;
+
+    fn test_heap(max_size: usize) -> HeapState {
+        assert!(map_pages(TEST_HEAP_START, TEST_HEAP_INITIAL_SIZE));
+
+        let mut state = HeapState::new(max_size);
+        unsafe {
+            state
+                .heap
+                .init(TEST_HEAP_START as *mut u8, TEST_HEAP_INITIAL_SIZE)
+        };
+
+        state
+    }
+
+    #[test_case]
+    fn grows_past_initial_size() {
+        let mut state = test_heap(2 * HEAP_GROW_STEP);
+
+        // Larger than everything mapped so far, so it can only fit after growing
+        let layout = Layout::from_size_align(TEST_HEAP_INITIAL_SIZE + 1, 8).unwrap();
+        let allocation = state.allocate(layout).expect("test heap didn't grow");
+
+        assert_eq!(state.grows, 1);
+        assert!(state.heap.size() >= TEST_HEAP_INITIAL_SIZE + layout.size());
+
+        let bytes = unsafe { core::slice::from_raw_parts_mut(allocation.as_ptr(), layout.size()) };
+        bytes.fill(0xA5);
+        assert!(bytes.iter().all(|&byte| byte == 0xA5));
+
+        unsafe { state.deallocate(allocation, layout) };
+        assert_eq!(state.allocations, 0);
+    }
+
+    #[test_case]
+    fn fails_past_maximum_size() {
+        let mut state = test_heap(HEAP_GROW_STEP);
+
+        let layout = Layout::from_size_align(2 * HEAP_GROW_STEP, 8).unwrap();
+        assert!(state.allocate(layout).is_none());
+        assert_eq!(state.allocations, 0);
+    }
+
+    // Collects formatted text in a fixed buffer, failing when it is full
+    struct FixedBuffer {
+        bytes: [u8; 512],
+        len: usize,
+    }
+
+    impl fmt::Write for FixedBuffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            let space = self.bytes.get_mut(self.len..end).ok_or(fmt::Error)?;
+            space.copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test_case]
+    fn reports_failed_growth_without_allocating() {
+        use core::fmt::Write;
+
+        let mut state = test_heap(HEAP_GROW_STEP);
+
+        // Only fits after growing, which the maximum size forbids
+        let layout = Layout::from_size_align(TEST_HEAP_INITIAL_SIZE + 1, 8).unwrap();
+        assert!(state.allocate(layout).is_none());
+
+        let report = state.out_of_memory(layout);
+        assert_eq!(report.layout, layout);
+        assert_eq!(report.heap.grows, 0);
+
+        let allocations = stats().allocations;
+        let mut text = FixedBuffer {
+            bytes: [0; 512],
+            len: 0,
+        };
+        write!(text, "{report}").unwrap();
+        assert_eq!(stats().allocations, allocations);
+
+        let text = core::str::from_utf8(&text.bytes[..text.len]).unwrap();
+        let expected = "Out of kernel heap memory allocating 16385 bytes aligned to 8\n";
+        assert!(text.starts_with(expected));
+        assert!(text.contains("Physical memory: "));
+    }
+}
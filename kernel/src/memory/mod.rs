@@ -0,0 +1,38 @@
+use spin::Once;
+
+use crate::boot;
+
+pub mod frame;
+pub mod heap;
+pub mod paging;
+pub mod slab;
+
+/// Size of a physical frame and of a base page.
+pub const PAGE_SIZE: u64 = 4096;
+
+// Offset of Limine's higher half direct map of physical memory
+static HHDM_OFFSET: Once<u64> = Once::new();
+
+/// Returns the virtual address physical address `phys` is mapped at in the
+/// higher half direct map.
+pub fn phys_to_virt(phys: u64) -> u64 {
+    phys + HHDM_OFFSET.get().expect("memory not initialized")
+}
+
+/// Sets up the higher half direct map, the physical frame allocator, the
+/// kernel's page tables and the kernel heap.
+pub fn init() {
+    let hhdm_response = boot::HHDM_REQUEST
+        .get_response()
+        .expect("Failed to get HHDM response");
+
+    HHDM_OFFSET.call_once(|| hhdm_response.offset());
+
+    let memory_map_response = boot::MEMORY_MAP_REQUEST
+        .get_response()
+        .expect("Failed to get memory map");
+
+    frame::init(memory_map_response.entries());
+    paging::init();
+    heap::init();
+}
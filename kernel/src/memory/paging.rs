@@ -0,0 +1,603 @@
+use core::ops::{BitOr, BitOrAssign};
+use core::{fmt, ptr};
+
+use limine::memory_map::EntryType;
+use spin::Once;
+
+use super::{PAGE_SIZE, frame, phys_to_virt};
+use crate::arch::x86_64::cpu::{self, CR0_WP, EFER_NXE, MSR_EFER, MSR_PAT};
+use crate::boot;
+use crate::sync::SpinLock;
+
+const ENTRIES_PER_TABLE: usize = 512;
+
+/// Size of a page mapped directly by a level 2 entry.
+pub const HUGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
+// Bits 12..52 of an entry hold the physical address of the next level
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// PAT memory types. Weakly uncacheable (UC-) lets the MTRRs make a range
+// write-combining
+const PAT_UNCACHEABLE: u64 = 0x00;
+const PAT_WRITE_COMBINING: u64 = 0x01;
+const PAT_WRITE_BACK: u64 = 0x06;
+const PAT_WEAKLY_UNCACHEABLE: u64 = 0x07;
+
+// Memory types of the eight PAT entries, which a page selects with its
+// write-through, cache disable and PAT bits. This is the power-on layout
+// with write-combining in place of write-through, so the write-through bit
+// alone selects it, in 4 KiB and huge pages alike
+const PAT_LAYOUT: [u64; 8] = [
+    PAT_WRITE_BACK,
+    PAT_WRITE_COMBINING,
+    PAT_WEAKLY_UNCACHEABLE,
+    PAT_UNCACHEABLE,
+    PAT_WRITE_BACK,
+    PAT_WRITE_COMBINING,
+    PAT_WEAKLY_UNCACHEABLE,
+    PAT_UNCACHEABLE,
+];
+
+/// Page table entry flags.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+    pub const PRESENT: Self = Self(1 << 0);
+    pub const WRITABLE: Self = Self(1 << 1);
+    /// Write-combining instead of write-back, for framebuffers. This is the
+    /// write-through bit, which [`init`] reprograms the PAT for.
+    pub const WRITE_COMBINING: Self = Self(1 << 3);
+    pub const NO_CACHE: Self = Self(1 << 4);
+    pub const HUGE: Self = Self(1 << 7);
+    pub const GLOBAL: Self = Self(1 << 8);
+    pub const NO_EXECUTE: Self = Self(1 << 63);
+
+    // The bits that select a page's memory type
+    const MEMORY_TYPE: Self = Self(Self::WRITE_COMBINING.0 | Self::NO_CACHE.0);
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for PageFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Debug for PageFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PageFlags({:#x})", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// The page is already mapped.
+    AlreadyMapped,
+    /// The page lies inside an existing huge page mapping.
+    HugePage,
+    /// No frame was available for an intermediate page table.
+    OutOfMemory,
+    /// The page isn't mapped.
+    NotMapped,
+}
+
+#[repr(C, align(4096))]
+struct PageTable {
+    entries: [u64; ENTRIES_PER_TABLE],
+}
+
+// Index into the page table at `level` (4 = PML4, 1 = PT) for `virt`
+fn table_index(virt: u64, level: u32) -> usize {
+    ((virt >> (12 + 9 * (level - 1))) & 0x1FF) as usize
+}
+
+// Size of the page an entry at `level` maps directly
+fn page_size(level: u32) -> u64 {
+    1 << (12 + 9 * (level - 1))
+}
+
+fn table_mut(phys: u64) -> &'static mut PageTable {
+    unsafe { &mut *(phys_to_virt(phys) as *mut PageTable) }
+}
+
+fn allocate_table() -> Option<u64> {
+    let phys = frame::allocate_frame()?;
+
+    unsafe { ptr::write_bytes(phys_to_virt(phys) as *mut u8, 0, PAGE_SIZE as usize) };
+
+    Some(phys)
+}
+
+/// A 4-level page table hierarchy.
+pub struct AddressSpace {
+    pml4: u64,
+}
+
+impl AddressSpace {
+    /// Creates an empty address space.
+    pub fn new() -> Option<Self> {
+        Some(Self {
+            pml4: allocate_table()?,
+        })
+    }
+
+    // Returns the entry at `target_level` for `virt`, creating intermediate
+    // tables on the way down.
+    fn entry_mut(&mut self, virt: u64, target_level: u32) -> Result<&'static mut u64, MapError> {
+        let mut table = table_mut(self.pml4);
+
+        for level in (target_level + 1..=4).rev() {
+            let entry = &mut table.entries[table_index(virt, level)];
+
+            if *entry & PageFlags::PRESENT.bits() == 0 {
+                let next = allocate_table().ok_or(MapError::OutOfMemory)?;
+
+                // Intermediate tables are permissive; leaf entries restrict access
+                *entry = next | (PageFlags::PRESENT | PageFlags::WRITABLE).bits();
+            } else if *entry & PageFlags::HUGE.bits() != 0 {
+                return Err(MapError::HugePage);
+            }
+
+            table = table_mut(*entry & ADDRESS_MASK);
+        }
+
+        Ok(&mut table.entries[table_index(virt, target_level)])
+    }
+
+    fn map_at_level(
+        &mut self,
+        virt: u64,
+        phys: u64,
+        flags: PageFlags,
+        level: u32,
+    ) -> Result<(), MapError> {
+        let entry = self.entry_mut(virt, level)?;
+
+        if *entry & PageFlags::PRESENT.bits() != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        *entry = phys | (flags | PageFlags::PRESENT).bits();
+
+        Ok(())
+    }
+
+    /// Maps the 4 KiB page at `virt` to the frame at `phys`.
+    pub fn map(&mut self, virt: u64, phys: u64, flags: PageFlags) -> Result<(), MapError> {
+        self.map_at_level(virt, phys, flags, 1)
+    }
+
+    /// Maps the 2 MiB page at `virt` to the 2 MiB aligned region at `phys`.
+    pub fn map_huge(&mut self, virt: u64, phys: u64, flags: PageFlags) -> Result<(), MapError> {
+        self.map_at_level(virt, phys, flags | PageFlags::HUGE, 2)
+    }
+
+    /// Maps `[phys, phys + size)` at `virt`, using 2 MiB pages wherever both
+    /// addresses are suitably aligned. Pages that already map the same memory
+    /// keep their permissions but take the memory type `flags` asks for,
+    /// splitting a huge page when only part of it changes. Returns whether
+    /// an existing mapping changed, leaving TLB entries to flush.
+    pub fn map_range(
+        &mut self,
+        virt: u64,
+        phys: u64,
+        size: u64,
+        flags: PageFlags,
+    ) -> Result<bool, MapError> {
+        let memory_type = PageFlags::MEMORY_TYPE.bits();
+        let mut changed = false;
+        let mut offset = 0;
+
+        while offset < size {
+            let (virt, phys) = (virt + offset, phys + offset);
+            let remaining = size - offset;
+
+            let step = match self.find_leaf(virt) {
+                // Nothing below `level` is mapped, so a huge page fits there
+                // unless `level` is the last one
+                Err(level) => {
+                    let huge = level >= 2
+                        && virt.is_multiple_of(HUGE_PAGE_SIZE)
+                        && phys.is_multiple_of(HUGE_PAGE_SIZE)
+                        && remaining >= HUGE_PAGE_SIZE;
+
+                    if huge {
+                        self.map_huge(virt, phys, flags)?;
+                        HUGE_PAGE_SIZE
+                    } else {
+                        self.map(virt, phys, flags)?;
+                        PAGE_SIZE
+                    }
+                }
+                Ok((entry, level)) => {
+                    let page_size = page_size(level);
+                    let page_offset = virt & (page_size - 1);
+
+                    if (*entry & ADDRESS_MASK & !(page_size - 1)) + page_offset != phys {
+                        return Err(MapError::AlreadyMapped);
+                    }
+
+                    if *entry & memory_type != flags.bits() & memory_type {
+                        if page_offset != 0 || remaining < page_size {
+                            // Only 2 MiB pages are ever made, never 1 GiB ones
+                            if level != 2 {
+                                return Err(MapError::HugePage);
+                            }
+
+                            split_huge_page(entry)?;
+                            continue;
+                        }
+
+                        *entry = (*entry & !memory_type) | (flags.bits() & memory_type);
+                        changed = true;
+                    }
+
+                    (page_size - page_offset).min(remaining)
+                }
+            };
+
+            offset += step;
+        }
+
+        Ok(changed)
+    }
+
+    // Returns the entry that maps `virt` and its level: 1 for a 4 KiB page,
+    // 2 or 3 for a huge one. Without one, returns the level whose entry is
+    // missing.
+    fn find_leaf(&self, virt: u64) -> Result<(&'static mut u64, u32), u32> {
+        let mut table = table_mut(self.pml4);
+
+        for level in (2..=4).rev() {
+            let entry = &mut table.entries[table_index(virt, level)];
+
+            if *entry & PageFlags::PRESENT.bits() == 0 {
+                return Err(level);
+            }
+
+            // Level 2 and 3 entries can map 2 MiB and 1 GiB pages directly
+            if level <= 3 && *entry & PageFlags::HUGE.bits() != 0 {
+                return Ok((entry, level));
+            }
+
+            table = table_mut(*entry & ADDRESS_MASK);
+        }
+
+        let entry = &mut table.entries[table_index(virt, 1)];
+
+        if *entry & PageFlags::PRESENT.bits() == 0 {
+            return Err(1);
+        }
+
+        Ok((entry, 1))
+    }
+
+    /// Removes the 4 KiB page at `virt`, returning the frame it mapped. The
+    /// caller flushes it from the TLB.
+    pub fn unmap(&mut self, virt: u64) -> Result<u64, MapError> {
+        let (entry, level) = self.find_leaf(virt).map_err(|_| MapError::NotMapped)?;
+
+        if level != 1 {
+            return Err(MapError::HugePage);
+        }
+
+        let phys = *entry & ADDRESS_MASK;
+        *entry = 0;
+
+        Ok(phys)
+    }
+
+    /// Returns the physical address `virt` maps to, following huge pages.
+    pub fn translate(&self, virt: u64) -> Option<u64> {
+        let (entry, level) = self.find_leaf(virt).ok()?;
+        let page_size = page_size(level);
+
+        Some((*entry & ADDRESS_MASK & !(page_size - 1)) + (virt & (page_size - 1)))
+    }
+
+    /// Loads this address space into CR3.
+    ///
+    /// # Safety
+    ///
+    /// The address space must map the running code, its stack and all data
+    /// that is accessed afterwards.
+    pub unsafe fn activate(&self) {
+        unsafe { cpu::write_cr3(self.pml4) };
+    }
+}
+
+// Replaces the 2 MiB page `entry` maps with a table of 4 KiB pages mapping
+// the same memory with the same flags
+fn split_huge_page(entry: &mut u64) -> Result<(), MapError> {
+    let table_phys = allocate_table().ok_or(MapError::OutOfMemory)?;
+    let base = *entry & ADDRESS_MASK & !(HUGE_PAGE_SIZE - 1);
+    let flags = *entry & !ADDRESS_MASK & !PageFlags::HUGE.bits();
+
+    for (index, small) in table_mut(table_phys).entries.iter_mut().enumerate() {
+        *small = (base + index as u64 * PAGE_SIZE) | flags;
+    }
+
+    // Like every intermediate table, permissive; the new entries restrict
+    *entry = table_phys | (PageFlags::PRESENT | PageFlags::WRITABLE).bits();
+
+    Ok(())
+}
+
+/// Hook run after a mapping is removed or changed, so other CPUs can drop
+/// stale TLB entries for `[virt, virt + pages * PAGE_SIZE)`.
+pub type ShootdownHook = fn(virt: u64, pages: usize);
+
+static KERNEL_SPACE: Once<SpinLock<AddressSpace>> = Once::new();
+static SHOOTDOWN_HOOK: Once<ShootdownHook> = Once::new();
+
+/// Installs the hook used to invalidate TLB entries on other CPUs.
+pub fn set_shootdown_hook(hook: ShootdownHook) {
+    SHOOTDOWN_HOOK.call_once(|| hook);
+}
+
+/// Flushes `pages` pages starting at `virt` from this CPU's TLB and asks
+/// every other CPU to do the same.
+pub fn flush(virt: u64, pages: usize) {
+    for page in 0..pages as u64 {
+        cpu::invlpg(virt + page * PAGE_SIZE);
+    }
+
+    if let Some(hook) = SHOOTDOWN_HOOK.get() {
+        hook(virt, pages);
+    }
+}
+
+fn kernel_space() -> &'static SpinLock<AddressSpace> {
+    KERNEL_SPACE.get().expect("paging not initialized")
+}
+
+/// Maps the 4 KiB page at `virt` to `phys` in the kernel address space.
+pub fn map(virt: u64, phys: u64, flags: PageFlags) -> Result<(), MapError> {
+    kernel_space().lock().map(virt, phys, flags)
+}
+
+/// Unmaps the 4 KiB page at `virt` from the kernel address space and flushes
+/// it from every TLB, returning the frame it mapped.
+// Nothing unmaps kernel pages yet
+#[allow(dead_code)]
+pub fn unmap(virt: u64) -> Result<u64, MapError> {
+    let phys = kernel_space().lock().unmap(virt)?;
+
+    flush(virt, 1);
+
+    Ok(phys)
+}
+
+/// Translates a kernel virtual address to a physical address.
+pub fn translate(virt: u64) -> Option<u64> {
+    kernel_space().lock().translate(virt)
+}
+
+/// Maps `[phys, phys + size)` into the HHDM with `flags` and returns the
+/// virtual address of `phys`. Pages that are already mapped keep their
+/// permissions, but take the memory type in `flags`.
+///
+/// Needed for regions Limine's memory map doesn't report as memory, such as
+/// firmware tables in reserved ranges.
+pub fn map_physical(phys: u64, size: u64, flags: PageFlags) -> u64 {
+    let start = phys & !(PAGE_SIZE - 1);
+    let end = (phys + size).next_multiple_of(PAGE_SIZE);
+
+    let changed = kernel_space()
+        .lock()
+        .map_range(phys_to_virt(start), start, end - start, flags)
+        .expect("Failed to map physical memory");
+
+    // Other CPUs may still use the old memory type
+    if changed {
+        flush(phys_to_virt(start), ((end - start) / PAGE_SIZE) as usize);
+    }
+
+    phys_to_virt(phys)
+}
+
+/// Maps a device's registers into the HHDM as uncached memory and returns
+/// their virtual address.
+pub fn map_mmio(phys: u64, size: u64) -> u64 {
+    map_physical(
+        phys,
+        size,
+        PageFlags::WRITABLE | PageFlags::NO_CACHE | PageFlags::NO_EXECUTE,
+    )
+}
+
+// Honor NX and make the kernel respect read-only pages as well. Both are
+// per-CPU settings
+fn enable_protection() {
+    unsafe {
+        cpu::wrmsr(MSR_EFER, cpu::rdmsr(MSR_EFER) | EFER_NXE);
+        cpu::write_cr0(cpu::read_cr0() | CR0_WP);
+    }
+}
+
+// Loads PAT_LAYOUT, which every CPU needs the same. Called before switching
+// to our page tables, which flushes any translations made with the old one
+fn program_pat() {
+    let pat = PAT_LAYOUT
+        .iter()
+        .enumerate()
+        .fold(0, |pat, (entry, memory_type)| {
+            pat | memory_type << (entry * 8)
+        });
+
+    unsafe { cpu::wrmsr(MSR_PAT, pat) };
+}
+
+unsafe extern "C" {
+    static __kernel_text_start: u8;
+    static __kernel_text_end: u8;
+    static __kernel_rodata_start: u8;
+    static __kernel_rodata_end: u8;
+    static __kernel_data_start: u8;
+    static __kernel_data_end: u8;
+}
+
+/// Builds the kernel's own page tables and switches to them.
+///
+/// The kernel image is mapped section by section with W^X permissions, and
+/// every memory map region Limine gave us is mapped into the HHDM at the same
+/// offset Limine used, so existing HHDM pointers stay valid.
+pub fn init() {
+    let kernel_address = boot::EXECUTABLE_ADDRESS_REQUEST
+        .get_response()
+        .expect("Failed to get kernel address response");
+
+    let memory_map_response = boot::MEMORY_MAP_REQUEST
+        .get_response()
+        .expect("Failed to get memory map");
+
+    enable_protection();
+    program_pat();
+
+    let mut space = AddressSpace::new().expect("Failed to allocate the kernel PML4");
+
+    let sections = [
+        (
+            &raw const __kernel_text_start,
+            &raw const __kernel_text_end,
+            PageFlags::GLOBAL,
+        ),
+        (
+            &raw const __kernel_rodata_start,
+            &raw const __kernel_rodata_end,
+            PageFlags::GLOBAL | PageFlags::NO_EXECUTE,
+        ),
+        (
+            &raw const __kernel_data_start,
+            &raw const __kernel_data_end,
+            PageFlags::GLOBAL | PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
+        ),
+    ];
+
+    for (start, end, flags) in sections {
+        let start = start as u64 & !(PAGE_SIZE - 1);
+        let end = (end as u64).next_multiple_of(PAGE_SIZE);
+
+        for virt in (start..end).step_by(PAGE_SIZE as usize) {
+            let phys = virt - kernel_address.virtual_base() + kernel_address.physical_base();
+
+            space
+                .map(virt, phys, flags)
+                .expect("Failed to map the kernel image");
+        }
+    }
+
+    for entry in memory_map_response.entries() {
+        let flags = match entry.entry_type {
+            EntryType::USABLE
+            | EntryType::BOOTLOADER_RECLAIMABLE
+            | EntryType::EXECUTABLE_AND_MODULES
+            | EntryType::ACPI_RECLAIMABLE
+            | EntryType::ACPI_NVS => PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
+            EntryType::FRAMEBUFFER => {
+                PageFlags::WRITABLE | PageFlags::NO_EXECUTE | PageFlags::WRITE_COMBINING
+            }
+            _ => continue,
+        };
+
+        let start = entry.base & !(PAGE_SIZE - 1);
+        let end = (entry.base + entry.length).next_multiple_of(PAGE_SIZE);
+
+        space
+            .map_range(phys_to_virt(start), start, end - start, flags)
+            .expect("Failed to map the HHDM");
+    }
+
+    unsafe { space.activate() };
+
+    KERNEL_SPACE.call_once(|| SpinLock::new("kernel address space", space));
+}
+
+/// Switches an application processor to the kernel page tables built by
+/// [`init`].
+pub fn init_ap() {
+    enable_protection();
+    program_pat();
+
+    // The kernel address space maps the kernel image and the HHDM, which
+    // holds the stack Limine started this CPU on
+    unsafe { kernel_space().lock().activate() };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn map_range_changes_memory_type_of_existing_pages() {
+        // Never activated, so the addresses only need to be aligned
+        let mut space = AddressSpace::new().unwrap();
+        let (virt, phys) = (0xFFFF_C000_0000_0000, 0x4000_0000);
+        let flags = PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+
+        assert_eq!(
+            space.map_range(virt, phys, HUGE_PAGE_SIZE, flags),
+            Ok(false)
+        );
+        assert_eq!(space.find_leaf(virt).map(|(_, level)| level), Ok(2));
+
+        // Mapping it again the same way changes nothing
+        assert_eq!(
+            space.map_range(virt, phys, HUGE_PAGE_SIZE, flags),
+            Ok(false)
+        );
+
+        // A different memory type for one page splits the huge page
+        let uncached = flags | PageFlags::NO_CACHE;
+        assert_eq!(
+            space.map_range(virt + PAGE_SIZE, phys + PAGE_SIZE, PAGE_SIZE, uncached),
+            Ok(true)
+        );
+
+        let (entry, level) = space.find_leaf(virt + PAGE_SIZE).unwrap();
+        assert_eq!(level, 1);
+        assert_ne!(*entry & PageFlags::NO_CACHE.bits(), 0);
+
+        let (entry, _) = space.find_leaf(virt).unwrap();
+        assert_eq!(*entry & PageFlags::NO_CACHE.bits(), 0);
+        assert_eq!(space.translate(virt + 0x1234), Some(phys + 0x1234));
+
+        // Mapping other memory over it fails
+        assert_eq!(
+            space.map_range(virt, phys + HUGE_PAGE_SIZE, PAGE_SIZE, flags),
+            Err(MapError::AlreadyMapped)
+        );
+    }
+
+    #[test_case]
+    fn unmap_removes_the_mapping() {
+        let mut space = AddressSpace::new().unwrap();
+        let (virt, phys) = (0xFFFF_C000_0000_0000, 0x4000_0000);
+
+        space.map(virt, phys, PageFlags::WRITABLE).unwrap();
+        assert_eq!(space.translate(virt), Some(phys));
+
+        assert_eq!(space.unmap(virt), Ok(phys));
+        assert_eq!(space.translate(virt), None);
+        assert_eq!(space.unmap(virt), Err(MapError::NotMapped));
+
+        // The freed slot can be mapped again
+        space
+            .map(virt, phys + PAGE_SIZE, PageFlags::WRITABLE)
+            .unwrap();
+        assert_eq!(space.translate(virt), Some(phys + PAGE_SIZE));
+    }
+}
@@ -0,0 +1,233 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{PAGE_SIZE, frame, heap, phys_to_virt};
+use crate::println;
+use crate::sync::SpinLock;
+
+/// Object sizes served by the slab caches. Anything larger, or more strictly
+/// aligned, goes to the large-object heap.
+pub const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// Free objects are linked through their own first word
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+struct Cache {
+    free: Option<NonNull<FreeObject>>,
+}
+
+// The free list only points into slab pages owned by this cache
+unsafe impl Send for Cache {}
+
+impl Cache {
+    const fn new() -> Self {
+        Self { free: None }
+    }
+
+    // Carves a fresh page into objects of `size` bytes and pushes them onto
+    // the free list.
+    fn refill(&mut self, size: usize) -> bool {
+        let Some(phys) = frame::allocate_frame() else {
+            return false;
+        };
+
+        let page = phys_to_virt(phys) as usize;
+
+        for object in (page..page + PAGE_SIZE as usize).step_by(size).rev() {
+            let object = object as *mut FreeObject;
+
+            unsafe { object.write(FreeObject { next: self.free }) };
+            self.free = NonNull::new(object);
+        }
+
+        true
+    }
+
+    fn pop(&mut self) -> Option<NonNull<u8>> {
+        let object = self.free?;
+
+        self.free = unsafe { object.as_ref().next };
+
+        Some(object.cast())
+    }
+
+    fn push(&mut self, object: NonNull<u8>) {
+        let object = object.cast::<FreeObject>();
+
+        unsafe { object.write(FreeObject { next: self.free }) };
+        self.free = Some(object);
+    }
+}
+
+/// Allocation counters for one size class.
+#[derive(Clone, Copy, Debug)]
+pub struct ClassStats {
+    pub size: usize,
+    /// Allocations served since boot.
+    pub allocations: usize,
+    /// Frees since boot.
+    pub frees: usize,
+    /// Pages carved into objects of this size.
+    pub pages: usize,
+}
+
+impl ClassStats {
+    /// Objects currently allocated.
+    pub fn live(&self) -> usize {
+        // The counters are read one at a time, so a snapshot can see a free
+        // before the allocation it matches
+        self.allocations.saturating_sub(self.frees)
+    }
+}
+
+impl fmt::Display for ClassStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>5} B: {:>8} live, {:>10} allocs, {:>10} frees, {:>5} pages",
+            self.size,
+            self.live(),
+            self.allocations,
+            self.frees,
+            self.pages,
+        )
+    }
+}
+
+struct Counters {
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+    pages: AtomicUsize,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            allocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            pages: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// The kernel's global allocator: power-of-two slab caches for small objects
+/// on top of the growable heap for large ones.
+pub struct SlabAllocator {
+    caches: [SpinLock<Cache>; SIZE_CLASSES.len()],
+    counters: [Counters; SIZE_CLASSES.len()],
+}
+
+#[global_allocator]
+static ALLOCATOR: SlabAllocator = SlabAllocator {
+    caches: [const { SpinLock::new("slab cache", Cache::new()) }; SIZE_CLASSES.len()],
+    counters: [const { Counters::new() }; SIZE_CLASSES.len()],
+};
+
+// Objects are naturally aligned to their size class, since slab pages are
+// page aligned and every class divides the page size
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+
+    SIZE_CLASSES.iter().position(|&class| size <= class)
+}
+
+impl SlabAllocator {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = size_class(layout) else {
+            return heap::allocate(layout);
+        };
+
+        let mut cache = self.caches[class].lock();
+
+        let object = match cache.pop() {
+            Some(object) => object,
+            None => {
+                // Out of physical memory; the caller reports the failure
+                if !cache.refill(SIZE_CLASSES[class]) {
+                    return ptr::null_mut();
+                }
+
+                self.counters[class].pages.fetch_add(1, Ordering::Relaxed);
+                cache.pop().expect("refilled slab cache is empty")
+            }
+        };
+
+        self.counters[class]
+            .allocations
+            .fetch_add(1, Ordering::Relaxed);
+
+        object.as_ptr()
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        let Some(class) = size_class(layout) else {
+            return unsafe { heap::deallocate(ptr, layout) };
+        };
+
+        if let Some(object) = NonNull::new(ptr) {
+            self.caches[class].lock().push(object);
+            self.counters[class].frees.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.deallocate(ptr, layout) }
+    }
+}
+
+/// Returns the counters of every size class, smallest first.
+pub fn stats() -> [ClassStats; SIZE_CLASSES.len()] {
+    core::array::from_fn(|class| {
+        let counters = &ALLOCATOR.counters[class];
+
+        ClassStats {
+            size: SIZE_CLASSES[class],
+            allocations: counters.allocations.load(Ordering::Relaxed),
+            frees: counters.frees.load(Ordering::Relaxed),
+            pages: counters.pages.load(Ordering::Relaxed),
+        }
+    })
+}
+
+/// Prints the slab counters and heap statistics, for profiling.
+pub fn dump() {
+    // Snapshot first; printing allocates and would skew the numbers
+    let classes = stats();
+    let heap = heap::stats();
+
+    println!("Slab caches:");
+    for class in classes {
+        println!("  {class}");
+    }
+    println!("Large objects: {heap}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn reuses_freed_objects() {
+        // A cache of its own, so allocations made elsewhere can't take the
+        // freed object first
+        let mut cache = Cache::new();
+        assert!(cache.refill(64));
+
+        let first = cache.pop().unwrap();
+        assert_eq!(first.as_ptr() as usize % 64, 0);
+        cache.push(first);
+
+        // The most recently freed object is handed back first
+        assert_eq!(cache.pop(), Some(first));
+    }
+}
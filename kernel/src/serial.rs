@@ -0,0 +1,238 @@
+use core::fmt::{self, Write};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use crossbeam_queue::ArrayQueue;
+use spin::Once;
+
+use crate::arch::x86_64::interrupts::{self, InterruptFrame};
+use crate::arch::x86_64::port::{inb, outb};
+use crate::sync::SpinLock;
+
+// I/O port base of the first serial port
+const COM1: u16 = 0x3F8;
+// Legacy ISA IRQ of the first serial port
+const COM1_IRQ: u8 = 4;
+
+// Register offsets relative to the port base
+const DATA: u16 = 0; // Receive/transmit buffer (divisor low byte when DLAB is set)
+const INTERRUPT_ENABLE: u16 = 1; // Divisor high byte when DLAB is set
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+// Line status register bits
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+// Interrupt enable register bits
+const INTERRUPT_DATA_AVAILABLE: u8 = 1 << 0;
+
+// 115200 / DIVISOR gives the baud rate, so 1 is the fastest the UART can go
+const DIVISOR: u16 = 1;
+
+// Upper bound on how long we poll for the transmitter before dropping a byte,
+// so a wedged or missing UART can never hang the kernel.
+const TRANSMIT_SPIN_LIMIT: usize = 100_000;
+
+// Received bytes beyond this many unread ones are dropped
+const INPUT_QUEUE_CAPACITY: usize = 256;
+
+/// A 16550-compatible UART driven through x86 port I/O.
+pub struct SerialPort {
+    base: u16,
+    present: bool,
+}
+
+/// The COM1 serial port every `print!` is mirrored to.
+pub static SERIAL: SpinLock<SerialPort> = SpinLock::new("serial", SerialPort::new(COM1));
+
+impl SerialPort {
+    /// Creates an uninitialized serial port at the given I/O port base.
+    pub const fn new(base: u16) -> Self {
+        Self {
+            base,
+            present: false,
+        }
+    }
+
+    /// Programs the UART for 115200 baud, 8N1 with FIFOs enabled.
+    ///
+    /// The chip is verified with a loopback test first; if it does not echo
+    /// back, the port is marked absent and all further output is discarded.
+    pub fn init(&mut self) {
+        unsafe {
+            outb(self.base + INTERRUPT_ENABLE, 0x00); // Disable all UART interrupts
+            outb(self.base + LINE_CONTROL, 0x80); // Enable DLAB to set the divisor
+            outb(self.base + DATA, DIVISOR as u8);
+            outb(self.base + INTERRUPT_ENABLE, (DIVISOR >> 8) as u8);
+            outb(self.base + LINE_CONTROL, 0x03); // 8 bits, no parity, one stop bit
+            outb(self.base + FIFO_CONTROL, 0xC7); // Enable and clear FIFOs, 14-byte threshold
+            outb(self.base + MODEM_CONTROL, 0x0B); // DTR, RTS and OUT2
+
+            // Loopback test: anything we send should come straight back
+            outb(self.base + MODEM_CONTROL, 0x1E);
+            outb(self.base + DATA, 0xAE);
+            self.present = inb(self.base + DATA) == 0xAE;
+
+            // Back to normal operation
+            outb(self.base + MODEM_CONTROL, 0x0F);
+        }
+    }
+
+    fn line_status(&self) -> u8 {
+        unsafe { inb(self.base + LINE_STATUS) }
+    }
+
+    /// Sends a single byte, waiting for the transmit holding register to drain.
+    pub fn send(&mut self, byte: u8) {
+        if !self.present {
+            return;
+        }
+
+        for _ in 0..TRANSMIT_SPIN_LIMIT {
+            if self.line_status() & LINE_STATUS_TRANSMIT_EMPTY != 0 {
+                unsafe { outb(self.base + DATA, byte) };
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the next received byte, if one is waiting.
+    pub fn receive(&mut self) -> Option<u8> {
+        if !self.present || self.line_status() & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+
+        Some(unsafe { inb(self.base + DATA) })
+    }
+
+    fn enable_receive_interrupt(&mut self) {
+        unsafe { outb(self.base + INTERRUPT_ENABLE, INTERRUPT_DATA_AVAILABLE) };
+    }
+}
+
+impl Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // Terminals expect CRLF line endings
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Initializes COM1. Safe to call before the heap or framebuffer exist.
+pub fn init() {
+    SERIAL.lock().init();
+}
+
+// Serial-only print macros, for output that should not reach the framebuffer
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    SERIAL.lock().write_fmt(args).unwrap();
+}
+
+/// Returns a writer to COM1 that doesn't take [`SERIAL`], for the panic
+/// handler: the lock may be held by code that will never resume, on this
+/// CPU or on one that was halted. The report may interleave with what that
+/// code was sending.
+pub fn panic_writer() -> SerialPort {
+    // A missing UART reads as all ones, which looks like an empty
+    // transmitter, so bytes sent to one are dropped without waiting
+    SerialPort {
+        base: COM1,
+        present: true,
+    }
+}
+
+static INPUT: Once<ArrayQueue<u8>> = Once::new();
+
+// Task waiting in `next_byte`
+static WAKER: SpinLock<Option<Waker>> = SpinLock::new("serial waker", None);
+
+fn serial_interrupt(_frame: &mut InterruptFrame) {
+    let Some(input) = INPUT.get() else {
+        return;
+    };
+
+    // Reading every waiting byte clears the interrupt
+    while let Some(byte) = SERIAL.lock().receive() {
+        if input.push(byte).is_err() {
+            break;
+        }
+    }
+
+    if let Some(waker) = WAKER.lock().take() {
+        waker.wake();
+    }
+}
+
+/// Returns the next byte received on COM1 without waiting.
+pub fn try_read_byte() -> Option<u8> {
+    INPUT.get()?.pop()
+}
+
+/// Future returned by [`next_byte`].
+pub struct NextByte {
+    _private: (),
+}
+
+impl Future for NextByte {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<u8> {
+        if let Some(byte) = try_read_byte() {
+            return Poll::Ready(byte);
+        }
+
+        // Registered before checking again, so a byte arriving in between
+        // still wakes the task
+        *WAKER.lock() = Some(context.waker().clone());
+
+        match try_read_byte() {
+            Some(byte) => {
+                WAKER.lock().take();
+                Poll::Ready(byte)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Completes with the next byte received on COM1. Only one task should
+/// wait for input at a time.
+pub fn next_byte() -> NextByte {
+    NextByte { _private: () }
+}
+
+/// Starts queueing bytes received on COM1 from its interrupt. Needs the heap
+/// and interrupts.
+pub fn init_input() {
+    let mut serial = SERIAL.lock();
+
+    if !serial.present {
+        return;
+    }
+
+    INPUT.call_once(|| ArrayQueue::new(INPUT_QUEUE_CAPACITY));
+    interrupts::register_irq(COM1_IRQ, serial_interrupt);
+    serial.enable_receive_interrupt();
+}
@@ -0,0 +1,293 @@
+use alloc::format;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use crate::acpi::{self, mcfg};
+use crate::arch::x86_64::smp;
+use crate::memory::{frame, paging, slab};
+use crate::sync::SpinLock;
+use crate::{framebuffer, print, println, task, time};
+
+/// A shell command. `run` gets the words after the command's name. It runs
+/// on the executor's thread, so it shouldn't block for long.
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    /// One line shown by `help`.
+    pub help: &'static str,
+    pub run: fn(&[&str]),
+}
+
+static COMMANDS: SpinLock<Vec<Command>> = SpinLock::new("shell commands", Vec::new());
+
+/// Adds a command to the shell, replacing any with the same name.
+pub fn register(command: Command) {
+    let mut commands = COMMANDS.lock();
+
+    commands.retain(|existing| existing.name != command.name);
+    commands.push(command);
+    commands.sort_unstable_by_key(|command| command.name);
+}
+
+/// Returns the command named `name`, if one is registered.
+pub fn find(name: &str) -> Option<Command> {
+    COMMANDS
+        .lock()
+        .iter()
+        .find(|command| command.name == name)
+        .copied()
+}
+
+/// Names of every registered command, sorted.
+pub fn names() -> Vec<&'static str> {
+    COMMANDS.lock().iter().map(|command| command.name).collect()
+}
+
+fn help(_args: &[&str]) {
+    // Copied so the registry isn't locked while printing
+    let commands = COMMANDS.lock().clone();
+    let width = commands
+        .iter()
+        .map(|command| command.name.len())
+        .max()
+        .unwrap_or(0);
+
+    for command in commands {
+        println!("  {:width$}  {}", command.name, command.help);
+    }
+}
+
+fn echo(args: &[&str]) {
+    let mut words = args.iter();
+
+    if let Some(first) = words.next() {
+        print!("{first}");
+    }
+    for word in words {
+        print!(" {word}");
+    }
+    println!();
+}
+
+fn mem(_args: &[&str]) {
+    println!("Physical memory: {}", frame::stats());
+    slab::dump();
+}
+
+fn cpus(_args: &[&str]) {
+    println!("{} CPUs online", smp::cpu_count());
+}
+
+fn threads(_args: &[&str]) {
+    println!("{:>4}  {:<8}  name", "id", "state");
+
+    for thread in task::all() {
+        let state = format!("{:?}", thread.state());
+        println!("{:>4}  {state:<8}  {}", thread.id(), thread.name());
+    }
+}
+
+fn uptime(_args: &[&str]) {
+    let uptime = time::uptime();
+    let seconds = uptime.as_secs();
+
+    println!(
+        "up {}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        uptime.subsec_millis()
+    );
+}
+
+fn date(_args: &[&str]) {
+    println!("{}", time::now());
+}
+
+fn clear(_args: &[&str]) {
+    // Home the cursor, then erase the screen and the scrollback
+    print!("\x1B[H\x1B[2J\x1B[3J");
+}
+
+fn log_level(args: &[&str]) {
+    match args {
+        [] => println!("{}", log::max_level()),
+        [level] => match log::LevelFilter::from_str(level) {
+            Ok(level) => log::set_max_level(level),
+            Err(_) => println!("log-level: unknown level {level}"),
+        },
+        _ => println!("usage: log-level [off|error|warn|info|debug|trace]"),
+    }
+}
+
+fn reboot(_args: &[&str]) {
+    acpi::reboot();
+}
+
+fn shutdown(_args: &[&str]) {
+    acpi::shutdown();
+}
+
+// Offsets into a function's configuration space
+const PCI_VENDOR_ID: u64 = 0x00;
+const PCI_CLASS: u64 = 0x08;
+const PCI_HEADER_TYPE: u64 = 0x0C;
+
+// Header type bit marking a device with more than one function
+const PCI_MULTIFUNCTION: u32 = 1 << 23;
+
+fn lspci(_args: &[&str]) {
+    let entries = mcfg::entries();
+
+    if entries.is_empty() {
+        println!("lspci: no PCI Express configuration space (no MCFG)");
+        return;
+    }
+
+    for entry in entries {
+        // Each bus has 1 MiB of configuration space
+        let buses = u64::from(entry.end_bus - entry.start_bus) + 1;
+        let base = paging::map_mmio(entry.base_address, buses << 20);
+
+        let read = |bus: u8, device: u8, function: u8, offset: u64| {
+            let address = base
+                + (u64::from(bus - entry.start_bus) << 20
+                    | u64::from(device) << 15
+                    | u64::from(function) << 12)
+                + offset;
+            unsafe { (address as *const u32).read_volatile() }
+        };
+
+        for bus in entry.start_bus..=entry.end_bus {
+            for device in 0..32 {
+                for function in 0..8 {
+                    let id = read(bus, device, function, PCI_VENDOR_ID);
+
+                    // No device answers reads with all ones
+                    if id & 0xFFFF == 0xFFFF {
+                        if function == 0 {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let class = read(bus, device, function, PCI_CLASS);
+                    println!(
+                        "{:04x}:{:02x}:{:02x}.{} {:04x}:{:04x} class {:04x}",
+                        entry.segment,
+                        bus,
+                        device,
+                        function,
+                        id & 0xFFFF,
+                        id >> 16,
+                        class >> 16
+                    );
+
+                    let header = read(bus, device, function, PCI_HEADER_TYPE);
+                    if function == 0 && header & PCI_MULTIFUNCTION == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn displays(_args: &[&str]) {
+    let reported = framebuffer::reported();
+
+    if reported.is_empty() {
+        println!("displays: no framebuffers");
+        return;
+    }
+
+    // The modes are what `cargo xtask run --resolution` can pick from
+    for (index, info) in reported.iter().enumerate() {
+        match info.format {
+            Some(format) => println!("{index}: {}x{}, {format}", info.width, info.height),
+            None => println!(
+                "{index}: {}x{}, unsupported format",
+                info.width, info.height
+            ),
+        }
+
+        for mode in &info.modes {
+            println!("    {}x{}x{}", mode.width, mode.height, mode.bpp);
+        }
+    }
+}
+
+const BUILTINS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "list commands",
+        run: help,
+    },
+    Command {
+        name: "echo",
+        help: "print the arguments",
+        run: echo,
+    },
+    Command {
+        name: "mem",
+        help: "show memory usage",
+        run: mem,
+    },
+    Command {
+        name: "cpus",
+        help: "show the number of CPUs online",
+        run: cpus,
+    },
+    Command {
+        name: "threads",
+        help: "list the kernel threads",
+        run: threads,
+    },
+    Command {
+        name: "uptime",
+        help: "show the time since boot",
+        run: uptime,
+    },
+    Command {
+        name: "date",
+        help: "show the date and time",
+        run: date,
+    },
+    Command {
+        name: "clear",
+        help: "clear the screen",
+        run: clear,
+    },
+    Command {
+        name: "log-level",
+        help: "show or set the most verbose level logged",
+        run: log_level,
+    },
+    Command {
+        name: "reboot",
+        help: "reset the machine",
+        run: reboot,
+    },
+    Command {
+        name: "shutdown",
+        help: "power the machine off",
+        run: shutdown,
+    },
+    Command {
+        name: "lspci",
+        help: "list PCI devices",
+        run: lspci,
+    },
+    Command {
+        name: "displays",
+        help: "list framebuffers and their video modes",
+        run: displays,
+    },
+];
+
+/// Registers the commands every kernel has.
+pub fn register_builtins() {
+    for &command in BUILTINS {
+        register(command);
+    }
+}
@@ -0,0 +1,207 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::keyboard::{self, KeyCode, KeyEvent, NextEvent};
+use crate::serial::{self, NextByte};
+
+/// An editing key, from either the keyboard or the serial port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Ctrl+C, which abandons the line.
+    Cancel,
+}
+
+impl Key {
+    /// Maps a key event to an editing key. Releases and keys that neither
+    /// type nor edit map to nothing.
+    pub fn from_event(event: KeyEvent) -> Option<Self> {
+        if !event.pressed {
+            return None;
+        }
+
+        match event.key {
+            KeyCode::Left => return Some(Key::Left),
+            KeyCode::Right => return Some(Key::Right),
+            KeyCode::Up => return Some(Key::Up),
+            KeyCode::Down => return Some(Key::Down),
+            KeyCode::Home => return Some(Key::Home),
+            KeyCode::End => return Some(Key::End),
+            KeyCode::Delete => return Some(Key::Delete),
+            _ => {}
+        }
+
+        match event.character? {
+            '\n' => Some(Key::Enter),
+            '\x08' => Some(Key::Backspace),
+            '\t' => Some(Key::Tab),
+            '\x03' => Some(Key::Cancel),
+            character if character.is_control() => None,
+            character => Some(Key::Char(character)),
+        }
+    }
+}
+
+enum SerialState {
+    Ground,
+    // After ESC
+    Escape,
+    // After ESC [ or ESC O, with the numeric parameter so far
+    Sequence(u8),
+}
+
+/// Turns the bytes a terminal sends into editing keys, decoding the VT100
+/// escape sequences of the cursor keys.
+pub struct SerialDecoder {
+    state: SerialState,
+    // Set after a CR, so the LF of a CRLF isn't a second Enter
+    after_cr: bool,
+}
+
+impl SerialDecoder {
+    pub const fn new() -> Self {
+        Self {
+            state: SerialState::Ground,
+            after_cr: false,
+        }
+    }
+
+    /// Feeds the next byte, returning the key once a sequence is complete.
+    /// Non-ASCII bytes and unknown sequences are dropped.
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        let after_cr = core::mem::take(&mut self.after_cr);
+
+        match self.state {
+            SerialState::Ground => match byte {
+                0x1B => {
+                    self.state = SerialState::Escape;
+                    None
+                }
+                b'\r' => {
+                    self.after_cr = true;
+                    Some(Key::Enter)
+                }
+                b'\n' if after_cr => None,
+                b'\n' => Some(Key::Enter),
+                0x08 | 0x7F => Some(Key::Backspace),
+                b'\t' => Some(Key::Tab),
+                0x03 => Some(Key::Cancel),
+                0x20..=0x7E => Some(Key::Char(byte as char)),
+                _ => None,
+            },
+            SerialState::Escape => {
+                self.state = match byte {
+                    b'[' | b'O' => SerialState::Sequence(0),
+                    _ => SerialState::Ground,
+                };
+                None
+            }
+            SerialState::Sequence(parameter) => {
+                if byte.is_ascii_digit() {
+                    let parameter = parameter.saturating_mul(10).saturating_add(byte - b'0');
+                    self.state = SerialState::Sequence(parameter);
+                    return None;
+                }
+
+                self.state = SerialState::Ground;
+
+                match (byte, parameter) {
+                    (b'A', _) => Some(Key::Up),
+                    (b'B', _) => Some(Key::Down),
+                    (b'C', _) => Some(Key::Right),
+                    (b'D', _) => Some(Key::Left),
+                    (b'H', _) | (b'~', 1 | 7) => Some(Key::Home),
+                    (b'F', _) | (b'~', 4 | 8) => Some(Key::End),
+                    (b'~', 3) => Some(Key::Delete),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Future returned by [`next_key`].
+pub struct NextKey<'a> {
+    decoder: &'a mut SerialDecoder,
+    keyboard: NextEvent,
+    serial: NextByte,
+}
+
+impl Future for NextKey<'_> {
+    type Output = Key;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Key> {
+        let this = &mut *self;
+
+        // Drain both sources until one of them yields a key, leaving both
+        // registered with the waker otherwise
+        loop {
+            let mut progress = false;
+
+            if let Poll::Ready(event) = Pin::new(&mut this.keyboard).poll(context) {
+                progress = true;
+                if let Some(key) = Key::from_event(event) {
+                    return Poll::Ready(key);
+                }
+            }
+
+            if let Poll::Ready(byte) = Pin::new(&mut this.serial).poll(context) {
+                progress = true;
+                if let Some(key) = this.decoder.feed(byte) {
+                    return Poll::Ready(key);
+                }
+            }
+
+            if !progress {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Completes with the next editing key typed on the keyboard or received on
+/// the serial port.
+pub fn next_key(decoder: &mut SerialDecoder) -> NextKey<'_> {
+    NextKey {
+        decoder,
+        keyboard: keyboard::next_event(),
+        serial: serial::next_byte(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn serial_decoder_reads_escape_sequences() {
+        let mut decoder = SerialDecoder::new();
+        let keys: alloc::vec::Vec<Key> = b"a\x1b[A\x1b[3~\x1bOH\r\n\x7f"
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte))
+            .collect();
+
+        assert_eq!(
+            keys,
+            [
+                Key::Char('a'),
+                Key::Up,
+                Key::Delete,
+                Key::Home,
+                Key::Enter,
+                Key::Backspace
+            ]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::input::Key;
+
+// Oldest entries are dropped beyond this many
+const HISTORY_CAPACITY: usize = 64;
+
+/// What a key did to the line.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// The line or its cursor changed, or nothing happened.
+    Edited,
+    /// Enter was pressed; the line is handed over and the editor is empty.
+    Submitted(String),
+    /// The line was abandoned; it is handed over and the editor is empty.
+    Cancelled(String),
+}
+
+/// A single line of input with a cursor and history.
+pub struct LineEditor {
+    line: String,
+    // Byte index of the cursor, always on a character boundary
+    cursor: usize,
+    history: VecDeque<String>,
+    // History entry being shown, and the line that was being typed before
+    // browsing started
+    browsing: Option<usize>,
+    draft: String,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            browsing: None,
+            draft: String::new(),
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Previous lines, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    fn previous_boundary(&self) -> usize {
+        self.line[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(index, _)| index)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.line[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |character| self.cursor + character.len_utf8())
+    }
+
+    fn replace(&mut self, line: String) {
+        self.line = line;
+        self.cursor = self.line.len();
+    }
+
+    fn show_history(&mut self, index: Option<usize>) {
+        if self.browsing.is_none() {
+            self.draft = core::mem::take(&mut self.line);
+        }
+
+        self.browsing = index;
+        let line = match index {
+            Some(index) => self.history[index].clone(),
+            None => core::mem::take(&mut self.draft),
+        };
+        self.replace(line);
+    }
+
+    /// Applies an editing key. Tab is handled by [`complete`](Self::complete).
+    pub fn handle(&mut self, key: Key) -> Action {
+        match key {
+            Key::Char(character) => {
+                self.line.insert(self.cursor, character);
+                self.cursor += character.len_utf8();
+            }
+            Key::Backspace if self.cursor > 0 => {
+                let start = self.previous_boundary();
+                self.line.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                let end = self.next_boundary();
+                self.line.replace_range(self.cursor..end, "");
+            }
+            Key::Left => self.cursor = self.previous_boundary(),
+            Key::Right => self.cursor = self.next_boundary(),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Up if !self.history.is_empty() => {
+                let index = self.browsing.unwrap_or(self.history.len());
+                self.show_history(Some(index.saturating_sub(1)));
+            }
+            Key::Down => {
+                if let Some(index) = self.browsing {
+                    let next = index + 1;
+                    self.show_history((next < self.history.len()).then_some(next));
+                }
+            }
+            Key::Enter => {
+                let line = core::mem::take(&mut self.line);
+                self.cursor = 0;
+                self.browsing = None;
+                self.draft.clear();
+
+                // Blank lines and repeats of the last line aren't worth keeping
+                if !line.trim().is_empty() && self.history.back() != Some(&line) {
+                    if self.history.len() == HISTORY_CAPACITY {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(line.clone());
+                }
+
+                return Action::Submitted(line);
+            }
+            Key::Cancel => {
+                let line = core::mem::take(&mut self.line);
+                self.cursor = 0;
+                self.browsing = None;
+                self.draft.clear();
+                return Action::Cancelled(line);
+            }
+            _ => {}
+        }
+
+        Action::Edited
+    }
+
+    /// Completes the command name before the cursor from `names`. When
+    /// several names match and can't be extended further, returns them so
+    /// they can be listed.
+    pub fn complete<'a>(&mut self, names: &[&'a str]) -> Vec<&'a str> {
+        let prefix = &self.line[..self.cursor];
+
+        // Only the command name is completed, not its arguments
+        if prefix.contains(' ') {
+            return Vec::new();
+        }
+
+        let matches: Vec<&str> = names
+            .iter()
+            .copied()
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+
+        let rest = &self.line[self.cursor..];
+
+        let completion = match matches.as_slice() {
+            [] => return Vec::new(),
+            [name] => {
+                let mut completion = String::from(*name);
+                if !rest.starts_with(' ') {
+                    completion.push(' ');
+                }
+                completion
+            }
+            [first, others @ ..] => {
+                let common = others.iter().fold(first.len(), |common, name| {
+                    first
+                        .bytes()
+                        .zip(name.bytes())
+                        .take(common)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+
+                if common == prefix.len() {
+                    return matches;
+                }
+                String::from(&first[..common])
+            }
+        };
+
+        self.cursor = completion.len();
+        self.line = completion + rest;
+
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_line(editor: &mut LineEditor, text: &str) {
+        for character in text.chars() {
+            editor.handle(Key::Char(character));
+        }
+    }
+
+    #[test_case]
+    fn editing_and_history() {
+        let mut editor = LineEditor::new();
+
+        type_line(&mut editor, "hllo");
+        editor.handle(Key::Home);
+        editor.handle(Key::Right);
+        editor.handle(Key::Char('e'));
+        editor.handle(Key::End);
+        editor.handle(Key::Backspace);
+        assert_eq!(editor.line(), "hell");
+        assert_eq!(editor.cursor(), 4);
+
+        assert_eq!(editor.handle(Key::Enter), Action::Submitted("hell".into()));
+        type_line(&mut editor, "second");
+        editor.handle(Key::Enter);
+
+        type_line(&mut editor, "draft");
+        editor.handle(Key::Up);
+        assert_eq!(editor.line(), "second");
+        editor.handle(Key::Up);
+        editor.handle(Key::Up);
+        assert_eq!(editor.line(), "hell");
+        editor.handle(Key::Down);
+        editor.handle(Key::Down);
+        assert_eq!(editor.line(), "draft");
+    }
+
+    #[test_case]
+    fn completion_extends_to_common_prefix() {
+        let names = ["help", "history", "log-level", "lspci"];
+        let mut editor = LineEditor::new();
+
+        type_line(&mut editor, "h");
+        assert_eq!(editor.complete(&names), ["help", "history"]);
+        assert_eq!(editor.line(), "h");
+
+        type_line(&mut editor, "i");
+        assert!(editor.complete(&names).is_empty());
+        assert_eq!(editor.line(), "history ");
+
+        editor.handle(Key::Cancel);
+        type_line(&mut editor, "lo");
+        editor.complete(&names);
+        assert_eq!(editor.line(), "log-level ");
+    }
+}
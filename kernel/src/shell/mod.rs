@@ -0,0 +1,140 @@
+//! An interactive shell on the console.
+//!
+//! The shell is an executor task reading keys from the keyboard and the
+//! serial port, so it can be driven by a terminal when QEMU runs headless.
+//! The line being edited is pinned to the bottom of the framebuffer console
+//! and redrawn in place on the terminal. Subsystems add commands with
+//! [`register`].
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ansi::{Style, StyledLine};
+use crate::sync::SpinLock;
+use crate::{executor, println, serial_print};
+
+mod commands;
+mod input;
+mod line;
+
+pub use commands::{Command, register};
+
+use input::{Key, SerialDecoder};
+use line::{Action, LineEditor};
+
+const PROMPT: &str = "> ";
+// The prompt is bold green, selected with this sequence in the terminal and
+// the scrollback and with its parameters in the input line
+const PROMPT_SGR: &str = "\x1B[1;32m";
+const PROMPT_SGR_PARAMS: [u16; 2] = [1, 32];
+const RESET_SGR: &str = "\x1B[0m";
+
+static EDITOR: SpinLock<LineEditor> = SpinLock::new("shell editor", LineEditor::new());
+
+// Erases the terminal's current line, so output doesn't land after the prompt
+fn clear_serial_line() {
+    serial_print!("\r\x1B[K");
+}
+
+// Shows the prompt and the line being edited
+fn show_line() {
+    let (line, cursor) = {
+        let editor = EDITOR.lock();
+        (String::from(editor.line()), editor.cursor())
+    };
+    let mut prompt_style = Style::PLAIN;
+    prompt_style.apply_sgr(&PROMPT_SGR_PARAMS);
+
+    let mut input_line = StyledLine::new();
+    input_line.push_str(PROMPT, prompt_style);
+    input_line.push_str(&line, Style::PLAIN);
+    crate::set_input_line(Some((&input_line, PROMPT.len() + cursor)));
+
+    // The terminal's cursor ends up after the text, so move it back
+    clear_serial_line();
+    serial_print!("{PROMPT_SGR}{PROMPT}{RESET_SGR}{line}");
+    let behind = line[cursor..].chars().count();
+    if behind > 0 {
+        serial_print!("\x1B[{behind}D");
+    }
+}
+
+// Moves a line that is done with into the output
+fn finish_line(text: &str) {
+    clear_serial_line();
+    crate::set_input_line(None);
+    println!("{PROMPT_SGR}{PROMPT}{RESET_SGR}{text}");
+}
+
+fn complete() {
+    let names = commands::names();
+    let matches = EDITOR.lock().complete(&names);
+
+    if !matches.is_empty() {
+        clear_serial_line();
+        println!("{}", matches.join("  "));
+    }
+}
+
+fn execute(line: &str) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((name, args)) = words.split_first() else {
+        return;
+    };
+
+    match commands::find(name) {
+        Some(command) => (command.run)(args),
+        None => println!("{name}: command not found"),
+    }
+}
+
+fn history(_args: &[&str]) {
+    // Copied so the editor isn't locked while printing
+    let history: Vec<String> = EDITOR.lock().history().map(String::from).collect();
+
+    for (number, line) in history.iter().enumerate() {
+        println!("{:>4}  {line}", number + 1);
+    }
+}
+
+async fn run() {
+    let mut decoder = SerialDecoder::new();
+
+    show_line();
+
+    loop {
+        let key = input::next_key(&mut decoder).await;
+
+        if key == Key::Tab {
+            complete();
+            show_line();
+            continue;
+        }
+
+        let action = EDITOR.lock().handle(key);
+        match action {
+            Action::Edited => {}
+            Action::Submitted(line) => {
+                finish_line(&line);
+                execute(&line);
+            }
+            Action::Cancelled(line) => finish_line(&format!("{line}^C")),
+        }
+
+        show_line();
+    }
+}
+
+/// Registers the built-in commands and starts the shell. Needs the executor
+/// and serial input.
+pub fn init() {
+    commands::register_builtins();
+    register(Command {
+        name: "history",
+        help: "list previous command lines",
+        run: history,
+    });
+
+    executor::spawn(run());
+}
@@ -0,0 +1,125 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{MutexGuard, WaitQueue};
+
+/// A condition variable, for waiting on state protected by a [`Mutex`].
+///
+/// [`Mutex`]: super::Mutex
+// No kernel code waits on a condition yet
+#[allow(dead_code)]
+pub struct Condvar {
+    // Bumped by every notification, so a waiter can tell whether one
+    // arrived after it released the mutex
+    sequence: AtomicU64,
+    waiters: WaitQueue,
+}
+
+#[allow(dead_code)]
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Releases the mutex, blocks until notified and takes the mutex again.
+    ///
+    /// Like any condition variable this can return without the state having
+    /// changed, so callers wait in a loop.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let sequence = self.sequence.load(Ordering::Acquire);
+        let mutex = guard.unlock();
+
+        self.waiters
+            .wait_until(|| self.sequence.load(Ordering::Acquire) != sequence);
+
+        mutex.lock()
+    }
+
+    /// Blocks until `condition` returns `false`, waiting for a notification
+    /// after every check.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    /// Wakes one waiting thread.
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    /// Wakes every waiting thread.
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{Mutex, Semaphore};
+    use crate::task;
+
+    #[test_case]
+    fn notify_one_wakes_a_waiter() {
+        static READY: Mutex<bool> = Mutex::new("test ready", false);
+        static CHANGED: Condvar = Condvar::new();
+        static FINISHED: Semaphore = Semaphore::new(0);
+
+        task::spawn("test", || {
+            let ready = CHANGED.wait_while(READY.lock(), |ready| !*ready);
+            assert!(*ready);
+            drop(ready);
+
+            FINISHED.release();
+        });
+
+        *READY.lock() = true;
+        CHANGED.notify_one();
+
+        FINISHED.acquire();
+    }
+
+    #[test_case]
+    fn notify_all_wakes_every_waiter() {
+        const THREADS: usize = 3;
+
+        static GENERATION: Mutex<usize> = Mutex::new("test generation", 0);
+        static CHANGED: Condvar = Condvar::new();
+        static WAITING: Semaphore = Semaphore::new(0);
+        static FINISHED: Semaphore = Semaphore::new(0);
+
+        for _ in 0..THREADS {
+            task::spawn("test", || {
+                let generation = GENERATION.lock();
+                WAITING.release();
+
+                let generation = CHANGED.wait_while(generation, |generation| *generation == 0);
+                drop(generation);
+
+                FINISHED.release();
+            });
+        }
+
+        for _ in 0..THREADS {
+            WAITING.acquire();
+        }
+
+        *GENERATION.lock() = 1;
+        CHANGED.notify_all();
+
+        for _ in 0..THREADS {
+            FINISHED.acquire();
+        }
+    }
+}
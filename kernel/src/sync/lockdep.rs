@@ -0,0 +1,203 @@
+//! Lock order checking in debug builds.
+//!
+//! Every time a lock is taken while others are held, the order is recorded
+//! as an edge from each held lock to the new one. Taking a lock that already
+//! leads back to one that is held means two code paths nest the same locks
+//! in opposite orders, which can deadlock even if it hasn't yet.
+//!
+//! Nothing here allocates, since the allocator's own locks are checked too.
+//! Reports are printed once the reporting thread holds no locks, so printing
+//! can't deadlock on a lock held further up the stack.
+
+#[cfg(debug_assertions)]
+mod checker {
+    use spin::Mutex;
+
+    use crate::println;
+    use crate::task;
+
+    // Deeper nesting than this goes unchecked
+    const MAX_HELD: usize = 16;
+    const MAX_EDGES: usize = 256;
+    const MAX_REPORTS: usize = 16;
+
+    // Locks held by a thread, innermost last
+    pub struct HeldLocks {
+        names: [&'static str; MAX_HELD],
+        count: usize,
+        // Set while the thread prints reports, which takes locks of its own
+        reporting: bool,
+    }
+
+    impl HeldLocks {
+        pub const fn new() -> Self {
+            Self {
+                names: [""; MAX_HELD],
+                count: 0,
+                reporting: false,
+            }
+        }
+
+        fn held(&self) -> &[&'static str] {
+            &self.names[..self.count.min(MAX_HELD)]
+        }
+    }
+
+    struct Graph {
+        edges: [(&'static str, &'static str); MAX_EDGES],
+        edge_count: usize,
+        // Pairs of (lock being taken, lock held) found in the wrong order
+        reports: [(&'static str, &'static str); MAX_REPORTS],
+        report_count: usize,
+        printed: usize,
+    }
+
+    impl Graph {
+        fn edges(&self) -> &[(&'static str, &'static str)] {
+            &self.edges[..self.edge_count]
+        }
+
+        fn add_edge(&mut self, from: &'static str, to: &'static str) {
+            if self.edge_count < MAX_EDGES && !self.edges().contains(&(from, to)) {
+                self.edges[self.edge_count] = (from, to);
+                self.edge_count += 1;
+            }
+        }
+
+        // Depth-first search, bounded since the graph can't hold a path
+        // longer than the number of edges
+        fn reaches(&self, from: &str, to: &str, depth: usize) -> bool {
+            if depth > MAX_HELD {
+                return false;
+            }
+
+            self.edges()
+                .iter()
+                .filter(|(source, _)| *source == from)
+                .any(|&(_, next)| next == to || self.reaches(next, to, depth + 1))
+        }
+
+        fn report(&mut self, acquiring: &'static str, held: &'static str) {
+            let pair = (acquiring, held);
+
+            if self.report_count < MAX_REPORTS && !self.reports[..self.report_count].contains(&pair)
+            {
+                self.reports[self.report_count] = pair;
+                self.report_count += 1;
+            }
+        }
+    }
+
+    static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+        edges: [("", ""); MAX_EDGES],
+        edge_count: 0,
+        reports: [("", ""); MAX_REPORTS],
+        report_count: 0,
+        printed: 0,
+    });
+
+    // Callers have interrupts disabled, so these locks are never taken
+    // recursively
+    pub fn acquire(name: &'static str) {
+        let Some(thread) = task::try_current() else {
+            return;
+        };
+
+        let mut held_locks = thread.held_locks.lock();
+        if held_locks.reporting {
+            return;
+        }
+
+        let mut graph = GRAPH.lock();
+
+        for &held in held_locks.held() {
+            if held == name {
+                continue;
+            }
+
+            if graph.reaches(name, held, 0) {
+                graph.report(name, held);
+            }
+
+            graph.add_edge(held, name);
+        }
+
+        if held_locks.count < MAX_HELD {
+            let count = held_locks.count;
+            held_locks.names[count] = name;
+        }
+        held_locks.count += 1;
+    }
+
+    pub fn release(name: &'static str) {
+        let Some(thread) = task::try_current() else {
+            return;
+        };
+
+        {
+            let mut held_locks = thread.held_locks.lock();
+            if held_locks.reporting {
+                return;
+            }
+
+            let count = held_locks.count.min(MAX_HELD);
+
+            if let Some(index) = held_locks.names[..count]
+                .iter()
+                .rposition(|&held| held == name)
+            {
+                held_locks.names.copy_within(index + 1..count, index);
+            }
+            held_locks.count = held_locks.count.saturating_sub(1);
+
+            if held_locks.count != 0 {
+                return;
+            }
+
+            held_locks.reporting = true;
+        }
+
+        print_reports();
+
+        thread.held_locks.lock().reporting = false;
+    }
+
+    fn print_reports() {
+        loop {
+            let (acquiring, held) = {
+                let mut graph = GRAPH.lock();
+
+                if graph.printed == graph.report_count {
+                    return;
+                }
+
+                graph.printed += 1;
+                graph.reports[graph.printed - 1]
+            };
+
+            println!(
+                "lockdep: possible deadlock: \"{acquiring}\" was taken while holding \"{held}\", \
+                 but elsewhere \"{held}\" is taken while \"{acquiring}\" is held"
+            );
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+pub use checker::{HeldLocks, acquire, release};
+
+#[cfg(not(debug_assertions))]
+pub struct HeldLocks;
+
+#[cfg(not(debug_assertions))]
+impl HeldLocks {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub fn acquire(_name: &'static str) {}
+
+#[cfg(not(debug_assertions))]
+pub fn release(_name: &'static str) {}
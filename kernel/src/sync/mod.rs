@@ -0,0 +1,15 @@
+mod condvar;
+pub mod lockdep;
+mod mutex;
+mod semaphore;
+mod spinlock;
+mod wait_queue;
+
+// Kernel primitives without users outside the tests yet
+#[allow(unused_imports)]
+pub use condvar::Condvar;
+#[allow(unused_imports)]
+pub use mutex::{Mutex, MutexGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::SpinLock;
+pub use wait_queue::WaitQueue;
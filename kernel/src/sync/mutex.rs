@@ -0,0 +1,136 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{WaitQueue, lockdep};
+use crate::arch::x86_64::cpu;
+
+/// A lock that blocks the thread waiting for it instead of spinning, for
+/// state held across long operations. It can't be taken in interrupt
+/// handlers, which have no thread to block.
+///
+/// The name identifies the lock in lock order reports, as with
+/// [`SpinLock`](super::SpinLock).
+// No kernel code takes a sleeping lock yet
+#[allow(dead_code)]
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    name: &'static str,
+    waiters: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+#[allow(dead_code)]
+impl<T> Mutex<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            name,
+            waiters: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Blocks until the lock is free and takes it.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        cpu::without_interrupts(|| lockdep::acquire(self.name));
+
+        self.waiters.wait_until(|| self.take());
+
+        MutexGuard { mutex: self }
+    }
+
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if !self.take() {
+            return None;
+        }
+
+        cpu::without_interrupts(|| lockdep::acquire(self.name));
+
+        Some(MutexGuard { mutex: self })
+    }
+
+    fn take(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    // Releases the lock, handing back the mutex so a condition variable can
+    // take it again after waiting
+    pub(super) fn unlock(self) -> &'a Mutex<T> {
+        let mutex = self.mutex;
+        drop(self);
+
+        mutex
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        cpu::without_interrupts(|| lockdep::release(self.mutex.name));
+
+        self.mutex.waiters.wake_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Semaphore;
+    use crate::task;
+
+    #[test_case]
+    fn mutex_excludes_threads_that_yield_while_holding_it() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 50;
+
+        static COUNTER: Mutex<usize> = Mutex::new("test counter", 0);
+        static FINISHED: Semaphore = Semaphore::new(0);
+
+        for _ in 0..THREADS {
+            task::spawn("test", || {
+                for _ in 0..ROUNDS {
+                    let mut counter = COUNTER.lock();
+                    let value = *counter;
+                    // Give the others a chance to see a half-done update
+                    task::yield_now();
+                    *counter = value + 1;
+                }
+
+                FINISHED.release();
+            });
+        }
+
+        for _ in 0..THREADS {
+            FINISHED.acquire();
+        }
+
+        assert_eq!(*COUNTER.lock(), THREADS * ROUNDS);
+    }
+}
@@ -0,0 +1,89 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::lockdep;
+use crate::arch::x86_64::cpu;
+
+/// A spinlock that keeps interrupts disabled while it is held, so an
+/// interrupt handler taking it can never spin on a lock its own CPU holds.
+///
+/// The name identifies the lock in lock order reports; every lock created
+/// with the same name is treated as the same lock.
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    name: &'static str,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for SpinLock<T> {}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            name,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Disables interrupts and spins until the lock is free.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let rflags = cpu::save_and_disable_interrupts();
+        lockdep::acquire(self.name);
+
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+
+        SpinLockGuard { lock: self, rflags }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Releases the lock without a guard.
+    ///
+    /// # Safety
+    ///
+    /// Whoever holds the lock must never touch the protected value again,
+    /// e.g. because it was interrupted by a panic or halted.
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    rflags: u64,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        lockdep::release(self.lock.name);
+        cpu::restore_interrupts(self.rflags);
+    }
+}
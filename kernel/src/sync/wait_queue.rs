@@ -0,0 +1,60 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use super::SpinLock;
+use crate::arch::x86_64::cpu;
+use crate::task::{self, Thread};
+
+/// Threads blocked until some condition holds.
+///
+/// Wakers change the state the condition looks at and then wake the queue.
+/// Since waiters check the condition with the queue locked, a wake-up can't
+/// slip in between the check and the thread blocking.
+pub struct WaitQueue {
+    waiters: SpinLock<VecDeque<Arc<Thread>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new("wait queue", VecDeque::new()),
+        }
+    }
+
+    /// Blocks until `condition` returns `true`. It is checked once before
+    /// blocking and again after every wake-up.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        cpu::without_interrupts(|| {
+            loop {
+                let mut waiters = self.waiters.lock();
+
+                if condition() {
+                    return;
+                }
+
+                task::block(|thread| {
+                    waiters.push_back(thread);
+                    drop(waiters);
+                });
+            }
+        });
+    }
+
+    /// Wakes the thread that has waited longest. Returns whether there was
+    /// one.
+    pub fn wake_one(&self) -> bool {
+        // Woken outside the lock, since waking takes the run queue's
+        let waiter = self.waiters.lock().pop_front();
+
+        waiter.is_some_and(task::wake)
+    }
+
+    /// Wakes every waiting thread.
+    pub fn wake_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+
+        for waiter in waiters {
+            task::wake(waiter);
+        }
+    }
+}
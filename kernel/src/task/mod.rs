@@ -0,0 +1,292 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::arch::x86_64::{context, cpu};
+use crate::sync::SpinLock;
+use crate::sync::lockdep::HeldLocks;
+use crate::time::Instant;
+
+mod scheduler;
+
+pub use scheduler::{add_boot_stacks, init, init_ap, remove_boot_stacks, wake};
+
+// Large enough for printing, which lays out and renders text on the stack
+const STACK_SIZE: usize = 64 * 1024;
+
+/// Identifies a thread for as long as the kernel runs; ids are not reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Forwarded so width and alignment apply
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum State {
+    /// Waiting in the run queue.
+    Ready,
+    Running,
+    /// Waiting for its wake-up time.
+    Sleeping,
+    /// Waiting for another thread to [`wake`] it.
+    Blocked,
+    /// Finished; its stack is freed once nothing refers to it.
+    Exited,
+}
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+/// A kernel thread: a stack and the context saved on it while the thread
+/// isn't running.
+pub struct Thread {
+    id: ThreadId,
+    name: String,
+    state: AtomicU8,
+    // Saved by the context switch while the thread is switched out
+    stack_pointer: AtomicU64,
+    // Set while a CPU runs on the thread's stack, until the context switch
+    // away from it has finished
+    on_cpu: AtomicBool,
+    wake_at: SpinLock<Option<Instant>>,
+    entry: SpinLock<Option<Entry>>,
+    // Taken by the lock order checker, so it can't be checked itself
+    pub(crate) held_locks: spin::Mutex<HeldLocks>,
+    // Threads adopted from a CPU's boot context run on a stack they don't own
+    stack: Option<Box<[u8]>>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Every thread created so far, for listing them. Exited threads drop out
+// once nothing else refers to them.
+static THREADS: SpinLock<Vec<Weak<Thread>>> = SpinLock::new("thread list", Vec::new());
+
+impl Thread {
+    fn new(name: String, entry: Option<Entry>) -> Arc<Self> {
+        let mut stack = entry
+            .is_some()
+            .then(|| vec![0; STACK_SIZE].into_boxed_slice());
+
+        let stack_pointer = stack
+            .as_deref_mut()
+            .map_or(0, |stack| context::init_stack(stack, thread_entry));
+
+        let thread = Arc::new(Self {
+            id: ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            name,
+            state: AtomicU8::new(State::Ready as u8),
+            stack_pointer: AtomicU64::new(stack_pointer),
+            on_cpu: AtomicBool::new(stack.is_none()),
+            wake_at: SpinLock::new("thread wake time", None),
+            entry: SpinLock::new("thread entry", entry),
+            held_locks: spin::Mutex::new(HeldLocks::new()),
+            stack,
+        });
+
+        let mut threads = THREADS.lock();
+        threads.retain(|thread| thread.strong_count() > 0);
+        threads.push(Arc::downgrade(&thread));
+        drop(threads);
+
+        thread
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> State {
+        match self.state.load(Ordering::Acquire) {
+            0 => State::Ready,
+            1 => State::Running,
+            2 => State::Sleeping,
+            3 => State::Blocked,
+            _ => State::Exited,
+        }
+    }
+
+    fn set_state(&self, state: State) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    // Moves the thread from `from` to `to`, unless it has left `from` already
+    fn transition(&self, from: State, to: State) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+}
+
+// First code run by every spawned thread
+extern "C" fn thread_entry() -> ! {
+    scheduler::finish_switch();
+
+    let entry = current().entry.lock().take().expect("thread started twice");
+
+    cpu::enable_interrupts();
+    entry();
+
+    exit()
+}
+
+/// Starts a thread running `f`. It is queued behind every thread that is
+/// already ready.
+pub fn spawn(name: impl Into<String>, f: impl FnOnce() + Send + 'static) -> ThreadId {
+    let thread = Thread::new(name.into(), Some(Box::new(f)));
+    let id = thread.id;
+
+    scheduler::enqueue(thread);
+
+    id
+}
+
+/// Returns every thread that hasn't exited, oldest first.
+pub fn all() -> Vec<Arc<Thread>> {
+    THREADS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter(|thread| thread.state() != State::Exited)
+        .collect()
+}
+
+/// Returns the thread that is running this code.
+pub fn current() -> Arc<Thread> {
+    scheduler::current()
+}
+
+/// Returns the thread that is running this code, or `None` while the CPU
+/// is still starting up and there are no threads on it yet.
+pub fn try_current() -> Option<Arc<Thread>> {
+    scheduler::try_current()
+}
+
+/// Lets every other ready thread run before the current one continues.
+// Only the tests yield so far
+#[allow(dead_code)]
+pub fn yield_now() {
+    cpu::without_interrupts(scheduler::schedule);
+}
+
+/// Blocks the current thread for at least `duration`, running other threads
+/// meanwhile. Wake-ups happen on timer ticks, so the wait is rounded up to
+/// the next tick.
+pub fn sleep(duration: Duration) {
+    cpu::without_interrupts(|| block_until(Some(Instant::now() + duration), |_| {}));
+}
+
+/// Blocks the current thread until something calls [`wake`] on it.
+///
+/// `register` is handed the current thread once it is marked blocked, and
+/// must store it wherever the waker will find it. A wake-up that arrives
+/// before the thread has switched out isn't lost; the thread just keeps
+/// running. Called with interrupts disabled, which `register` must not
+/// enable.
+pub fn block(register: impl FnOnce(Arc<Thread>)) {
+    block_until(None, register);
+}
+
+/// Like [`block`], but also wakes the thread once `deadline` has passed.
+pub fn block_until(deadline: Option<Instant>, register: impl FnOnce(Arc<Thread>)) {
+    let thread = current();
+
+    match deadline {
+        Some(deadline) => {
+            *thread.wake_at.lock() = Some(deadline);
+            thread.set_state(State::Sleeping);
+            scheduler::add_sleeper(thread.clone());
+        }
+        None => thread.set_state(State::Blocked),
+    }
+
+    register(thread);
+
+    scheduler::schedule();
+}
+
+/// Ends the current thread.
+pub fn exit() -> ! {
+    cpu::disable_interrupts();
+    current().set_state(State::Exited);
+    scheduler::schedule();
+
+    unreachable!("exited thread was scheduled again");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicUsize;
+
+    #[test_case]
+    fn spawned_threads_run_and_sleep() {
+        static STEPS: AtomicUsize = AtomicUsize::new(0);
+
+        spawn("test", || {
+            STEPS.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(20));
+            STEPS.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let start = Instant::now();
+        while STEPS.load(Ordering::SeqCst) < 2 {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "thread didn't finish"
+            );
+            yield_now();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test_case]
+    fn blocked_thread_runs_after_wake() {
+        static WOKEN: AtomicBool = AtomicBool::new(false);
+        static WAITER: SpinLock<Option<Arc<Thread>>> = SpinLock::new("test waiter", None);
+
+        spawn("test", || {
+            cpu::without_interrupts(|| {
+                block(|thread| *WAITER.lock() = Some(thread));
+            });
+            WOKEN.store(true, Ordering::SeqCst);
+        });
+
+        let start = Instant::now();
+        let waiter = loop {
+            if let Some(waiter) = WAITER.lock().take() {
+                break waiter;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "thread didn't block"
+            );
+            yield_now();
+        };
+
+        assert!(!WOKEN.load(Ordering::SeqCst));
+        assert!(wake(waiter));
+
+        while !WOKEN.load(Ordering::SeqCst) {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "thread wasn't woken"
+            );
+            yield_now();
+        }
+    }
+}
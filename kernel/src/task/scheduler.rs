@@ -0,0 +1,303 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{State, Thread};
+use crate::arch::x86_64::{context, cpu, interrupts, percpu};
+use crate::memory::frame;
+use crate::sync::SpinLock;
+use crate::time::{self, Instant};
+
+// Threads waiting for a CPU, in the order they will run. Shared by every
+// CPU. A thread is only queued once no CPU is running on its stack, which
+// is decided with this lock held.
+static READY: SpinLock<VecDeque<Arc<Thread>>> = SpinLock::new("run queue", VecDeque::new());
+
+// Sleeping threads, checked on every tick of the bootstrap processor
+static SLEEPING: SpinLock<Vec<Arc<Thread>>> = SpinLock::new("sleep queue", Vec::new());
+
+// CPUs that may still run on the stack the bootloader gave them, starting
+// with the bootstrap processor. That stack is in bootloader memory, which
+// is only reclaimed once every CPU has left it for good.
+static BOOT_STACKS: AtomicUsize = AtomicUsize::new(1);
+
+// The per-CPU task slots hold `Arc<Thread>` pointers converted with
+// `Arc::into_raw`, each owning one reference
+fn into_slot(thread: Arc<Thread>) -> *mut () {
+    Arc::into_raw(thread) as *mut ()
+}
+
+unsafe fn from_slot(slot: *mut ()) -> Arc<Thread> {
+    unsafe { Arc::from_raw(slot as *const Thread) }
+}
+
+pub(super) fn enqueue(thread: Arc<Thread>) {
+    thread.set_state(State::Ready);
+
+    READY.lock().push_back(thread);
+}
+
+pub(super) fn add_sleeper(thread: Arc<Thread>) {
+    SLEEPING.lock().push(thread);
+}
+
+pub(super) fn current() -> Arc<Thread> {
+    try_current().expect("scheduler not initialized on this CPU")
+}
+
+pub(super) fn try_current() -> Option<Arc<Thread>> {
+    if !percpu::is_initialized() {
+        return None;
+    }
+
+    // Read with interrupts disabled so the thread can't move to another CPU
+    // between finding this CPU's data and reading its slot
+    cpu::without_interrupts(|| {
+        let slot = percpu::current().current_task.load(Ordering::Acquire);
+        if slot.is_null() {
+            return None;
+        }
+
+        unsafe {
+            Arc::increment_strong_count(slot as *const Thread);
+            Some(from_slot(slot))
+        }
+    })
+}
+
+/// Makes a blocked or sleeping thread ready to run again. Returns `false`
+/// if it was neither, e.g. because something else woke it first.
+pub fn wake(thread: Arc<Thread>) -> bool {
+    make_ready(&mut READY.lock(), thread)
+}
+
+// Called with the run queue locked
+fn make_ready(ready: &mut VecDeque<Arc<Thread>>, thread: Arc<Thread>) -> bool {
+    if thread.transition(State::Sleeping, State::Ready) {
+        // Left in the sleep queue, which drops it on the next tick
+        *thread.wake_at.lock() = None;
+    } else if !thread.transition(State::Blocked, State::Ready) {
+        return false;
+    }
+
+    // A thread still switching out is queued by `finish_switch` once its
+    // context is saved
+    if !thread.on_cpu.load(Ordering::Acquire) {
+        ready.push_back(thread);
+    }
+
+    true
+}
+
+/// Switches to the next ready thread, or the idle thread when there is
+/// none. A running thread keeps the CPU if nothing else is ready. Called
+/// with interrupts disabled.
+pub(super) fn schedule() {
+    let per_cpu = percpu::current();
+    let current = per_cpu.current_task.load(Ordering::Acquire);
+    let idle = per_cpu.idle_task.load(Ordering::Acquire);
+
+    per_cpu.need_resched.store(false, Ordering::Relaxed);
+
+    let current_thread = unsafe { &*(current as *const Thread) };
+
+    let next = {
+        let mut ready = READY.lock();
+
+        // A thread woken before it got to switch out can simply continue
+        let runnable = matches!(current_thread.state(), State::Running | State::Ready);
+
+        let next = match ready.pop_front() {
+            Some(next) => next,
+            None if runnable || current == idle => {
+                current_thread.set_state(State::Running);
+                return;
+            }
+            None => unsafe {
+                Arc::increment_strong_count(idle as *const Thread);
+                from_slot(idle)
+            },
+        };
+
+        if current_thread.state() == State::Running {
+            current_thread.set_state(State::Ready);
+        }
+        next.set_state(State::Running);
+        next.on_cpu.store(true, Ordering::Release);
+
+        next
+    };
+
+    let next_stack_pointer = next.stack_pointer.load(Ordering::Relaxed);
+    per_cpu
+        .current_task
+        .store(into_slot(next), Ordering::Release);
+
+    // The previous thread can't be queued or woken until its context is
+    // saved, or another CPU could resume it first, so that is left to
+    // `finish_switch` on the other side
+    per_cpu.previous_task.store(current, Ordering::Release);
+
+    unsafe {
+        let stack_pointer = (*(current as *const Thread)).stack_pointer.as_ptr();
+        context::switch(stack_pointer, next_stack_pointer);
+    }
+
+    finish_switch();
+}
+
+/// Puts away the thread this CPU switched from. Runs right after every
+/// context switch, on the new thread's stack.
+pub(super) fn finish_switch() {
+    let per_cpu = percpu::current();
+    let previous = per_cpu
+        .previous_task
+        .swap(ptr::null_mut(), Ordering::AcqRel);
+
+    if previous.is_null() {
+        return;
+    }
+
+    let is_idle = previous == per_cpu.idle_task.load(Ordering::Acquire);
+    let previous = unsafe { from_slot(previous) };
+
+    // Decided under the lock `wake` takes, so a thread woken while it was
+    // switching out is queued exactly once. An exited thread is freed along
+    // with the last reference to it.
+    let mut ready = READY.lock();
+    previous.on_cpu.store(false, Ordering::Release);
+
+    // Threads without a stack of their own were adopted from a CPU's boot
+    // context, so exiting leaves the bootloader's stack behind
+    let left_boot_stack = previous.state() == State::Exited && previous.stack.is_none();
+
+    if previous.state() == State::Ready && !is_idle {
+        ready.push_back(previous);
+    }
+
+    drop(ready);
+
+    if left_boot_stack {
+        remove_boot_stacks(1);
+    }
+}
+
+/// Notes that `count` more CPUs were started on stacks the bootloader set
+/// up, so bootloader memory isn't reclaimed until they have left them.
+pub fn add_boot_stacks(count: usize) {
+    BOOT_STACKS.fetch_add(count, Ordering::AcqRel);
+}
+
+/// Notes that `count` boot stacks are no longer in use, or never will be,
+/// reclaiming bootloader memory once none are.
+pub fn remove_boot_stacks(count: usize) {
+    if BOOT_STACKS.fetch_sub(count, Ordering::AcqRel) == count {
+        frame::reclaim_bootloader_memory();
+    }
+}
+
+// Wakes sleeping threads whose wake-up time has passed
+fn wake_sleepers() {
+    let now = Instant::now();
+    let mut sleeping = SLEEPING.lock();
+    let mut ready = READY.lock();
+
+    sleeping.retain(|thread| {
+        let wake_at = *thread.wake_at.lock();
+
+        match wake_at {
+            Some(wake_at) if wake_at > now => true,
+            // Woken early, and may be waiting for something else by now
+            None => false,
+            Some(_) => {
+                make_ready(&mut ready, thread.clone());
+                false
+            }
+        }
+    });
+}
+
+fn tick() {
+    let per_cpu = percpu::current();
+
+    if per_cpu.id == 0 {
+        wake_sleepers();
+    }
+
+    // Every tick ends the running thread's time slice
+    per_cpu.need_resched.store(true, Ordering::Relaxed);
+}
+
+fn preempt() {
+    let per_cpu = percpu::current();
+
+    if per_cpu.need_resched.load(Ordering::Relaxed)
+        && !per_cpu.current_task.load(Ordering::Acquire).is_null()
+    {
+        schedule();
+    }
+}
+
+fn idle_loop() -> ! {
+    loop {
+        // `sti` only takes effect after the next instruction, so no wake-up
+        // can slip in between the two
+        cpu::enable_interrupts();
+        cpu::hlt();
+    }
+}
+
+// Adopts the code running on this CPU as the thread `name`
+fn adopt_current(name: &str) {
+    let thread = Thread::new(name.to_string(), None);
+    thread.set_state(State::Running);
+
+    percpu::current()
+        .current_task
+        .store(into_slot(thread), Ordering::Release);
+}
+
+// Creates the idle thread of the CPU numbered `id`
+fn idle_thread(id: u32) -> Arc<Thread> {
+    Thread::new(
+        alloc::format!("idle {id}"),
+        Some(Box::new(|| {
+            idle_loop();
+        })),
+    )
+}
+
+/// Turns the code running on the bootstrap processor into the thread
+/// `main`, creates its idle thread and starts preempting threads on every
+/// timer tick. Bootloader memory is reclaimed once `main` exits.
+pub fn init() {
+    cpu::without_interrupts(|| {
+        adopt_current("main");
+
+        let per_cpu = percpu::current();
+        per_cpu
+            .idle_task
+            .store(into_slot(idle_thread(per_cpu.id)), Ordering::Release);
+    });
+
+    time::set_tick_hook(tick);
+    interrupts::set_preempt_hook(preempt);
+}
+
+/// Creates the idle thread of an application processor and starts running
+/// threads on it, leaving the stack it booted on.
+pub fn init_ap() -> ! {
+    cpu::disable_interrupts();
+
+    let per_cpu = percpu::current();
+    adopt_current(&alloc::format!("boot {}", per_cpu.id));
+    per_cpu
+        .idle_task
+        .store(into_slot(idle_thread(per_cpu.id)), Ordering::Release);
+
+    super::exit()
+}
@@ -0,0 +1,101 @@
+//! In-kernel test runner for `#[test_case]` functions.
+//!
+//! Results are reported over the serial port using a line protocol that
+//! `cargo xtask test` parses, and QEMU is shut down through its
+//! `isa-debug-exit` device so the exit status reflects the outcome:
+//!
+//! ```text
+//! ignis-test: running <n> tests
+//! ignis-test: <name> ... ok
+//! ignis-test: <name> ... FAILED
+//! ignis-test: result: ok
+//! ignis-test: result: FAILED
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use crate::arch::x86_64::port::outl;
+use crate::sync::SpinLock;
+use crate::{serial, serial_println};
+
+// I/O port of QEMU's isa-debug-exit device (`-device isa-debug-exit,iobase=0xf4,iosize=0x04`)
+const QEMU_EXIT_PORT: u16 = 0xF4;
+
+// Name of the running test, so a panic can report which one failed
+static CURRENT_TEST: SpinLock<&str> = SpinLock::new("test", "");
+
+/// Exit codes written to the isa-debug-exit device. QEMU exits with
+/// `(code << 1) | 1`, so these become process exit statuses 33 and 35.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// Shuts QEMU down with the given exit code.
+pub fn exit_qemu(exit_code: QemuExitCode) -> ! {
+    unsafe { outl(QEMU_EXIT_PORT, exit_code as u32) };
+
+    // Only reached when the debug exit device is missing
+    loop {
+        unsafe { core::arch::asm!("cli", "hlt") };
+    }
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        let name = core::any::type_name::<T>();
+        *CURRENT_TEST.lock() = name;
+
+        self();
+
+        // The whole line is written at once, after anything the test printed
+        serial_println!("ignis-test: {name} ... ok");
+    }
+}
+
+/// Entry point for the generated `test_main`.
+pub fn runner(tests: &[&dyn Testable]) {
+    serial_println!("ignis-test: running {} tests", tests.len());
+
+    for test in tests {
+        test.run();
+    }
+
+    serial_println!("ignis-test: result: ok");
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Reports the running test as failed. Called from the panic handler.
+pub fn fail(info: &PanicInfo<'_>) {
+    let name = *CURRENT_TEST.lock();
+
+    // The panic may have left the serial port's lock held
+    let mut serial = serial::panic_writer();
+    let _ = writeln!(serial, "{info}");
+    let _ = writeln!(serial, "ignis-test: {name} ... FAILED");
+    let _ = writeln!(serial, "ignis-test: result: FAILED");
+    exit_qemu(QemuExitCode::Failed);
+}
+
+#[test_case]
+fn heap_allocation() {
+    let boxed = Box::new(41);
+    assert_eq!(*boxed + 1, 42);
+
+    let values: Vec<u64> = (0..1000).collect();
+    assert_eq!(values.iter().sum::<u64>(), 999 * 1000 / 2);
+}
+
+#[test_case]
+fn println_reaches_console() {
+    crate::println!("test output");
+}
@@ -0,0 +1,70 @@
+use core::ptr;
+use core::time::Duration;
+
+use spin::Once;
+
+use crate::acpi::{self, AddressSpace};
+use crate::memory::paging;
+
+// Registers, as offsets from the HPET base
+const REGISTER_CAPABILITIES: u64 = 0x000;
+const REGISTER_CONFIGURATION: u64 = 0x010;
+const REGISTER_MAIN_COUNTER: u64 = 0x0F0;
+
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+
+struct Hpet {
+    base: u64,
+    // Length of a main counter tick
+    period_fs: u64,
+}
+
+static HPET: Once<Hpet> = Once::new();
+
+fn read(base: u64, register: u64) -> u64 {
+    unsafe { ptr::read_volatile((base + register) as *const u64) }
+}
+
+fn write(base: u64, register: u64, value: u64) {
+    unsafe { ptr::write_volatile((base + register) as *mut u64, value) };
+}
+
+/// Enables the HPET main counter, returning false when ACPI doesn't
+/// describe an HPET.
+pub fn init() -> bool {
+    let Some(table) = acpi::hpet::get() else {
+        return false;
+    };
+
+    if table.base_address.address_space != AddressSpace::SystemMemory {
+        return false;
+    }
+
+    let base = paging::map_mmio(table.base_address.address, 0x400);
+    let period_fs = read(base, REGISTER_CAPABILITIES) >> 32;
+
+    let configuration = read(base, REGISTER_CONFIGURATION);
+    write(
+        base,
+        REGISTER_CONFIGURATION,
+        configuration | CONFIGURATION_ENABLE,
+    );
+
+    HPET.call_once(|| Hpet { base, period_fs });
+
+    true
+}
+
+/// Busy-waits for `duration` by polling the main counter.
+pub fn wait(duration: Duration) {
+    let hpet = HPET.get().expect("HPET not initialized");
+
+    let ticks = duration.as_nanos() as u64 * FEMTOSECONDS_PER_NANOSECOND / hpet.period_fs;
+    let start = read(hpet.base, REGISTER_MAIN_COUNTER);
+
+    while read(hpet.base, REGISTER_MAIN_COUNTER).wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,301 @@
+use core::fmt;
+use core::ops::{Add, Sub};
+use core::time::Duration;
+
+use spin::Once;
+
+use crate::arch::x86_64::{apic, cpu, interrupts};
+use crate::boot;
+
+mod hpet;
+mod pit;
+pub mod rtc;
+
+/// Frequency of the periodic timer tick.
+pub const TICK_HZ: u64 = 100;
+
+// Length of the window the TSC and local APIC timer are measured over
+const CALIBRATION_WINDOW: Duration = Duration::from_millis(10);
+
+const NANOSECONDS_PER_SECOND: u128 = 1_000_000_000;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+struct Calibration {
+    // TSC value at the zero instant
+    tsc_base: u64,
+    tsc_hz: u64,
+    lapic_timer_hz: u64,
+}
+
+static CALIBRATION: Once<Calibration> = Once::new();
+
+// Unix time read at boot, and the instant it was read at
+static WALL_CLOCK_BASE: Once<(u64, Instant)> = Once::new();
+
+static TICK_HOOK: Once<fn()> = Once::new();
+
+/// A point on the monotonic clock, which counts from when timekeeping was
+/// initialized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    nanoseconds: u64,
+}
+
+impl Instant {
+    /// Returns the current time. Before timekeeping is initialized this is
+    /// always the zero instant.
+    pub fn now() -> Self {
+        let Some(calibration) = CALIBRATION.get() else {
+            return Self { nanoseconds: 0 };
+        };
+
+        let cycles = cpu::rdtsc().saturating_sub(calibration.tsc_base);
+        let nanoseconds = cycles as u128 * NANOSECONDS_PER_SECOND / calibration.tsc_hz as u128;
+
+        Self {
+            nanoseconds: nanoseconds as u64,
+        }
+    }
+
+    /// Time elapsed since `earlier`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanoseconds.saturating_sub(earlier.nanoseconds))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    // Saturates, so a duration too long to represent waits forever
+    fn add(self, duration: Duration) -> Instant {
+        let nanoseconds = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+
+        Instant {
+            nanoseconds: self.nanoseconds.saturating_add(nanoseconds),
+        }
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Time since boot.
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant { nanoseconds: 0 })
+}
+
+/// Busy-waits for `duration`. Works with interrupts disabled, and before
+/// timekeeping is initialized.
+pub fn delay(duration: Duration) {
+    // The clock stands still until it is calibrated, so time the wait with
+    // the PIT instead, in pieces short enough for it
+    if CALIBRATION.get().is_none() {
+        let mut remaining = duration;
+
+        while !remaining.is_zero() {
+            let step = remaining.min(pit::MAX_WAIT);
+            pit::wait(step);
+            remaining -= step;
+        }
+
+        return;
+    }
+
+    let deadline = Instant::now() + duration;
+
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Installs a function called on every timer tick of every CPU, from
+/// interrupt context.
+pub fn set_tick_hook(hook: fn()) {
+    TICK_HOOK.call_once(|| hook);
+}
+
+fn tick(_frame: &mut interrupts::InterruptFrame) {
+    if let Some(hook) = TICK_HOOK.get() {
+        hook();
+    }
+}
+
+/// Calibrates the TSC and local APIC timer against the HPET, or the PIT
+/// when there is none, reads the wall clock and starts the periodic tick.
+pub fn init() {
+    let (wait, reference): (fn(Duration), _) = if hpet::init() {
+        (hpet::wait, "HPET")
+    } else {
+        (pit::wait, "PIT")
+    };
+
+    let calibration = cpu::without_interrupts(|| {
+        apic::start_timer_calibration(u32::MAX);
+        let tsc_start = cpu::rdtsc();
+
+        wait(CALIBRATION_WINDOW);
+
+        let tsc_elapsed = cpu::rdtsc() - tsc_start;
+        let lapic_timer_elapsed = u32::MAX - apic::timer_count();
+        apic::stop_timer();
+
+        let windows_per_second = Duration::from_secs(1).as_nanos() / CALIBRATION_WINDOW.as_nanos();
+
+        Calibration {
+            tsc_base: tsc_start,
+            tsc_hz: tsc_elapsed * windows_per_second as u64,
+            lapic_timer_hz: lapic_timer_elapsed as u64 * windows_per_second as u64,
+        }
+    });
+
+    log::info!(
+        "Calibrated against the {reference}: TSC {} MHz, local APIC timer {} MHz",
+        calibration.tsc_hz / 1_000_000,
+        calibration.lapic_timer_hz / 1_000_000,
+    );
+
+    CALIBRATION.call_once(|| calibration);
+
+    // Prefer the time Limine read at boot over reading the RTC ourselves
+    let unix_time = boot::DATE_AT_BOOT_REQUEST
+        .get_response()
+        .map(|response| response.timestamp().as_secs())
+        .unwrap_or_else(|| rtc::read().to_unix());
+
+    WALL_CLOCK_BASE.call_once(|| (unix_time, Instant::now()));
+
+    interrupts::set_handler(apic::TIMER_VECTOR, tick);
+    start_tick();
+}
+
+/// Starts the periodic tick on the current CPU.
+pub fn start_tick() {
+    let calibration = CALIBRATION.get().expect("time not initialized");
+
+    apic::start_timer_periodic((calibration.lapic_timer_hz / TICK_HZ) as u32);
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    let (unix_time, read_at) = WALL_CLOCK_BASE.get().expect("time not initialized");
+
+    unix_time + read_at.elapsed().as_secs()
+}
+
+/// Current date and time in UTC.
+pub fn now() -> DateTime {
+    DateTime::from_unix(unix_time())
+}
+
+/// A calendar date and time of day, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    // Conversions follow Howard Hinnant's civil calendar algorithms, with
+    // unsigned arithmetic since dates before 1970 never come up
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = seconds / SECONDS_PER_DAY + 719_468;
+        let time = seconds % SECONDS_PER_DAY;
+
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+        Self {
+            year: year as u32,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    pub fn to_unix(self) -> u64 {
+        let (month, day) = (self.month as u64, self.day as u64);
+        let year = self.year as u64 - (month <= 2) as u64;
+
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let shifted_month = (month + 9) % 12;
+        let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn unix_time_round_trips() {
+        let date = DateTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 13,
+            minute: 37,
+            second: 42,
+        };
+
+        assert_eq!(date.to_unix(), 1_709_213_862);
+        assert_eq!(DateTime::from_unix(date.to_unix()), date);
+    }
+
+    #[test_case]
+    fn adding_huge_durations_saturates() {
+        let now = Instant::now();
+
+        assert_eq!(
+            now + Duration::MAX,
+            Instant {
+                nanoseconds: u64::MAX
+            }
+        );
+        assert!(now + Duration::from_secs(1) > now);
+    }
+}
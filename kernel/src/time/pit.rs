@@ -0,0 +1,46 @@
+use core::time::Duration;
+
+use crate::arch::x86_64::port::{inb, outb};
+
+// Input clock of the 8253/8254 programmable interval timer
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_2_DATA: u16 = 0x42;
+const MODE_COMMAND: u16 = 0x43;
+
+// Controls the channel 2 gate and the PC speaker, and reports channel 2's output
+const SPEAKER_CONTROL: u16 = 0x61;
+const SPEAKER_GATE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+// Channel 2, low then high byte, mode 0 (interrupt on terminal count), binary
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Longest wait [`wait`] can time, just under its 16-bit count's limit.
+pub const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// Busy-waits for `duration` (at most about 54 ms) using PIT channel 2,
+/// which counts down without raising interrupts.
+pub fn wait(duration: Duration) {
+    let count = (PIT_FREQUENCY * duration.as_micros() as u64 / 1_000_000).clamp(1, 0xFFFF);
+
+    unsafe {
+        // Keep the speaker silent and the gate low while programming
+        let control = inb(SPEAKER_CONTROL) & !(SPEAKER_GATE | SPEAKER_ENABLE);
+        outb(SPEAKER_CONTROL, control);
+
+        outb(MODE_COMMAND, CHANNEL_2_ONE_SHOT);
+        outb(CHANNEL_2_DATA, count as u8);
+        outb(CHANNEL_2_DATA, (count >> 8) as u8);
+
+        // A rising gate edge starts the count
+        outb(SPEAKER_CONTROL, control | SPEAKER_GATE);
+
+        while inb(SPEAKER_CONTROL) & CHANNEL_2_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+
+        outb(SPEAKER_CONTROL, control);
+    }
+}
@@ -0,0 +1,102 @@
+use super::DateTime;
+use crate::acpi::fadt;
+use crate::arch::x86_64::port::{inb, outb};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// Setting bit 7 of the address keeps NMIs disabled while accessing CMOS
+const NMI_DISABLE: u8 = 1 << 7;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+const REGISTER_STATUS_D: u8 = 0x0D;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOURS_PM: u8 = 1 << 7;
+
+fn read_register(register: u8) -> u8 {
+    unsafe {
+        outb(CMOS_ADDRESS, NMI_DISABLE | register);
+        inb(CMOS_DATA)
+    }
+}
+
+fn read_raw() -> [u8; 6] {
+    while read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    [
+        REGISTER_SECONDS,
+        REGISTER_MINUTES,
+        REGISTER_HOURS,
+        REGISTER_DAY,
+        REGISTER_MONTH,
+        REGISTER_YEAR,
+    ]
+    .map(read_register)
+}
+
+/// Reads the date and time from the CMOS real-time clock, assumed to be UTC.
+pub fn read() -> DateTime {
+    // An update can still start between the check and the reads, so read
+    // until two consecutive snapshots agree
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = read_register(REGISTER_STATUS_B);
+    let bcd = status_b & STATUS_B_BINARY == 0;
+    let decode = |value: u8| {
+        if bcd {
+            (value & 0x0F) + (value >> 4) * 10
+        } else {
+            value
+        }
+    };
+
+    let [second, minute, hours, day, month, year] = raw;
+
+    let pm = hours & HOURS_PM != 0;
+    let mut hour = decode(hours & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (hour, true) => hour + 12,
+            (hour, false) => hour,
+        };
+    }
+
+    // The century register is only present when the FADT names it;
+    // otherwise assume the 2000s
+    let century = fadt::get()
+        .and_then(|fadt| fadt.century_register)
+        .map_or(20, |register| decode(read_register(register)) as u32);
+
+    // Selecting a register with bit 7 clear enables NMIs again
+    unsafe { outb(CMOS_ADDRESS, REGISTER_STATUS_D) };
+
+    DateTime {
+        year: century * 100 + decode(year) as u32,
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second),
+    }
+}
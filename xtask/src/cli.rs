@@ -0,0 +1,311 @@
+use std::ffi::OsString;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+usage: cargo xtask [command] [options] [-- <qemu args>...]
+
+commands:
+    build       build the kernel
+    iso         build the kernel and create target/ignis.iso
+    image       build the kernel and create the GPT disk image target/ignis.img
+    run         create the ISO and boot it in QEMU (default)
+    test        boot the kernel's #[test_case] tests headlessly in QEMU
+    gdb         boot QEMU paused with a GDB stub and attach gdb (alias: debug)
+    clean       remove xtask outputs (--all also runs `cargo clean`)
+    help        print this message
+
+options:
+    --release           use the release profile
+    -m, --memory <size> guest memory size, passed to QEMU's -m [default: 2G]
+    -c, --cpus <n>      number of guest CPUs [default: 1]
+    --image             boot the GPT disk image instead of the ISO
+    --headless          with `run`, show no display and connect the serial
+                        port to the terminal
+    --resolution <mode> video mode for Limine to set, as WxH or WxHxBPP (the
+                        kernel's `displays` command lists them)
+                        [default: Limine's choice]
+    --bios              boot using legacy BIOS instead of UEFI
+    --uefi              boot using UEFI firmware, failing if OVMF is missing
+                        [default: UEFI if OVMF is found, BIOS otherwise]
+    --timeout <secs>    timeout for `test` [default: 60]
+    --gdb-port <port>   port for the GDB stub used by `gdb` [default: 1234]
+    --all               with `clean`, remove the whole target directory
+
+Arguments after `--` are passed to QEMU verbatim.
+
+OVMF firmware is searched for in the usual distro locations; set OVMF_CODE
+and OVMF_VARS together to use specific images.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subcommand {
+    Build,
+    Iso,
+    Image,
+    Run,
+    Test,
+    Gdb,
+    Clean,
+    Help,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    Debug,
+    Release,
+}
+
+impl Profile {
+    /// Name of the profile's directory under `target/<triple>/`.
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Release => "release",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Firmware {
+    Bios,
+    Uefi,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cli {
+    pub subcommand: Subcommand,
+    pub profile: Profile,
+    pub memory: String,
+    pub cpus: u32,
+    /// Boot the disk image rather than the ISO.
+    pub image: bool,
+    /// Run without a display, with the serial port on the terminal.
+    pub headless: bool,
+    /// Video mode for Limine's `resolution` option, as `WxH` or `WxHxBPP`.
+    pub resolution: Option<String>,
+    /// Requested firmware, or `None` to pick UEFI when OVMF is available.
+    pub firmware: Option<Firmware>,
+    pub timeout: Duration,
+    pub gdb_port: u16,
+    pub clean_all: bool,
+    pub qemu_args: Vec<String>,
+}
+
+impl Default for Cli {
+    fn default() -> Self {
+        Self {
+            subcommand: Subcommand::Run,
+            profile: Profile::Debug,
+            memory: "2G".to_string(),
+            cpus: 1,
+            image: false,
+            headless: false,
+            resolution: None,
+            firmware: None,
+            timeout: Duration::from_secs(60),
+            gdb_port: 1234,
+            clean_all: false,
+            qemu_args: Vec::new(),
+        }
+    }
+}
+
+fn subcommand(name: &str) -> Option<Subcommand> {
+    let subcommand = match name {
+        "build" => Subcommand::Build,
+        "iso" => Subcommand::Iso,
+        "image" => Subcommand::Image,
+        "run" => Subcommand::Run,
+        "test" => Subcommand::Test,
+        "gdb" | "debug" => Subcommand::Gdb,
+        "clean" => Subcommand::Clean,
+        "help" => Subcommand::Help,
+        _ => return None,
+    };
+
+    Some(subcommand)
+}
+
+fn value(
+    flag: &str,
+    args: &mut impl Iterator<Item = Result<String, OsString>>,
+) -> Result<String, String> {
+    match args.next() {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(value)) => Err(format!("{flag}: invalid value {value:?}")),
+        None => Err(format!("{flag}: missing value")),
+    }
+}
+
+fn resolution(flag: &str, value: String) -> Result<String, String> {
+    let parts: Vec<&str> = value.split('x').collect();
+    let numeric = parts
+        .iter()
+        .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()));
+
+    if numeric && (parts.len() == 2 || parts.len() == 3) {
+        Ok(value)
+    } else {
+        Err(format!("{flag}: expected WxH or WxHxBPP, got {value:?}"))
+    }
+}
+
+fn parsed<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{flag}: invalid value {value:?}"))
+}
+
+/// Parses the command line, not including the program name.
+pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Cli, String> {
+    let mut args = args.into_iter().map(OsString::into_string);
+    let mut cli = Cli::default();
+    let mut subcommand_seen = false;
+    let mut help = false;
+
+    while let Some(arg) = args.next() {
+        let arg = arg.map_err(|arg| format!("invalid argument {arg:?}"))?;
+
+        match arg.as_str() {
+            "--" => {
+                cli.qemu_args = args
+                    .by_ref()
+                    .collect::<Result<_, _>>()
+                    .map_err(|arg| format!("invalid QEMU argument {arg:?}"))?;
+            }
+            "-h" | "--help" => help = true,
+            "--release" => cli.profile = Profile::Release,
+            "--image" => cli.image = true,
+            "--headless" => cli.headless = true,
+            "--bios" => cli.firmware = Some(Firmware::Bios),
+            "--uefi" => cli.firmware = Some(Firmware::Uefi),
+            "--all" => cli.clean_all = true,
+            "-m" | "--memory" => cli.memory = value(&arg, &mut args)?,
+            "-c" | "--cpus" => cli.cpus = parsed(&arg, value(&arg, &mut args)?)?,
+            "--timeout" => {
+                let secs = parsed(&arg, value(&arg, &mut args)?)?;
+
+                cli.timeout = Duration::from_secs(secs);
+            }
+            "--resolution" => cli.resolution = Some(resolution(&arg, value(&arg, &mut args)?)?),
+            "--gdb-port" => cli.gdb_port = parsed(&arg, value(&arg, &mut args)?)?,
+            flag if flag.starts_with('-') => return Err(format!("unknown option: {flag}")),
+            name if !subcommand_seen => {
+                cli.subcommand =
+                    subcommand(name).ok_or_else(|| format!("unknown command: {name}"))?;
+                subcommand_seen = true;
+            }
+            extra => return Err(format!("unexpected argument: {extra}")),
+        }
+    }
+
+    if help {
+        cli.subcommand = Subcommand::Help;
+    }
+
+    if cli.cpus == 0 {
+        return Err("--cpus: must be at least 1".to_string());
+    }
+
+    Ok(cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Cli, String> {
+        parse(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn defaults_to_run() {
+        let cli = parse_args(&[]).unwrap();
+
+        assert_eq!(cli.subcommand, Subcommand::Run);
+        assert_eq!(cli.profile, Profile::Debug);
+        assert_eq!(cli.memory, "2G");
+        assert_eq!(cli.cpus, 1);
+        assert_eq!(cli.firmware, None);
+        assert_eq!(cli.resolution, None);
+    }
+
+    #[test]
+    fn parses_subcommands_and_flags() {
+        let cli = parse_args(&[
+            "--release",
+            "test",
+            "-m",
+            "512M",
+            "--cpus",
+            "4",
+            "--bios",
+            "--timeout",
+            "30",
+            "--gdb-port",
+            "4321",
+            "--image",
+            "--headless",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.subcommand, Subcommand::Test);
+        assert_eq!(cli.profile, Profile::Release);
+        assert_eq!(cli.memory, "512M");
+        assert_eq!(cli.cpus, 4);
+        assert_eq!(cli.firmware, Some(Firmware::Bios));
+        assert_eq!(cli.timeout, Duration::from_secs(30));
+        assert_eq!(cli.gdb_port, 4321);
+        assert!(cli.image);
+        assert!(cli.headless);
+
+        assert_eq!(parse_args(&["debug"]).unwrap().subcommand, Subcommand::Gdb);
+        assert!(parse_args(&["clean", "--all"]).unwrap().clean_all);
+        assert_eq!(parse_args(&["-c", "2"]).unwrap().cpus, 2);
+    }
+
+    #[test]
+    fn help_overrides_the_subcommand() {
+        let cli = parse_args(&["build", "--help"]).unwrap();
+
+        assert_eq!(cli.subcommand, Subcommand::Help);
+    }
+
+    #[test]
+    fn passes_arguments_after_separator_to_qemu() {
+        let cli = parse_args(&["run", "--", "--release", "-d", "int"]).unwrap();
+
+        assert_eq!(cli.profile, Profile::Debug);
+        assert_eq!(cli.qemu_args, ["--release", "-d", "int"]);
+    }
+
+    #[test]
+    fn parses_resolution() {
+        let cli = parse_args(&["--resolution", "1280x720"]).unwrap();
+        assert_eq!(cli.resolution.as_deref(), Some("1280x720"));
+
+        let cli = parse_args(&["--resolution", "1920x1080x32"]).unwrap();
+        assert_eq!(cli.resolution.as_deref(), Some("1920x1080x32"));
+
+        for bad in ["1280", "1280x", "x720", "1280x720x32x1", "1280X720", "wide"] {
+            assert!(parse_args(&["--resolution", bad]).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for args in [
+            &["frobnicate"][..],
+            &["build", "iso"],
+            &["--verbose"],
+            &["--memory"],
+            &["--cpus", "many"],
+            &["-s", "2"],
+            &["--cpus", "0"],
+            &["--timeout", "-1"],
+            &["--gdb-port", "70000"],
+            &["--resolution"],
+        ] {
+            assert!(parse_args(args).is_err(), "{args:?}");
+        }
+    }
+}
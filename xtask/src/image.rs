@@ -0,0 +1,446 @@
+//! Raw GPT disk images with a FAT32 EFI system partition, built without
+//! loop devices or root.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use fatfs::{FatType, FileSystem, FormatVolumeOptions, FsOptions};
+
+const SECTOR_SIZE: u64 = 512;
+
+// Partitions start on 1 MiB boundaries, as most partitioning tools do
+const ALIGNMENT: u64 = 1024 * 1024;
+
+// Size of the EFI system partition. FAT32 needs at least 65525 clusters, and
+// with 512 byte clusters this comfortably clears that.
+const ESP_SIZE: u64 = 64 * 1024 * 1024;
+
+const PARTITION_ENTRY_COUNT: u64 = 128;
+const PARTITION_ENTRY_SIZE: u64 = 128;
+const PARTITION_ARRAY_SECTORS: u64 = PARTITION_ENTRY_COUNT * PARTITION_ENTRY_SIZE / SECTOR_SIZE;
+
+// C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+const EFI_SYSTEM_PARTITION: Guid = Guid::new(
+    0xC12A7328,
+    0xF81F,
+    0x11D2,
+    [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B],
+);
+
+/// A GUID in its on-disk mixed-endian encoding.
+#[derive(Clone, Copy, Debug)]
+struct Guid([u8; 16]);
+
+impl Guid {
+    const fn new(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Self {
+        let data1 = data1.to_le_bytes();
+        let data2 = data2.to_le_bytes();
+        let data3 = data3.to_le_bytes();
+
+        Self([
+            data1[0], data1[1], data1[2], data1[3], data2[0], data2[1], data3[0], data3[1],
+            data4[0], data4[1], data4[2], data4[3], data4[4], data4[5], data4[6], data4[7],
+        ])
+    }
+
+    /// Generates a random (version 4) GUID.
+    fn random() -> Self {
+        let mut bytes = [0; 16];
+
+        // RandomState is seeded from the OS, which is all the randomness we need
+        for chunk in bytes.chunks_mut(8) {
+            let random = RandomState::new().build_hasher().finish();
+
+            chunk.copy_from_slice(&random.to_le_bytes());
+        }
+
+        bytes[7] = (bytes[7] & 0x0F) | 0x40;
+        bytes[8] = (bytes[8] & 0x3F) | 0x80;
+
+        Self(bytes)
+    }
+}
+
+/// A file to place on the EFI system partition.
+pub struct Entry<'a> {
+    pub source: &'a Path,
+    /// Slash-separated path within the partition.
+    pub destination: &'a str,
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+/// A window onto part of a disk image, so a filesystem can be formatted and
+/// populated in place.
+struct Partition<'a> {
+    disk: &'a mut File,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<'a> Partition<'a> {
+    fn new(disk: &'a mut File, start: u64, len: u64) -> io::Result<Self> {
+        disk.seek(SeekFrom::Start(start))?;
+
+        Ok(Self {
+            disk,
+            start,
+            len,
+            position: 0,
+        })
+    }
+
+    fn remaining(&self, buf_len: usize) -> usize {
+        buf_len.min(self.len.saturating_sub(self.position) as usize)
+    }
+}
+
+impl Read for Partition<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.remaining(buf.len());
+        let read = self.disk.read(&mut buf[..len])?;
+
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Write for Partition<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.remaining(buf.len());
+
+        if len == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "write past the end of the partition",
+            ));
+        }
+
+        let written = self.disk.write(&buf[..len])?;
+
+        self.position += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.disk.flush()
+    }
+}
+
+impl Seek for Partition<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        let Some(position) = position.filter(|&position| position <= self.len) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek outside the partition",
+            ));
+        };
+
+        self.disk.seek(SeekFrom::Start(self.start + position))?;
+        self.position = position;
+
+        Ok(position)
+    }
+}
+
+/// A GPT partition table entry.
+struct GptPartition {
+    type_guid: Guid,
+    first_lba: u64,
+    last_lba: u64,
+    name: &'static str,
+}
+
+impl GptPartition {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut entry = Vec::with_capacity(PARTITION_ENTRY_SIZE as usize);
+
+        entry.extend_from_slice(&self.type_guid.0);
+        entry.extend_from_slice(&Guid::random().0);
+        entry.extend_from_slice(&self.first_lba.to_le_bytes());
+        entry.extend_from_slice(&self.last_lba.to_le_bytes());
+        entry.extend_from_slice(&0u64.to_le_bytes()); // Attributes
+
+        for unit in self.name.encode_utf16().take(36) {
+            entry.extend_from_slice(&unit.to_le_bytes());
+        }
+
+        entry.resize(PARTITION_ENTRY_SIZE as usize, 0);
+        entry
+    }
+}
+
+fn gpt_header(
+    disk_guid: Guid,
+    current_lba: u64,
+    backup_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    entries_lba: u64,
+    entries_crc: u32,
+) -> Vec<u8> {
+    let mut header = Vec::with_capacity(SECTOR_SIZE as usize);
+
+    header.extend_from_slice(b"EFI PART");
+    header.extend_from_slice(&0x0001_0000u32.to_le_bytes()); // Revision 1.0
+    header.extend_from_slice(&92u32.to_le_bytes()); // Header size
+    header.extend_from_slice(&0u32.to_le_bytes()); // Header CRC, filled in below
+    header.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+    header.extend_from_slice(&current_lba.to_le_bytes());
+    header.extend_from_slice(&backup_lba.to_le_bytes());
+    header.extend_from_slice(&first_usable_lba.to_le_bytes());
+    header.extend_from_slice(&last_usable_lba.to_le_bytes());
+    header.extend_from_slice(&disk_guid.0);
+    header.extend_from_slice(&entries_lba.to_le_bytes());
+    header.extend_from_slice(&(PARTITION_ENTRY_COUNT as u32).to_le_bytes());
+    header.extend_from_slice(&(PARTITION_ENTRY_SIZE as u32).to_le_bytes());
+    header.extend_from_slice(&entries_crc.to_le_bytes());
+
+    let crc = crc32(&header);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+
+    header.resize(SECTOR_SIZE as usize, 0);
+    header
+}
+
+fn protective_mbr(total_sectors: u64) -> Vec<u8> {
+    let mut mbr = vec![0; SECTOR_SIZE as usize];
+    let sectors = (total_sectors - 1).min(u64::from(u32::MAX)) as u32;
+
+    // A single partition of type 0xEE covering the whole disk
+    let entry = &mut mbr[446..462];
+    entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]); // CHS of LBA 1
+    entry[4] = 0xEE;
+    entry[5..8].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    mbr
+}
+
+fn write_at(disk: &mut File, lba: u64, bytes: &[u8]) -> io::Result<()> {
+    disk.seek(SeekFrom::Start(lba * SECTOR_SIZE))?;
+    disk.write_all(bytes)
+}
+
+fn write_gpt(disk: &mut File, total_sectors: u64, partitions: &[GptPartition]) -> io::Result<()> {
+    let last_lba = total_sectors - 1;
+    let first_usable_lba = 2 + PARTITION_ARRAY_SECTORS;
+    let last_usable_lba = last_lba - PARTITION_ARRAY_SECTORS - 1;
+    let backup_entries_lba = last_lba - PARTITION_ARRAY_SECTORS;
+
+    let mut entries: Vec<u8> = partitions.iter().flat_map(GptPartition::to_bytes).collect();
+    entries.resize((PARTITION_ENTRY_COUNT * PARTITION_ENTRY_SIZE) as usize, 0);
+
+    let entries_crc = crc32(&entries);
+    let disk_guid = Guid::random();
+
+    let primary = gpt_header(
+        disk_guid,
+        1,
+        last_lba,
+        first_usable_lba,
+        last_usable_lba,
+        2,
+        entries_crc,
+    );
+
+    let backup = gpt_header(
+        disk_guid,
+        last_lba,
+        1,
+        first_usable_lba,
+        last_usable_lba,
+        backup_entries_lba,
+        entries_crc,
+    );
+
+    write_at(disk, 0, &protective_mbr(total_sectors))?;
+    write_at(disk, 1, &primary)?;
+    write_at(disk, 2, &entries)?;
+    write_at(disk, backup_entries_lba, &entries)?;
+    write_at(disk, last_lba, &backup)
+}
+
+fn populate(filesystem: &FileSystem<Partition<'_>>, entries: &[Entry<'_>]) -> io::Result<()> {
+    for entry in entries {
+        let root = filesystem.root_dir();
+        let (parents, name) = entry
+            .destination
+            .rsplit_once('/')
+            .unwrap_or(("", entry.destination));
+
+        let mut dir = root;
+        for component in parents.split('/').filter(|component| !component.is_empty()) {
+            dir = dir.create_dir(component)?;
+        }
+
+        let contents = fs::read(entry.source)?;
+        let mut file = dir.create_file(name)?;
+
+        file.truncate()?;
+        file.write_all(&contents)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a GPT disk image to `output` with a single FAT32 EFI system
+/// partition containing `entries`.
+pub fn create(output: &Path, entries: &[Entry<'_>]) -> Result<(), String> {
+    let display = output.display();
+    let error = |error: io::Error| format!("image: {display}: {error}");
+
+    let esp_start = ALIGNMENT;
+    let esp_end = esp_start + ESP_SIZE;
+
+    // Leave room for the backup GPT, rounded up to the next boundary
+    let total_size = align_up(
+        esp_end + (PARTITION_ARRAY_SECTORS + 1) * SECTOR_SIZE,
+        ALIGNMENT,
+    );
+    let total_sectors = total_size / SECTOR_SIZE;
+
+    let mut disk = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output)
+        .map_err(error)?;
+
+    disk.set_len(total_size).map_err(error)?;
+
+    let esp = GptPartition {
+        type_guid: EFI_SYSTEM_PARTITION,
+        first_lba: esp_start / SECTOR_SIZE,
+        last_lba: esp_end / SECTOR_SIZE - 1,
+        name: "EFI System Partition",
+    };
+
+    write_gpt(&mut disk, total_sectors, &[esp]).map_err(error)?;
+
+    let options = FormatVolumeOptions::new()
+        .fat_type(FatType::Fat32)
+        .bytes_per_cluster(SECTOR_SIZE as u32)
+        .volume_label(*b"IGNIS ESP  ");
+
+    fatfs::format_volume(
+        Partition::new(&mut disk, esp_start, ESP_SIZE).map_err(error)?,
+        options,
+    )
+    .map_err(error)?;
+
+    let filesystem = FileSystem::new(
+        Partition::new(&mut disk, esp_start, ESP_SIZE).map_err(error)?,
+        FsOptions::new(),
+    )
+    .map_err(error)?;
+
+    populate(&filesystem, entries).map_err(error)?;
+
+    filesystem.unmount().map_err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn builds_gpt_image_with_files_on_the_esp() {
+        let dir = env::temp_dir().join(format!("xtask-image-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let loader = dir.join("loader");
+        let kernel = dir.join("kernel");
+        fs::write(&loader, b"loader contents").unwrap();
+        fs::write(&kernel, vec![0x5A; 3000]).unwrap();
+
+        let output = dir.join("disk.img");
+        create(
+            &output,
+            &[
+                Entry {
+                    source: &loader,
+                    destination: "EFI/BOOT/BOOTX64.EFI",
+                },
+                Entry {
+                    source: &kernel,
+                    destination: "kernel",
+                },
+            ],
+        )
+        .unwrap();
+
+        let mut disk = File::open(&output).unwrap();
+        let mut sectors = vec![0; 2 * SECTOR_SIZE as usize];
+        disk.read_exact(&mut sectors).unwrap();
+
+        // Protective MBR, then the primary GPT header with a valid CRC
+        assert_eq!(sectors[450], 0xEE);
+        assert_eq!(sectors[510..512], [0x55, 0xAA]);
+        let header = &mut sectors[SECTOR_SIZE as usize..SECTOR_SIZE as usize + 92];
+        assert_eq!(&header[..8], b"EFI PART");
+        let crc = u32::from_le_bytes(header[16..20].try_into().unwrap());
+        header[16..20].fill(0);
+        assert_eq!(crc32(header), crc);
+
+        let filesystem = FileSystem::new(
+            Partition::new(&mut disk, ALIGNMENT, ESP_SIZE).unwrap(),
+            FsOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(filesystem.fat_type(), FatType::Fat32);
+
+        let read = |path: &str| {
+            let mut contents = Vec::new();
+            filesystem
+                .root_dir()
+                .open_file(path)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert_eq!(read("EFI/BOOT/BOOTX64.EFI"), b"loader contents");
+        assert_eq!(read("kernel"), vec![0x5A; 3000]);
+
+        drop(filesystem);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::create_dir_all;
+
+/// Checks that the Limine submodule is checked out.
+pub fn ensure_checked_out(external_limine: &Path) -> Result<(), String> {
+    if external_limine.join("limine-bios.sys").is_file() {
+        return Ok(());
+    }
+
+    let display = external_limine.display();
+
+    Err(format!(
+        "limine: {display} is not checked out; run `git submodule update --init`"
+    ))
+}
+
+fn is_newer(path: &Path, than: &Path) -> bool {
+    let modified = |path: &Path| path.metadata().and_then(|metadata| metadata.modified());
+
+    match (modified(path), modified(than)) {
+        (Ok(path), Ok(than)) => path >= than,
+        _ => false,
+    }
+}
+
+/// Compiles the `limine` host utility from the submodule's `limine.c` into
+/// `output_dir`, skipping the build when it is already up to date.
+///
+/// Returns `Ok(None)` when no C compiler could be started.
+fn build(external_limine: &Path, output_dir: &Path) -> Result<Option<PathBuf>, String> {
+    let source = external_limine.join("limine.c");
+    let tool = output_dir.join("limine");
+
+    if is_newer(&tool, &source) {
+        return Ok(Some(tool));
+    }
+
+    create_dir_all(output_dir)?;
+
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&compiler)
+        .args(["-O2", "-pipe", "-std=c99"])
+        .arg(&source)
+        .arg("-o")
+        .arg(&tool)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Ok(Some(tool)),
+        Ok(status) => Err(format!("limine: {compiler} exited with {status}")),
+        Err(_) => Ok(None),
+    }
+}
+
+fn system_tool() -> Option<PathBuf> {
+    let found = Command::new("limine")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+
+    found.then(|| PathBuf::from("limine"))
+}
+
+/// Returns the `limine` utility to use: `$LIMINE` if set, otherwise one built
+/// from the submodule, otherwise one from `PATH`.
+pub fn tool(external_limine: &Path, output_dir: &Path) -> Result<PathBuf, String> {
+    if let Some(tool) = env::var_os("LIMINE") {
+        return Ok(PathBuf::from(tool));
+    }
+
+    if let Some(tool) = build(external_limine, output_dir)? {
+        return Ok(tool);
+    }
+
+    // The system utility may not match the submodule's version, so it is
+    // only a fallback for hosts without a C compiler
+    system_tool().ok_or_else(|| {
+        "limine: no C compiler to build the limine utility and none found in PATH".to_string()
+    })
+}
+
+/// Installs the BIOS boot sector into `image`, making it bootable from a raw disk.
+pub fn bios_install(tool: &Path, image: &Path) -> Result<(), String> {
+    let status = Command::new(tool)
+        .arg("bios-install")
+        .arg(image)
+        .status()
+        .map_err(|error| format!("limine bios-install: {error}"))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("limine bios-install: exited with {status}"))
+    }
+}
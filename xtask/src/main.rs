@@ -1,7 +1,19 @@
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitCode, Stdio};
 use std::{env, fs};
 
+use crate::cli::{Cli, Firmware, Profile, Subcommand};
+use crate::qemu::{Boot, Media, Qemu};
+
+mod cli;
+mod image;
+mod limine;
+mod ovmf;
+mod qemu;
+
+const KERNEL_PACKAGE: &str = "kernel";
+const KERNEL_TARGET: &str = "x86_64-unknown-none";
+
 fn create_dir_all(dir: impl AsRef<Path>) -> Result<(), String> {
     let dir = dir.as_ref();
 
@@ -34,13 +46,40 @@ fn copy(source: impl AsRef<Path>, destination: impl AsRef<Path>) -> Result<(), S
     Ok(())
 }
 
-fn cargo_build(package: &str, target: &str) -> Result<(), String> {
-    let status = Command::new("cargo")
+fn remove(path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    match result {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+            let path = path.display();
+
+            Err(format!("remove: {path}: {error}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn cargo_build(package: &str, target: &str, profile: Profile) -> Result<(), String> {
+    let mut command = Command::new("cargo");
+
+    command
         .arg("build")
         .arg("--package")
         .arg(package)
         .arg("--target")
-        .arg(target)
+        .arg(target);
+
+    if profile == Profile::Release {
+        command.arg("--release");
+    }
+
+    let status = command
         .spawn()
         .map_err(|error| format!("cargo build {package} for {target}: {error}"))?
         .wait()
@@ -53,6 +92,50 @@ fn cargo_build(package: &str, target: &str) -> Result<(), String> {
     }
 }
 
+/// Builds the kernel's test binary and returns the path to the executable.
+///
+/// The kernel uses `custom_test_frameworks`, so this needs a nightly toolchain.
+fn cargo_test_build(package: &str, target: &str, profile: Profile) -> Result<PathBuf, String> {
+    let mut command = Command::new("cargo");
+
+    command
+        .arg("+nightly")
+        .arg("test")
+        .arg("--package")
+        .arg(package)
+        .arg("--target")
+        .arg(target)
+        .arg("--no-run")
+        .arg("--message-format=json");
+
+    if profile == Profile::Release {
+        command.arg("--release");
+    }
+
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|error| format!("cargo test {package} for {target}: {error}"))?;
+
+    if !output.status.success() {
+        return Err("failed to build tests".to_string());
+    }
+
+    // Pick the `executable` out of the compiler-artifact message for the test
+    // binary without pulling in a JSON parser.
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.contains(r#""reason":"compiler-artifact""#))
+        .filter(|line| line.contains(r#""test":true"#))
+        .find_map(|line| {
+            let (_, rest) = line.split_once(r#""executable":""#)?;
+            let (executable, _) = rest.split_once('"')?;
+
+            Some(PathBuf::from(executable))
+        })
+        .ok_or_else(|| format!("cargo test {package} for {target}: no test executable produced"))
+}
+
 fn create_iso(
     bios_cd: impl AsRef<Path>,
     uefi_cd: impl AsRef<Path>,
@@ -91,111 +174,326 @@ fn create_iso(
     }
 }
 
-fn run_qemu(
-    ovmf_code: impl AsRef<Path>,
-    ovmf_vars: impl AsRef<Path>,
-    iso: impl AsRef<Path>,
-) -> Result<(), String> {
-    let ovmf_code = ovmf_code.as_ref().display();
-    let ovmf_vars = ovmf_vars.as_ref().display();
-    let iso = iso.as_ref();
-
-    let status = Command::new("qemu-system-x86_64")
-        .args(["-M", "q35"])
-        .args([
-            "-drive",
-            &format!("if=pflash,unit=0,format=raw,file={ovmf_code},readonly=on"),
-        ])
-        .args([
-            "-drive",
-            &format!("if=pflash,unit=1,format=raw,file={ovmf_vars}"),
-        ])
-        .arg("-cdrom")
-        .arg(iso)
-        .args(["-m", "2G"])
-        .spawn()
-        .map_err(|error| format!("qemu: {error}"))?
-        .wait()
-        .map_err(|error| format!("qemu: {error}"))?;
+/// Well-known locations within the project and its target directory.
+struct Project {
+    root_dir: PathBuf,
+    target_dir: PathBuf,
+    external_limine: PathBuf,
+    limine_dir: PathBuf,
+    limine_conf: PathBuf,
+    image: PathBuf,
+    iso: PathBuf,
+    iso_dir: PathBuf,
+    iso_limine: PathBuf,
+    iso_efi: PathBuf,
+    ovmf_dir: PathBuf,
+    ovmf_code: PathBuf,
+    ovmf_vars: PathBuf,
+}
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err("failed to build".to_string())
+impl Project {
+    fn locate() -> Result<Self, String> {
+        let Some(root_dir) = env::var_os("CARGO_MANIFEST_DIR")
+            .map(PathBuf::from)
+            .as_deref()
+            .and_then(Path::parent)
+            .map(PathBuf::from)
+        else {
+            return Err("xtask must be executed within the ignis project".to_string());
+        };
+
+        let target_dir = env::var_os("CARGO_TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| root_dir.join("target"));
+
+        let iso_dir = target_dir.join("iso");
+        let ovmf_dir = target_dir.join("ovmf");
+
+        Ok(Self {
+            external_limine: root_dir.join("external/boot/limine"),
+            limine_dir: target_dir.join("limine"),
+            limine_conf: target_dir.join("limine.conf"),
+            image: target_dir.join("ignis.img"),
+            iso: target_dir.join("ignis.iso"),
+            iso_limine: iso_dir.join("boot/limine"),
+            iso_efi: iso_dir.join("EFI/BOOT"),
+            ovmf_code: ovmf_dir.join("ovmf-code-x86_64.fd"),
+            ovmf_vars: ovmf_dir.join("ovmf-vars-x86_64.fd"),
+            root_dir,
+            target_dir,
+            iso_dir,
+            ovmf_dir,
+        })
     }
-}
 
-fn main() {
-    if let Err(error) = run() {
-        eprintln!("xtask: {error}");
+    /// Path of the kernel ELF produced by `cargo build`.
+    fn kernel(&self, profile: Profile) -> PathBuf {
+        self.target_dir
+            .join(KERNEL_TARGET)
+            .join(profile.dir_name())
+            .join(KERNEL_PACKAGE)
     }
 }
 
-fn run() -> Result<(), String> {
-    let Some(root_dir) = env::var_os("CARGO_MANIFEST_DIR")
-        .map(PathBuf::from)
-        .as_deref()
-        .and_then(Path::parent)
-        .map(PathBuf::from)
-    else {
-        return Err("xtask must be executed within the ignis project".to_string());
-    };
+fn build(project: &Project, cli: &Cli) -> Result<PathBuf, String> {
+    cargo_build(KERNEL_PACKAGE, KERNEL_TARGET, cli.profile)?;
 
-    let target_dir = env::var_os("CARGO_TARGET_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|| root_dir.join("target"));
+    Ok(project.kernel(cli.profile))
+}
 
-    let external_limine = root_dir.join("external/boot/limine");
+/// Writes Limine's config to the target directory, with the options given on
+/// the command line added.
+fn write_limine_conf(project: &Project, cli: &Cli) -> Result<(), String> {
+    let source = project.root_dir.join("boot/limine.conf");
+    let mut config = fs::read_to_string(&source).map_err(|error| {
+        let source = source.display();
 
-    let iso = target_dir.join("ignis.iso");
+        format!("read: {source}: {error}")
+    })?;
 
-    let iso_dir = target_dir.join("iso");
-    let iso_limine = iso_dir.join("boot/limine");
-    let iso_efi = iso_dir.join("EFI/BOOT");
+    // Options at the end belong to the last (and only) entry
+    if let Some(resolution) = &cli.resolution {
+        config.push_str(&format!("    resolution: {resolution}\n"));
+    }
 
-    let ovmf_dir = target_dir.join("ovmf");
-    let ovmf_code = ovmf_dir.join("ovmf-code-x86_64.fd");
-    let ovmf_vars = ovmf_dir.join("ovmf-vars-x86_64.fd");
+    create_dir_all(&project.target_dir)?;
 
-    create_dir_all(&iso_limine)?;
-    create_dir_all(&iso_efi)?;
-    create_dir_all(&ovmf_dir)?;
+    fs::write(&project.limine_conf, config).map_err(|error| {
+        let path = project.limine_conf.display();
 
-    copy("/usr/share/edk2/x64/OVMF_CODE.4m.fd", &ovmf_code)?;
-    copy("/usr/share/edk2/x64/OVMF_VARS.4m.fd", &ovmf_vars)?;
+        format!("write: {path}: {error}")
+    })
+}
 
-    cargo_build("kernel", "x86_64-unknown-none")?;
+/// Stages the kernel, Limine and its config, then builds a hybrid ISO that
+/// boots from optical media as well as a raw disk.
+fn iso(project: &Project, cli: &Cli, kernel: &Path) -> Result<(), String> {
+    limine::ensure_checked_out(&project.external_limine)?;
 
-    copy(
-        target_dir.join("x86_64-unknown-none/debug/kernel"),
-        iso_limine.join("ignis.elf"),
-    )?;
+    let limine = limine::tool(&project.external_limine, &project.limine_dir)?;
 
-    copy(
-        root_dir.join("boot/limine.conf"),
-        iso_limine.join("limine.conf"),
-    )?;
+    create_dir_all(&project.iso_limine)?;
+    create_dir_all(&project.iso_efi)?;
+
+    copy(kernel, project.iso_limine.join("ignis.elf"))?;
+
+    write_limine_conf(project, cli)?;
+    copy(&project.limine_conf, project.iso_limine.join("limine.conf"))?;
 
     for file in [
         "limine-bios.sys",
         "limine-bios-cd.bin",
         "limine-uefi-cd.bin",
     ] {
-        copy(external_limine.join(file), iso_limine.join(file))?;
+        copy(
+            project.external_limine.join(file),
+            project.iso_limine.join(file),
+        )?;
     }
 
     for file in ["BOOTIA32.EFI", "BOOTX64.EFI"] {
-        copy(external_limine.join(file), iso_efi.join(file))?;
+        copy(
+            project.external_limine.join(file),
+            project.iso_efi.join(file),
+        )?;
     }
 
     create_iso(
         "boot/limine/limine-bios-cd.bin",
         "boot/limine/limine-uefi-cd.bin",
-        iso_dir,
-        &iso,
+        &project.iso_dir,
+        &project.iso,
+    )?;
+
+    limine::bios_install(&limine, &project.iso)
+}
+
+/// Builds a GPT disk image whose EFI system partition holds Limine, its
+/// config and the kernel, bootable under both UEFI and BIOS.
+fn image(project: &Project, cli: &Cli, kernel: &Path) -> Result<(), String> {
+    limine::ensure_checked_out(&project.external_limine)?;
+
+    let limine = limine::tool(&project.external_limine, &project.limine_dir)?;
+
+    write_limine_conf(project, cli)?;
+
+    let limine_bios = project.external_limine.join("limine-bios.sys");
+    let bootx64 = project.external_limine.join("BOOTX64.EFI");
+
+    create_dir_all(&project.target_dir)?;
+
+    image::create(
+        &project.image,
+        &[
+            image::Entry {
+                source: &bootx64,
+                destination: "EFI/BOOT/BOOTX64.EFI",
+            },
+            image::Entry {
+                source: &limine_bios,
+                destination: "boot/limine/limine-bios.sys",
+            },
+            image::Entry {
+                source: &project.limine_conf,
+                destination: "boot/limine/limine.conf",
+            },
+            image::Entry {
+                source: kernel,
+                destination: "boot/limine/ignis.elf",
+            },
+        ],
     )?;
 
-    run_qemu(ovmf_code, ovmf_vars, iso)?;
+    limine::bios_install(&limine, &project.image)
+}
+
+/// Builds whichever boot medium was asked for.
+fn media(project: &Project, cli: &Cli, kernel: &Path) -> Result<Media, String> {
+    if cli.image {
+        image(project, cli, kernel)?;
+
+        Ok(Media::Disk(project.image.clone()))
+    } else {
+        iso(project, cli, kernel)?;
+
+        Ok(Media::Cdrom(project.iso.clone()))
+    }
+}
+
+fn boot(project: &Project, firmware: Option<Firmware>) -> Result<Boot, String> {
+    if firmware == Some(Firmware::Bios) {
+        return Ok(Boot::Bios);
+    }
+
+    let Some(ovmf) = ovmf::locate()? else {
+        if firmware == Some(Firmware::Uefi) {
+            return Err("no OVMF firmware found; set OVMF_CODE and OVMF_VARS".to_string());
+        }
+
+        eprintln!("xtask: no OVMF firmware found, falling back to BIOS boot");
+
+        return Ok(Boot::Bios);
+    };
+
+    // The variable store is written to by the firmware, so boot from a copy
+    create_dir_all(&project.ovmf_dir)?;
+
+    copy(ovmf.code, &project.ovmf_code)?;
+    copy(ovmf.vars, &project.ovmf_vars)?;
+
+    Ok(Boot::Uefi {
+        ovmf_code: project.ovmf_code.clone(),
+        ovmf_vars: project.ovmf_vars.clone(),
+    })
+}
+
+fn qemu(project: &Project, cli: &Cli, media: Media) -> Result<Qemu, String> {
+    Ok(Qemu {
+        boot: boot(project, cli.firmware)?,
+        media,
+        memory: cli.memory.clone(),
+        cpus: cli.cpus,
+        headless: cli.headless,
+        extra_args: cli.qemu_args.clone(),
+    })
+}
+
+fn gdb(project: &Project, cli: &Cli, kernel: &Path, media: Media) -> Result<(), String> {
+    let mut server = qemu(project, cli, media)?.spawn_gdb_server(cli.gdb_port)?;
+
+    let result = Command::new("gdb")
+        .arg(kernel)
+        .arg("-ex")
+        .arg(format!("target remote localhost:{}", cli.gdb_port))
+        .status()
+        .map_err(|error| format!("gdb: {error}"));
+
+    let _ = server.kill();
+    let _ = server.wait();
+
+    match result? {
+        status if status.success() => Ok(()),
+        status => Err(format!("gdb: exited with {status}")),
+    }
+}
+
+fn clean(project: &Project, cli: &Cli) -> Result<(), String> {
+    if cli.clean_all {
+        let status = Command::new("cargo")
+            .arg("clean")
+            .status()
+            .map_err(|error| format!("cargo clean: {error}"))?;
+
+        return if status.success() {
+            Ok(())
+        } else {
+            Err("cargo clean failed".to_string())
+        };
+    }
+
+    for path in [
+        &project.iso,
+        &project.iso_dir,
+        &project.image,
+        &project.ovmf_dir,
+        &project.limine_dir,
+        &project.limine_conf,
+    ] {
+        remove(path)?;
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("xtask: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let cli = cli::parse(env::args_os().skip(1))?;
+    let project = Project::locate()?;
+
+    match cli.subcommand {
+        Subcommand::Help => println!("{}", cli::USAGE),
+        Subcommand::Build => {
+            build(&project, &cli)?;
+        }
+        Subcommand::Iso => {
+            let kernel = build(&project, &cli)?;
+
+            iso(&project, &cli, &kernel)?;
+        }
+        Subcommand::Image => {
+            let kernel = build(&project, &cli)?;
+
+            image(&project, &cli, &kernel)?;
+        }
+        Subcommand::Run => {
+            let kernel = build(&project, &cli)?;
+            let media = media(&project, &cli, &kernel)?;
+
+            qemu(&project, &cli, media)?.run()?;
+        }
+        Subcommand::Test => {
+            let kernel = cargo_test_build(KERNEL_PACKAGE, KERNEL_TARGET, cli.profile)?;
+            let media = media(&project, &cli, &kernel)?;
+
+            qemu(&project, &cli, media)?.run_test(cli.timeout)?;
+        }
+        Subcommand::Gdb => {
+            let kernel = build(&project, &cli)?;
+            let media = media(&project, &cli, &kernel)?;
+
+            gdb(&project, &cli, &kernel, media)?;
+        }
+        Subcommand::Clean => clean(&project, &cli)?,
+    }
 
     Ok(())
 }
@@ -0,0 +1,99 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Known (code, vars) OVMF image pairs, in the order they are probed.
+const CANDIDATES: &[(&str, &str)] = &[
+    // Arch Linux
+    (
+        "/usr/share/edk2/x64/OVMF_CODE.4m.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.4m.fd",
+    ),
+    (
+        "/usr/share/edk2/x64/OVMF_CODE.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.fd",
+    ),
+    // Debian and Ubuntu
+    (
+        "/usr/share/OVMF/OVMF_CODE_4M.fd",
+        "/usr/share/OVMF/OVMF_VARS_4M.fd",
+    ),
+    (
+        "/usr/share/OVMF/OVMF_CODE.fd",
+        "/usr/share/OVMF/OVMF_VARS.fd",
+    ),
+    // Fedora
+    (
+        "/usr/share/edk2/ovmf/OVMF_CODE.fd",
+        "/usr/share/edk2/ovmf/OVMF_VARS.fd",
+    ),
+    // Gentoo
+    (
+        "/usr/share/edk2-ovmf/OVMF_CODE.fd",
+        "/usr/share/edk2-ovmf/OVMF_VARS.fd",
+    ),
+    // openSUSE
+    (
+        "/usr/share/qemu/ovmf-x86_64-code.bin",
+        "/usr/share/qemu/ovmf-x86_64-vars.bin",
+    ),
+    // Firmware bundled with upstream QEMU, including Homebrew on macOS
+    (
+        "/usr/share/qemu/edk2-x86_64-code.fd",
+        "/usr/share/qemu/edk2-i386-vars.fd",
+    ),
+    (
+        "/usr/local/share/qemu/edk2-x86_64-code.fd",
+        "/usr/local/share/qemu/edk2-i386-vars.fd",
+    ),
+    (
+        "/opt/homebrew/share/qemu/edk2-x86_64-code.fd",
+        "/opt/homebrew/share/qemu/edk2-i386-vars.fd",
+    ),
+];
+
+/// A pair of OVMF firmware images found on the host.
+#[derive(Clone, Debug)]
+pub struct Ovmf {
+    pub code: PathBuf,
+    pub vars: PathBuf,
+}
+
+fn from_env(name: &str) -> Result<Option<PathBuf>, String> {
+    let Some(path) = env::var_os(name).map(PathBuf::from) else {
+        return Ok(None);
+    };
+
+    if path.is_file() {
+        Ok(Some(path))
+    } else {
+        let display = path.display();
+
+        Err(format!("{name}: {display} does not exist"))
+    }
+}
+
+/// Finds OVMF firmware, preferring the `OVMF_CODE` and `OVMF_VARS`
+/// environment variables over the well-known distro locations. The two
+/// must be set together, since images from different builds don't match.
+///
+/// Returns `Ok(None)` when no firmware is installed.
+pub fn locate() -> Result<Option<Ovmf>, String> {
+    match (from_env("OVMF_CODE")?, from_env("OVMF_VARS")?) {
+        (Some(code), Some(vars)) => return Ok(Some(Ovmf { code, vars })),
+        (None, None) => {}
+        _ => return Err("OVMF_CODE and OVMF_VARS must be set together".to_string()),
+    }
+
+    let existing = |path: &str| Some(PathBuf::from(path)).filter(|path| path.is_file());
+
+    let found = CANDIDATES
+        .iter()
+        .find_map(|(candidate_code, candidate_vars)| {
+            Some(Ovmf {
+                code: existing(candidate_code)?,
+                vars: existing(candidate_vars)?,
+            })
+        });
+
+    Ok(found)
+}
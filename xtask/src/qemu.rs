@@ -0,0 +1,290 @@
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Exit statuses produced by the kernel writing to QEMU's isa-debug-exit
+// device; QEMU exits with `(value << 1) | 1`.
+const QEMU_EXIT_SUCCESS: i32 = (0x10 << 1) | 1;
+const QEMU_EXIT_FAILED: i32 = (0x11 << 1) | 1;
+
+// Prefix of every line of the kernel's test protocol, see kernel/src/test.rs
+const TEST_PROTOCOL_PREFIX: &str = "ignis-test: ";
+
+/// Firmware QEMU boots the ISO with.
+#[derive(Clone, Debug)]
+pub enum Boot {
+    /// SeaBIOS, QEMU's built-in legacy BIOS.
+    Bios,
+    /// OVMF, with a writable copy of the variable store.
+    Uefi {
+        ovmf_code: PathBuf,
+        ovmf_vars: PathBuf,
+    },
+}
+
+/// Boot medium attached to the guest.
+#[derive(Clone, Debug)]
+pub enum Media {
+    /// An ISO attached as a CD-ROM.
+    Cdrom(PathBuf),
+    /// A raw disk image attached as a hard drive.
+    Disk(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct Qemu {
+    pub boot: Boot,
+    pub media: Media,
+    pub memory: String,
+    pub cpus: u32,
+    /// Run without a display, with the serial port on the terminal.
+    pub headless: bool,
+    pub extra_args: Vec<String>,
+}
+
+impl Qemu {
+    fn command(&self) -> Command {
+        let mut command = Command::new("qemu-system-x86_64");
+
+        command.args(["-M", "q35"]);
+
+        if let Boot::Uefi {
+            ovmf_code,
+            ovmf_vars,
+        } = &self.boot
+        {
+            let ovmf_code = ovmf_code.display();
+            let ovmf_vars = ovmf_vars.display();
+
+            command
+                .args([
+                    "-drive",
+                    &format!("if=pflash,unit=0,format=raw,file={ovmf_code},readonly=on"),
+                ])
+                .args([
+                    "-drive",
+                    &format!("if=pflash,unit=1,format=raw,file={ovmf_vars}"),
+                ]);
+        }
+
+        match &self.media {
+            Media::Cdrom(iso) => {
+                command.arg("-cdrom").arg(iso);
+            }
+            Media::Disk(image) => {
+                let image = image.display();
+
+                command.args(["-drive", &format!("format=raw,file={image}")]);
+            }
+        }
+
+        command
+            .args(["-m", &self.memory])
+            .args(["-smp", &self.cpus.to_string()]);
+
+        command
+    }
+
+    /// Boots interactively, with a graphical display unless headless.
+    pub fn run(&self) -> Result<(), String> {
+        let mut command = self.command();
+
+        if self.headless {
+            command
+                .args(["-display", "none"])
+                .args(["-serial", "stdio"]);
+        }
+
+        let status = command
+            .args(&self.extra_args)
+            .spawn()
+            .map_err(|error| format!("qemu: {error}"))?
+            .wait()
+            .map_err(|error| format!("qemu: {error}"))?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("qemu: exited with {status}"))
+        }
+    }
+
+    /// Boots paused with a GDB stub listening on `port`.
+    pub fn spawn_gdb_server(&self, port: u16) -> Result<Child, String> {
+        self.command()
+            .args(["-gdb", &format!("tcp::{port}")])
+            .arg("-S")
+            .args(&self.extra_args)
+            .spawn()
+            .map_err(|error| format!("qemu: {error}"))
+    }
+
+    /// Boots headlessly and checks the kernel's test protocol on the serial port.
+    pub fn run_test(&self, timeout: Duration) -> Result<(), String> {
+        let mut child = self
+            .command()
+            .args(["-display", "none"])
+            .args(["-serial", "stdio"])
+            .args(["-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"])
+            .arg("-no-reboot")
+            .args(&self.extra_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("qemu: {error}"))?;
+
+        // Echo the serial output as it arrives and forward protocol lines
+        let stdout = child.stdout.take().expect("qemu stdout is piped");
+        let (sender, receiver) = mpsc::channel();
+        let reader = thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("{line}");
+
+                if let Some(message) = line.trim_end().strip_prefix(TEST_PROTOCOL_PREFIX) {
+                    let _ = sender.send(message.to_string());
+                }
+            }
+        });
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(|error| format!("qemu: {error}"))? {
+                break status;
+            }
+
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+
+                return Err(format!("qemu: timed out after {}s", timeout.as_secs()));
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        let _ = reader.join();
+
+        let mut report = TestReport::default();
+
+        for message in receiver.try_iter() {
+            report.record(&message);
+        }
+
+        println!(
+            "xtask: {} passed, {} failed",
+            report.passed.len(),
+            report.failed.len()
+        );
+
+        match (status.code(), report.result) {
+            (Some(QEMU_EXIT_SUCCESS), Some(true)) => report.check(),
+            (Some(QEMU_EXIT_FAILED), _) | (_, Some(false)) => {
+                Err(format!("tests failed: {}", report.failed.join(", ")))
+            }
+            (_, None) => Err(format!(
+                "qemu exited ({status}) before the kernel reported a test result"
+            )),
+            (_, Some(true)) => Err(format!("qemu: unexpected exit ({status})")),
+        }
+    }
+}
+
+// What the kernel reported over the test protocol. Lines that aren't exactly
+// one of the protocol messages are output from the tests or the kernel log.
+#[derive(Debug, Default)]
+struct TestReport {
+    announced: Option<usize>,
+    passed: Vec<String>,
+    failed: Vec<String>,
+    result: Option<bool>,
+}
+
+impl TestReport {
+    // Records one message, with the protocol prefix already stripped
+    fn record(&mut self, message: &str) {
+        if let Some(count) = message
+            .strip_prefix("running ")
+            .and_then(|rest| rest.strip_suffix(" tests"))
+            .and_then(|count| count.parse().ok())
+        {
+            self.announced = Some(count);
+        } else if message == "result: ok" {
+            self.result = Some(true);
+        } else if message == "result: FAILED" {
+            self.result = Some(false);
+        } else if let Some(name) = message.strip_suffix(" ... ok") {
+            self.passed.push(name.to_string());
+        } else if let Some(name) = message.strip_suffix(" ... FAILED") {
+            self.failed.push(name.to_string());
+        }
+    }
+
+    // Checks that a run reported as successful really passed every test
+    fn check(&self) -> Result<(), String> {
+        if !self.failed.is_empty() {
+            return Err(format!(
+                "kernel reported success, but tests failed: {}",
+                self.failed.join(", ")
+            ));
+        }
+
+        match self.announced {
+            Some(count) if count == self.passed.len() => Ok(()),
+            Some(count) => Err(format!(
+                "kernel reported success, but only {} of {count} tests passed",
+                self.passed.len()
+            )),
+            None => Err("kernel reported success without announcing its tests".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(messages: &[&str]) -> TestReport {
+        let mut report = TestReport::default();
+
+        for message in messages {
+            report.record(message);
+        }
+
+        report
+    }
+
+    #[test]
+    fn counts_only_exact_protocol_lines() {
+        let report = report(&[
+            "running 2 tests",
+            "kernel::tests::a ... ok",
+            "test output ... okay",
+            "kernel::tests::b ... ok",
+            "result: ok",
+        ]);
+
+        assert_eq!(report.passed, ["kernel::tests::a", "kernel::tests::b"]);
+        assert!(report.failed.is_empty());
+        assert_eq!(report.result, Some(true));
+        assert_eq!(report.check(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_success_that_contradicts_the_counts() {
+        let missing = report(&["running 2 tests", "kernel::tests::a ... ok", "result: ok"]);
+        assert!(missing.check().is_err());
+
+        let failed = report(&[
+            "running 1 tests",
+            "kernel::tests::a ... FAILED",
+            "result: ok",
+        ]);
+        assert!(failed.check().is_err());
+
+        let unannounced = report(&["kernel::tests::a ... ok", "result: ok"]);
+        assert!(unannounced.check().is_err());
+    }
+}